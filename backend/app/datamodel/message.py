@@ -28,6 +28,7 @@ class MessageType(StrEnum):
     SYSTEM = "SYSTEM"
     JOIN = "JOIN"
     LEAVE = "LEAVE"
+    FILE_CHUNK = "FILE_CHUNK"
 
 
 class BaseMessage(BaseModel):
@@ -38,6 +39,8 @@ class Encrypted(BaseMessage):
     type: Literal[MessageType.ENCRYPTED] = MessageType.ENCRYPTED
     content_base64: str
     nonce: str
+    signature: Optional[str] = None
+    key_epoch: Optional[int] = None
 
 
 class Plaintext(BaseMessage):
@@ -48,6 +51,7 @@ class Plaintext(BaseMessage):
 class KeyRequest(BaseMessage):
     type: Literal[MessageType.KEY_REQUEST] = MessageType.KEY_REQUEST
     public_key: str
+    signing_key: Optional[str] = None
 
 
 class KeyResponse(BaseMessage):
@@ -56,6 +60,16 @@ class KeyResponse(BaseMessage):
     check_msg: str
     sender_public_key: str
     nonce: str
+    signing_key: Optional[str] = None
+    key_epoch: Optional[int] = None
+
+
+class FileChunk(BaseMessage):
+    type: Literal[MessageType.FILE_CHUNK] = MessageType.FILE_CHUNK
+    content_base64: str
+    nonce: str
+    signature: Optional[str] = None
+    key_epoch: Optional[int] = None
 
 
 class SystemMessage(BaseMessage):
@@ -90,9 +104,13 @@ def get_correct_message_type(message: MessageType):
             return SystemMessage
         case MessageType.LEAVE:
             return SystemMessage
+        case MessageType.FILE_CHUNK:
+            return FileChunk
 
 
-MessageContent = Union[Encrypted, Plaintext, KeyRequest, KeyResponse, SystemMessage]
+MessageContent = Union[
+    Encrypted, Plaintext, KeyRequest, KeyResponse, SystemMessage, FileChunk
+]
 
 
 class MessageBase(SQLModel):
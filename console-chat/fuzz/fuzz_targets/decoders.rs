@@ -0,0 +1,27 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use openapi::models::{Encrypted, KeyRequest, KeyResponse};
+
+#[path = "../../src/network/payload.rs"]
+mod payload;
+
+// Splits the input into the string fields of the payloads, most of them valid base64 of the
+// wrong length, which is what the length checks are about.
+fuzz_target!(|fields: [String; 5]| {
+    let [a, b, c, d, e] = fields;
+    let mut encrypted = Encrypted::new(a.clone(), b.clone());
+    encrypted.signature = Some(c.clone());
+    if let Ok(decoded) = payload::decode_encrypted(&encrypted) {
+        let _ = decoded.plaintext_len();
+    }
+    let _ = payload::signature("signature", &c);
+
+    let mut request = KeyRequest::new(a.clone());
+    request.signing_key = Some(e.clone());
+    let _ = payload::decode_key_request(&request);
+
+    let mut response = KeyResponse::new(a, b, c, d);
+    response.signing_key = Some(e);
+    let _ = payload::decode_key_response(&response);
+});
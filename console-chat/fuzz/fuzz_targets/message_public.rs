@@ -0,0 +1,26 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use openapi::models::{Content, MessagePublic};
+
+#[path = "../../src/network/payload.rs"]
+mod payload;
+
+// Everything the server relays from other clients goes through here first.
+fuzz_target!(|data: &[u8]| {
+    let Ok(message) = serde_json::from_slice::<MessagePublic>(data) else {
+        return;
+    };
+    match message.content {
+        Some(Content::Encrypted(encrypted)) => {
+            let _ = payload::decode_encrypted(&encrypted).map(|decoded| decoded.plaintext_len());
+        }
+        Some(Content::KeyRequest(request)) => {
+            let _ = payload::decode_key_request(&request);
+        }
+        Some(Content::KeyResponse(response)) => {
+            let _ = payload::decode_key_response(&response);
+        }
+        _ => {}
+    }
+});
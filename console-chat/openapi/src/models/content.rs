@@ -24,6 +24,15 @@ pub enum Content {
     KeyResponse(models::KeyResponse),
     #[serde(rename = "SYSTEM")]
     System(models::SystemMessage),
+    #[serde(rename = "JOIN")]
+    Join(models::JoinMessage),
+    #[serde(rename = "LEAVE")]
+    Leave(models::LeaveMessage),
+    #[serde(rename = "FILE_CHUNK")]
+    FileChunk(models::FileChunk),
+    /// Any content type added to the server after this client was built.
+    #[serde(rename = "UNKNOWN", other)]
+    Unknown,
 }
 
 impl Default for Content {
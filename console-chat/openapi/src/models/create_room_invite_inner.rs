@@ -8,14 +8,30 @@
  * Generated by: https://openapi-generator.tech
  */
 
-use crate::models;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
-pub struct CreateRoomInviteInner {}
+/// A user to invite, by id or by username.
+///
+/// Written by hand, the generator emits an empty struct for `anyOf` of primitives.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CreateRoomInviteInner {
+    Id(uuid::Uuid),
+    Username(String),
+}
+
+impl Default for CreateRoomInviteInner {
+    fn default() -> CreateRoomInviteInner {
+        Self::Username(Default::default())
+    }
+}
 
 impl CreateRoomInviteInner {
-    pub fn new() -> CreateRoomInviteInner {
-        CreateRoomInviteInner {}
+    /// Takes anything that parses as a UUID as an id, everything else as a username.
+    pub fn new(user: &str) -> CreateRoomInviteInner {
+        match uuid::Uuid::parse_str(user) {
+            Ok(id) => Self::Id(id),
+            Err(_) => Self::Username(user.to_owned()),
+        }
     }
 }
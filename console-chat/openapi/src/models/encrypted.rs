@@ -19,6 +19,12 @@ pub struct Encrypted {
     pub content_base64: String,
     #[serde(rename = "nonce")]
     pub nonce: String,
+    /// Base64 Ed25519 signature of the sender over room, nonce and ciphertext.
+    #[serde(rename = "signature", skip_serializing_if = "Option::is_none", default)]
+    pub signature: Option<String>,
+    /// Rotation epoch of the room key the content is encrypted with.
+    #[serde(rename = "key_epoch", skip_serializing_if = "Option::is_none", default)]
+    pub key_epoch: Option<u64>,
 }
 
 impl Encrypted {
@@ -27,6 +33,8 @@ impl Encrypted {
             r#type: None,
             content_base64,
             nonce,
+            signature: None,
+            key_epoch: None,
         }
     }
 }
@@ -0,0 +1,50 @@
+/*
+ * Console Chat API
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: 0.2.0
+ *
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+/// One encrypted piece of a file attachment, relayed like an `Encrypted` message.
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FileChunk {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<Type>,
+    #[serde(rename = "content_base64")]
+    pub content_base64: String,
+    #[serde(rename = "nonce")]
+    pub nonce: String,
+    /// Base64 Ed25519 signature of the sender over room, nonce and ciphertext.
+    #[serde(rename = "signature", skip_serializing_if = "Option::is_none", default)]
+    pub signature: Option<String>,
+    /// Rotation epoch of the room key the content is encrypted with.
+    #[serde(rename = "key_epoch", skip_serializing_if = "Option::is_none", default)]
+    pub key_epoch: Option<u64>,
+}
+
+impl FileChunk {
+    pub fn new(content_base64: String, nonce: String) -> FileChunk {
+        FileChunk {
+            r#type: None,
+            content_base64,
+            nonce,
+            signature: None,
+            key_epoch: None,
+        }
+    }
+}
+
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Default,
+)]
+pub enum Type {
+    #[serde(rename = "FILE_CHUNK")]
+    #[default]
+    FileChunk,
+}
@@ -0,0 +1,38 @@
+/*
+ * Console Chat API
+ *
+ * No description provided (generated by Openapi Generator https://github.com/openapitools/openapi-generator)
+ *
+ * The version of the OpenAPI document: 0.2.0
+ *
+ * Generated by: https://openapi-generator.tech
+ */
+
+use crate::models;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Default, Debug, PartialEq, Serialize, Deserialize)]
+pub struct JoinMessage {
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub r#type: Option<Type>,
+    #[serde(rename = "content")]
+    pub content: String,
+}
+
+impl JoinMessage {
+    pub fn new(content: String) -> JoinMessage {
+        JoinMessage {
+            r#type: None,
+            content,
+        }
+    }
+}
+
+#[derive(
+    Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize, Default,
+)]
+pub enum Type {
+    #[serde(rename = "JOIN")]
+    #[default]
+    Join,
+}
@@ -17,6 +17,13 @@ pub struct KeyRequest {
     pub r#type: Option<Type>,
     #[serde(rename = "public_key")]
     pub public_key: String,
+    /// Base64 Ed25519 key the requester signs messages with.
+    #[serde(
+        rename = "signing_key",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub signing_key: Option<String>,
 }
 
 impl KeyRequest {
@@ -24,6 +31,7 @@ impl KeyRequest {
         KeyRequest {
             r#type: None,
             public_key,
+            signing_key: None,
         }
     }
 }
@@ -23,6 +23,16 @@ pub struct KeyResponse {
     pub sender_public_key: String,
     #[serde(rename = "nonce")]
     pub nonce: String,
+    /// Base64 Ed25519 key the sender signs messages with.
+    #[serde(
+        rename = "signing_key",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub signing_key: Option<String>,
+    /// Rotation epoch of the room key, a newer epoch than the current one is a rotation.
+    #[serde(rename = "key_epoch", skip_serializing_if = "Option::is_none", default)]
+    pub key_epoch: Option<u64>,
 }
 
 impl KeyResponse {
@@ -38,6 +48,8 @@ impl KeyResponse {
             check_msg,
             sender_public_key,
             nonce,
+            signing_key: None,
+            key_epoch: None,
         }
     }
 }
@@ -30,6 +30,11 @@ pub enum MessageType {
     Join,
     #[serde(rename = "LEAVE")]
     Leave,
+    #[serde(rename = "FILE_CHUNK")]
+    FileChunk,
+    /// Any message type added to the server after this client was built.
+    #[serde(rename = "UNKNOWN", other)]
+    Unknown,
 }
 
 impl std::fmt::Display for MessageType {
@@ -42,6 +47,8 @@ impl std::fmt::Display for MessageType {
             Self::System => write!(f, "SYSTEM"),
             Self::Join => write!(f, "JOIN"),
             Self::Leave => write!(f, "LEAVE"),
+            Self::FileChunk => write!(f, "FILE_CHUNK"),
+            Self::Unknown => write!(f, "UNKNOWN"),
         }
     }
 }
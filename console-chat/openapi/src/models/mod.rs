@@ -10,12 +10,18 @@ pub mod encrypted;
 pub use self::encrypted::Encrypted;
 pub mod error_model;
 pub use self::error_model::ErrorModel;
+pub mod file_chunk;
+pub use self::file_chunk::FileChunk;
 pub mod http_validation_error;
 pub use self::http_validation_error::HttpValidationError;
+pub mod join_message;
+pub use self::join_message::JoinMessage;
 pub mod key_request;
 pub use self::key_request::KeyRequest;
 pub mod key_response;
 pub use self::key_response::KeyResponse;
+pub mod leave_message;
+pub use self::leave_message::LeaveMessage;
 pub mod login_data;
 pub use self::login_data::LoginData;
 pub mod message_public;
@@ -1,7 +1,12 @@
 pub(crate) use crate::error::{AppError, Result};
-use crate::network::Message;
-use openapi::models::UserPrivate;
+use crate::network::approval::{KeyDecision, KeyRequestPrompt};
+use crate::network::search::{Hit, Query};
+use crate::network::trust::IdentityView;
+use crate::network::{ConnectionState, Message};
+use crate::secret::Secret;
+use openapi::models::{CreateRoom, StaticRoomPublic, UpdateRoom, UserPrivate};
 use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 use strum::Display;
 
 #[derive(Debug, Clone, PartialEq, Display, Serialize, Deserialize)]
@@ -25,19 +30,92 @@ pub enum Action {
     OpenSettings,
     OpenRawSettings,
     OpenChat,
+    /// Shows the chat of a joined room.
+    OpenRoom(String),
     OpenJoin,
     OpenHome,
+    OpenRooms,
+    OpenRoomAdmin,
+    EditRoom(StaticRoomPublic),
     Hide,
 
     TriggerLogin,
-    PerformLogin(String, String),
+    /// Username and password.
+    PerformLogin(String, Secret),
     TriggerJoin,
     PerformJoin(String),
+    /// Joins a room whose key is derived from the passphrase.
+    JoinWithPassphrase(String, Secret),
+    /// Opens the key import for a room.
+    OpenKeyImport(String),
+    /// Joins a room with a key shared as words or QR code text.
+    JoinWithKey(String, Secret),
+    /// Opens the join screen for a room that needs more than its name.
+    PromptJoin(String),
+    RefreshRooms,
+    /// Public rooms and the rooms of the current user.
+    RoomList(Vec<StaticRoomPublic>, Vec<StaticRoomPublic>),
+    CreateRoom(String, CreateRoom),
+    UpdateRoom(String, UpdateRoom),
+    DeleteRoom(String),
     JoinRandom,
-    SendMessage(String),
+    /// Room and message.
+    SendMessage(String, String),
+    /// Replaces the key of a room and hands it to the remaining members.
+    RotateKey(String),
+    /// Shows the key of a room to share it in person.
+    ShareKey(String),
+    /// Room, key words and QR code text.
+    SharedKey(String, Secret, Secret),
+    /// Room and the file to send to it.
+    SendFile(String, PathBuf),
+    /// Room and transfer id of a complete attachment.
+    SaveAttachment(String, String),
+    /// Room and transfer id of a received attachment to throw away.
+    DismissAttachment(String, String),
+    /// Progress of an attachment, replaces the card with the same transfer id.
+    Attachment(String, Message),
     Me(UserPrivate),
-    ReceivedMessage(Message),
-    Leave,
+    ReceivedMessage(String, Message),
+    /// Room, stored messages oldest first and the index of the first one in the history.
+    History(String, Vec<Message>, usize),
+    /// Room, history index of the first message to load and the index the page ends at.
+    LoadHistory(String, usize, usize),
+    /// Opens the search for what is shown, the room in the chat or every room from home.
+    StartSearch,
+    /// Opens the search, limited to a room or over every room.
+    OpenSearch(Option<String>),
+    CloseSearch,
+    /// Runs a search over the chat and the local history.
+    Search(Query),
+    /// Hits for the query, from the chat or from the history.
+    SearchResults(Query, Vec<Hit>),
+    /// Shows a hit in the chat.
+    JumpTo(Hit),
+    ConnectionState(String, ConnectionState),
+    /// The room key is known, messages to the room are end-to-end encrypted from now on.
+    RoomEncrypted(String),
+    /// Someone asked for a room key and `approve_key_requests` is set.
+    KeyRequestPrompt(KeyRequestPrompt),
+    ApproveKeyRequest,
+    DenyKeyRequest,
+    TrustKeyRequest,
+    AnswerKeyRequest(KeyRequestPrompt, KeyDecision),
+    OpenUnlock,
+    OpenVerify,
+    /// Own fingerprint and every known identity.
+    Identities(String, Vec<IdentityView>),
+    /// Trusts the changed key of a user.
+    AcceptIdentity(String),
+    /// Marks a user as verified after comparing safety numbers.
+    VerifyIdentity(String),
+    /// Unlocks the keystore, or creates it if there is none.
+    UnlockKeystore(Secret),
+    Leave(String),
+    NextRoom,
+    PreviousRoom,
+    /// Leaves the room shown in the chat.
+    CloseRoom,
 
     SyncProfile,
     ReloadConfig,
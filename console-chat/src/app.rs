@@ -4,8 +4,9 @@ use crate::{
     cli::Cli,
     components::{
         Component, chat::Chat, editor::ConfigFileEditor, error_display::ErrorDisplay,
-        fps::FpsCounter, home::Home, join::Join, login::Login, settings::Settings,
-        sorted_components,
+        fps::FpsCounter, home::Home, join::Join, key_approval::KeyApproval, key_import::KeyImport,
+        key_share::KeyShare, login::Login, room_admin::RoomAdmin, rooms::RoomBrowser,
+        search::Search, settings::Settings, sorted_components, unlock::Unlock, verify::Verify,
     },
     config::Config,
     error::AppError,
@@ -39,6 +40,13 @@ pub enum Mode {
     Home,
     Login,
     Join,
+    Rooms,
+    RoomAdmin,
+    Unlock,
+    Verify,
+    KeyShare,
+    KeyImport,
+    Search,
     Chat,
     Settings,
     RawSettings,
@@ -55,9 +63,17 @@ impl App {
                 Box::new(Home::new()),
                 Box::new(Chat::new()),
                 Box::new(Join::new()),
+                Box::new(RoomBrowser::new()),
+                Box::new(RoomAdmin::new()),
+                Box::new(Unlock::new()),
+                Box::new(Verify::new()),
+                Box::new(KeyShare::new()),
+                Box::new(KeyImport::new()),
+                Box::new(Search::new()),
                 Box::new(ConfigFileEditor::new()),
                 Box::new(Settings::new()),
                 Box::new(Login::new()),
+                Box::new(KeyApproval::new()),
                 Box::new(ErrorDisplay::new()),
                 Box::new(FpsCounter::default()),
             ]),
@@ -73,7 +89,14 @@ impl App {
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        network::init(self.args.clone(), self.action_tx.clone())
+        let network_config = self
+            .config
+            .read()
+            .error()
+            .map_err(AppError::Error)?
+            .network
+            .clone();
+        network::init(self.args.clone(), network_config, self.action_tx.clone())
             .await
             .map_err(|e| color_eyre::Report::new(e))?;
         let mut tui = Tui::new()?
@@ -91,6 +114,7 @@ impl App {
         for component in self.components.iter_mut() {
             component.init(tui.size()?)?;
         }
+        self.action_tx.send(Action::OpenUnlock)?;
 
         let action_tx = self.action_tx.clone();
         loop {
@@ -132,6 +156,14 @@ impl App {
         match self.mode {
             Mode::Home => self.action_tx.send(Action::OpenHome),
             Mode::Join => self.action_tx.send(Action::OpenJoin),
+            Mode::Rooms => self.action_tx.send(Action::OpenRooms),
+            Mode::RoomAdmin => self.action_tx.send(Action::OpenRoomAdmin),
+            Mode::Unlock => self.action_tx.send(Action::OpenUnlock),
+            Mode::Verify => self.action_tx.send(Action::OpenVerify),
+            // the key isn't kept, ask again with /share-key
+            Mode::KeyShare => self.action_tx.send(Action::OpenChat),
+            Mode::KeyImport => self.action_tx.send(Action::OpenJoin),
+            Mode::Search => self.action_tx.send(Action::OpenSearch(None)),
             Mode::Login => self.action_tx.send(Action::OpenLogin),
             Mode::Chat => self.action_tx.send(Action::OpenChat),
             Mode::Settings => self.action_tx.send(Action::OpenSettings),
@@ -243,9 +275,6 @@ impl App {
             self.restore_prev_mode()?;
         }
         self.hide_all();
-        if self.mode != mode && self.mode == Mode::Chat {
-            let _ = self.action_tx.send(Action::Leave);
-        }
         self.mode = mode;
         Ok(())
     }
@@ -267,11 +296,20 @@ impl App {
                 Action::Render => self.render(tui)?,
                 Action::ReloadConfig => self.reload_config(tui)?,
                 //open
-                Action::OpenJoin => self.set_mode(Mode::Join)?,
+                Action::OpenJoin | Action::PromptJoin(_) => self.set_mode(Mode::Join)?,
+                Action::OpenRooms => self.set_mode(Mode::Rooms)?,
+                Action::OpenUnlock => self.set_mode(Mode::Unlock)?,
+                Action::OpenVerify => self.set_mode(Mode::Verify)?,
+                Action::SharedKey(..) => self.set_mode(Mode::KeyShare)?,
+                Action::OpenKeyImport(_) => self.set_mode(Mode::KeyImport)?,
+                Action::OpenSearch(_) => self.set_mode(Mode::Search)?,
+                Action::OpenRoomAdmin | Action::EditRoom(_) => self.set_mode(Mode::RoomAdmin)?,
                 Action::OpenSettings => self.set_mode(Mode::Settings)?,
                 Action::OpenLogin => self.set_mode(Mode::Login)?,
                 Action::OpenHome => self.set_mode(Mode::Home)?,
-                Action::OpenChat => self.set_mode(Mode::Chat)?,
+                Action::OpenChat | Action::OpenRoom(_) | Action::JumpTo(_) => {
+                    self.set_mode(Mode::Chat)?
+                }
                 Action::OpenRawSettings => self.set_mode(Mode::RawSettings)?,
                 Action::Hide => self.hide_all(),
                 Action::Insert => {
@@ -1,6 +1,16 @@
-use clap::Parser;
+use chrono::Local;
+use clap::{Parser, Subcommand};
+use color_eyre::{Report, eyre::eyre};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::Stylize;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
 
 use crate::config::{get_config_dir, get_data_dir};
+use crate::network::history::History;
+use crate::network::keystore::{self, Keystore, Passphrase};
+use crate::network::search::{self, Hit, Query};
 
 #[derive(Parser, Debug, Clone)]
 #[command(author, version = version(), about)]
@@ -16,6 +26,154 @@ pub struct Cli {
     /// Accept invalid certificate, helpfull if server uses selfsinged certs
     #[arg(short, long, value_name = "BOOl", default_value_t = false)]
     pub accept_invalid_certificate: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// Manage the keystore holding your identity and room keys
+    #[command(subcommand)]
+    Keystore(KeystoreCommand),
+    /// Search the local history, asks for the passphrase of the keystore
+    Search {
+        /// Words or a /regex/, filtered with from:name, after:YYYY-MM-DD and before:YYYY-MM-DD
+        #[arg(required = true)]
+        query: Vec<String>,
+        /// Only search this room
+        #[arg(long)]
+        room: Option<String>,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum KeystoreCommand {
+    /// Copy the keystore to a file, it stays encrypted with your passphrase
+    Export { path: PathBuf },
+    /// Replace the keystore with an exported one
+    Import {
+        path: PathBuf,
+        /// Overwrite an existing keystore
+        #[arg(long)]
+        force: bool,
+    },
+    /// Delete the keystore and every key in it
+    Wipe {
+        /// Confirm that the keys are lost
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+/// Runs a subcommand instead of the TUI.
+pub fn run_command(command: Command) -> color_eyre::Result<()> {
+    match command {
+        Command::Keystore(command) => {
+            let result = match command {
+                KeystoreCommand::Export { path } => keystore::export(&path)
+                    .map(|_| format!("Exported keystore to {}", path.display())),
+                KeystoreCommand::Import { path, force } => keystore::import(&path, force)
+                    .map(|_| format!("Imported keystore from {}", path.display())),
+                KeystoreCommand::Wipe { yes: false } => {
+                    return Err(eyre!(
+                        "Wiping deletes all keys for good, pass --yes to confirm"
+                    ));
+                }
+                KeystoreCommand::Wipe { yes: true } => keystore::wipe()
+                    .map(|_| format!("Deleted {}", Keystore::default_path().display())),
+            };
+            println!("{}", result.map_err(color_eyre::Report::new)?);
+        }
+        Command::Search { query, room } => {
+            let mut query = Query::parse(&query.join(" ")).map_err(Report::new)?;
+            query.room = room;
+            let matcher = query.matcher().map_err(Report::new)?;
+            let passphrase = read_passphrase("Passphrase of the keystore: ")?;
+            let unlocked =
+                Keystore::unlock(Keystore::default_path(), &passphrase).map_err(Report::new)?;
+            let key = unlocked.keystore.history_key().map_err(Report::new)?;
+            let history = History::new(History::default_dir(), key);
+            let mut hits = Vec::new();
+            search::merge(&mut hits, history.search(&matcher).map_err(Report::new)?);
+            if hits.is_empty() {
+                eprintln!("Nothing found");
+            }
+            let highlight = std::io::stdout().is_terminal();
+            // oldest first, as in the chat
+            for hit in hits.iter().rev() {
+                println!("{}", format_hit(hit, highlight));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a line without echoing it, or a plain line if stdin isn't a terminal.
+fn read_passphrase(prompt: &str) -> color_eyre::Result<Passphrase> {
+    let mut passphrase = Zeroizing::new(String::new());
+    if !std::io::stdin().is_terminal() {
+        std::io::stdin().read_line(&mut passphrase)?;
+        return Ok(Passphrase::new(passphrase.trim_end().to_owned()));
+    }
+    eprint!("{prompt}");
+    std::io::stderr().flush()?;
+    crossterm::terminal::enable_raw_mode()?;
+    let read = loop {
+        match event::read() {
+            Ok(Event::Key(key)) if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => break Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    break Err(eyre!("Aborted"));
+                }
+                KeyCode::Char(c) => passphrase.push(c),
+                KeyCode::Backspace => {
+                    passphrase.pop();
+                }
+                _ => {}
+            },
+            Ok(_) => {}
+            Err(e) => break Err(e.into()),
+        }
+    };
+    crossterm::terminal::disable_raw_mode()?;
+    eprintln!();
+    read.map(|_| Passphrase::new(std::mem::take(&mut *passphrase)))
+}
+
+/// Time, room, sender and content of a hit, with the matches reversed if `highlight` is set.
+fn format_hit(hit: &Hit, highlight: bool) -> String {
+    let message = &hit.message;
+    let time = message
+        .send_at
+        .map(|send_at| {
+            send_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let sender = message
+        .user
+        .as_ref()
+        .and_then(|user| user.username.as_deref())
+        .unwrap_or("System");
+    let mut content = String::new();
+    let mut end = 0;
+    for range in hit.matches.iter().filter(|_| highlight) {
+        // a range that doesn't fit the content is left unhighlighted
+        let (Some(before), Some(found)) = (
+            message.content.get(end..range.start),
+            message.content.get(range.clone()),
+        ) else {
+            continue;
+        };
+        content.push_str(before);
+        content.push_str(&found.reverse().to_string());
+        end = range.end;
+    }
+    content.push_str(&message.content[end..]);
+    format!("{time} [{}] {sender}: {content}", hit.room)
 }
 
 const VERSION_MESSAGE: &str = concat!(
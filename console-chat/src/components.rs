@@ -16,9 +16,17 @@ pub mod error_display;
 pub mod fps;
 pub mod home;
 pub mod join;
+pub mod key_approval;
+pub mod key_import;
+pub mod key_share;
 pub mod login;
+pub mod room_admin;
+pub mod rooms;
+pub mod search;
 pub mod settings;
 pub mod ui_utils;
+pub mod unlock;
+pub mod verify;
 pub use ui_utils::*;
 
 /// `Component` is a trait that represents a visual and interactive element of the user interface.
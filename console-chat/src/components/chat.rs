@@ -3,12 +3,21 @@ use crate::LockErrorExt;
 use crate::action::Result;
 use crate::components::theme::Theme;
 use crate::components::vim::*;
-use crate::network::{Message, USERNAME};
-use crate::{action::Action, config::Config};
+use crate::network::attachments::{AttachmentState, AttachmentView};
+use crate::network::history::PAGE_SIZE;
+use crate::network::replay::Freshness;
+use crate::network::search::{self, Hit};
+use crate::network::signing::SignatureState;
+use crate::network::{ConnectionState, Message, RoomAddress, RoomEvent, RoomKind, USERNAME};
+use crate::{
+    action::Action,
+    config::{Config, EncryptionPolicy},
+};
 use chrono::Local;
 use crossterm::event::{KeyCode, KeyEvent};
 use openapi::models::{AppearancePublic, UserPublic};
 use ratatui::{prelude::*, widgets::*};
+use std::ops::Range;
 use std::sync::{Arc, RwLock};
 use tokio::sync::mpsc::UnboundedSender;
 use tracing::debug;
@@ -16,10 +25,27 @@ use tui_textarea::TextArea;
 
 const STYLE_KEY: crate::app::Mode = crate::app::Mode::Chat;
 
+/// Typed into the message field, rotates the room key instead of sending a message.
+const ROTATE_COMMAND: &str = "/rotate";
+
+/// Typed into the message field, shows the room key to hand it over in person.
+const SHARE_KEY_COMMAND: &str = "/share-key";
+
+/// Typed into the message field followed by a path, sends the file as an attachment.
+const SEND_FILE_COMMAND: &str = "/send-file";
+
+/// What follows [`SEND_FILE_COMMAND`], `None` if `content` isn't that command.
+fn send_file_argument(content: &str) -> Option<&str> {
+    let rest = content.trim().strip_prefix(SEND_FILE_COMMAND)?;
+    (rest.is_empty() || rest.starts_with(char::is_whitespace)).then(|| rest.trim())
+}
+
 struct MessageComponent {
     content: Message,
     alignment: Alignment,
     selected: bool,
+    /// Search matches to highlight, set when jumping to it.
+    matches: Vec<Range<usize>>,
 }
 impl MessageComponent {
     fn new(content: Message) -> Self {
@@ -40,6 +66,7 @@ impl MessageComponent {
             content,
             alignment,
             selected: false,
+            matches: Vec::new(),
         }
     }
     fn select(&mut self) {
@@ -50,6 +77,67 @@ impl MessageComponent {
     }
 }
 
+/// Name, size and progress of an attachment.
+fn attachment_card(attachment: &AttachmentView, own: bool) -> Text<'static> {
+    let header = format!("📎 {} · {}", attachment.name, format_size(attachment.size));
+    let progress = |verb: &str| {
+        let width = 20;
+        let filled = (attachment.done as usize * width) / attachment.count.max(1) as usize;
+        format!(
+            "{verb} ▕{}{}▏ {}/{}",
+            "█".repeat(filled),
+            "░".repeat(width - filled),
+            attachment.done,
+            attachment.count
+        )
+    };
+    let status = match &attachment.state {
+        AttachmentState::Sending => Line::from(progress("sending")),
+        AttachmentState::Receiving => Line::from(progress("receiving")),
+        AttachmentState::Sent => Line::from("✓ sent").green(),
+        AttachmentState::Complete if own => Line::from("✓ complete").green(),
+        AttachmentState::Complete => {
+            Line::from("✓ complete, select it and press <s> to save or <x> to dismiss").green()
+        }
+        AttachmentState::Saved(path) => Line::from(format!("saved to {}", path.display())).green(),
+        AttachmentState::Dismissed => Line::from("dismissed").dark_gray(),
+    };
+    Text::from(vec![Line::from(header), status])
+}
+
+/// `text` with the byte ranges in `matches` highlighted. Ranges that overlap or don't fit the
+/// text, like those of a search over an older version of it, are left out.
+pub(crate) fn highlighted(text: &str, matches: &[Range<usize>]) -> Line<'static> {
+    let mut line = Line::default();
+    let mut end = 0;
+    for range in matches {
+        let (Some(before), Some(found)) = (text.get(end..range.start), text.get(range.clone()))
+        else {
+            continue;
+        };
+        line.push_span(Span::from(before.to_owned()));
+        line.push_span(Span::from(found.to_owned()).black().on_yellow());
+        end = range.end;
+    }
+    line.push_span(Span::from(text[end..].to_owned()));
+    line
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
 impl Widget for &MessageComponent {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let user = self
@@ -61,14 +149,69 @@ impl Widget for &MessageComponent {
         let color = user.appearance.color.parse().unwrap_or(Color::Gray);
         let message = self.content.content.clone();
 
+        if self.content.event == Some(RoomEvent::KeyChanged) {
+            Paragraph::new(format!("⚠ {message}"))
+                .style(Style::new().fg(Color::Red).bold())
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: true })
+                .render(area, buf);
+            return;
+        }
+        if let Some(event) = self.content.event {
+            let marker = match event {
+                RoomEvent::Join => "→",
+                RoomEvent::Leave => "←",
+                RoomEvent::KeyChanged => "⚠",
+            };
+            Paragraph::new(format!("{marker} {message}"))
+                .style(Style::new().fg(Color::Gray).italic())
+                .alignment(Alignment::Center)
+                .render(area, buf);
+            return;
+        }
+
+        let mut title = Line::from(name);
+        if self.content.user.is_some() {
+            title.push_span(" ");
+            title.push_span(if self.content.encrypted {
+                Span::from("🔒")
+            } else {
+                Span::from("⚠ plaintext").red()
+            });
+            if self.content.encrypted {
+                title.push_span(" ");
+                title.push_span(match self.content.signature {
+                    SignatureState::Verified => Span::from("✓ verified").green(),
+                    SignatureState::Unverified => Span::from("? unverified").yellow(),
+                    SignatureState::Forged => Span::from("✗ forged").red().bold(),
+                });
+                match self.content.freshness {
+                    Freshness::Fresh => {}
+                    Freshness::OutOfWindow => {
+                        title.push_span(" ");
+                        title.push_span(Span::from("⏱ out of window").red());
+                    }
+                    Freshness::Unbound => {
+                        title.push_span(" ");
+                        title.push_span(Span::from("↺ unchecked").yellow());
+                    }
+                }
+                if self.content.envelope_version.is_none() {
+                    title.push_span(" ");
+                    title.push_span(Span::from("old client").gray());
+                }
+            }
+        }
+
         let mut block = Block::bordered()
             .border_type(BorderType::Rounded)
             .fg(color)
-            .title(name);
+            .title(title);
 
         if self.selected {
             //let theme: Style = active.to_owned().into();
             //block = block.style(theme.bg(color));
+            block = block.border_type(BorderType::Thick);
         }
 
         if let Some(send_time) = self.content.send_at {
@@ -81,7 +224,12 @@ impl Widget for &MessageComponent {
 
         block = block.title_alignment(self.alignment);
 
-        let para: Paragraph = Paragraph::new(message)
+        let body = match &self.content.attachment {
+            Some(attachment) => attachment_card(attachment, self.alignment == Alignment::Right),
+            None if self.matches.is_empty() => Text::from(message),
+            None => Text::from(highlighted(&message, &self.matches)),
+        };
+        let para: Paragraph = Paragraph::new(body)
             .wrap(Wrap { trim: false })
             .block(block)
             .alignment(self.alignment);
@@ -96,6 +244,21 @@ impl From<Message> for MessageComponent {
     }
 }
 
+/// History and connection state of one joined room.
+#[derive(Default)]
+struct RoomView {
+    name: String,
+    msgs: Vec<MessageComponent>,
+    connection: Option<ConnectionState>,
+    /// The room key is known.
+    encrypted: bool,
+    unread: usize,
+    /// Index of the oldest loaded message in the local history, `None` if none was loaded.
+    history_start: Option<usize>,
+    /// Messages from this index on arrived after the stored ones were loaded.
+    new_from: Option<usize>,
+}
+
 #[derive(Default)]
 pub struct Chat<'a> {
     active: bool,
@@ -104,7 +267,10 @@ pub struct Chat<'a> {
     textinput: TextArea<'a>,
     vim: Option<Vim>,
     index: usize, // index of currently selected message in msgs (0 means none / input)
-    msgs: Vec<MessageComponent>,
+    rooms: Vec<RoomView>,
+    current: usize, // index of the shown room in rooms
+    /// A search hit that is shown once its room is joined or its page of the history is loaded.
+    pending_jump: Option<Hit>,
 }
 
 impl Chat<'_> {
@@ -112,8 +278,38 @@ impl Chat<'_> {
         Self::default()
     }
 
+    fn msgs(&self) -> &[MessageComponent] {
+        self.rooms
+            .get(self.current)
+            .map(|room| room.msgs.as_slice())
+            .unwrap_or_default()
+    }
+
     fn safe_len(&self) -> usize {
-        self.msgs.len().max(1) // ensure math using len doesn't underflow; index 0 is input
+        self.msgs().len().max(1) // ensure math using len doesn't underflow; index 0 is input
+    }
+
+    /// Index of `name` in rooms, adds the room if it isn't known yet.
+    fn room_index(&mut self, name: &str) -> usize {
+        match self.rooms.iter().position(|room| room.name == name) {
+            Some(i) => i,
+            None => {
+                self.rooms.push(RoomView {
+                    name: name.to_owned(),
+                    ..Default::default()
+                });
+                self.rooms.len() - 1
+            }
+        }
+    }
+
+    fn show_room(&mut self, index: usize) {
+        self.current = index;
+        if let Some(room) = self.rooms.get_mut(index) {
+            room.unread = 0;
+        }
+        self.index = 0;
+        self.update_selection();
     }
 
     fn up(&mut self) {
@@ -121,31 +317,140 @@ impl Chat<'_> {
         let max = self.safe_len();
         self.index = if self.index == 0 {
             // move to last message if any
-            if self.msgs.is_empty() {
-                0
-            } else {
-                self.msgs.len()
-            }
+            self.msgs().len()
         } else {
             (self.index - 1) % max
         };
+        if self.index == 1 {
+            self.load_older();
+        }
         // update selection
         self.update_selection();
     }
 
+    /// Asks for the stored messages before the oldest loaded one.
+    fn load_older(&self) {
+        if let Some(room) = self.rooms.get(self.current)
+            && let Some(start) = room.history_start.filter(|start| *start > 0)
+            && let Some(command_tx) = self.command_tx.as_ref()
+        {
+            let _ = command_tx.send(Action::LoadHistory(
+                room.name.clone(),
+                start.saturating_sub(PAGE_SIZE),
+                start,
+            ));
+        }
+    }
+
     fn down(&mut self) {
         let max = self.safe_len();
         self.index = (self.index + 1) % max;
         self.update_selection();
     }
 
+    /// Selects the message of `hit` and highlights its matches. Joins its room or loads the
+    /// history down to it first if needed.
+    fn jump(&mut self, hit: Hit) -> Option<Action> {
+        let Some(index) = self.rooms.iter().position(|room| room.name == hit.room) else {
+            let action = Action::PerformJoin(hit.room.clone());
+            self.pending_jump = Some(hit);
+            return Some(action);
+        };
+        self.show_room(index);
+        let room = &mut self.rooms[index];
+        for msg in &mut room.msgs {
+            msg.matches.clear();
+        }
+        if let Some(position) = room.msgs.iter().position(|msg| msg.content == hit.message) {
+            room.msgs[position].matches = hit.matches;
+            self.index = position + 1;
+            self.update_selection();
+            return None;
+        }
+        match (hit.stored_at, room.history_start) {
+            (Some(stored_at), Some(start)) if stored_at < start => {
+                let action = Action::LoadHistory(
+                    room.name.clone(),
+                    stored_at.saturating_sub(search::CONTEXT),
+                    start,
+                );
+                self.pending_jump = Some(hit);
+                Some(action)
+            }
+            _ => Some(Action::Error(
+                "The message is no longer in the chat or the history".into(),
+            )),
+        }
+    }
+
+    /// Saves the selected attachment if it was received completely.
+    fn save_selected(&self) -> Option<Action> {
+        let room = self.rooms.get(self.current)?;
+        let msg = room.msgs.get(self.index.checked_sub(1)?)?;
+        let attachment = msg.content.attachment.as_ref()?;
+        (msg.alignment == Alignment::Left && attachment.state == AttachmentState::Complete)
+            .then(|| Action::SaveAttachment(room.name.clone(), attachment.transfer.clone()))
+    }
+
+    /// Throws the selected attachment away if it is still being received or waits to be saved.
+    fn dismiss_selected(&self) -> Option<Action> {
+        let room = self.rooms.get(self.current)?;
+        let msg = room.msgs.get(self.index.checked_sub(1)?)?;
+        let attachment = msg.content.attachment.as_ref()?;
+        let held = matches!(
+            attachment.state,
+            AttachmentState::Receiving | AttachmentState::Complete
+        );
+        (msg.alignment == Alignment::Left && held)
+            .then(|| Action::DismissAttachment(room.name.clone(), attachment.transfer.clone()))
+    }
+
+    /// Kind and connection state of the shown room.
+    fn status_line(&self) -> Option<Line<'static>> {
+        let room = self.rooms.get(self.current)?;
+        let kind = match room.name.parse::<RoomAddress>().map(|address| address.kind) {
+            Ok(RoomKind::Static) => "static room, history is kept",
+            _ => "ephemeral room",
+        };
+        let mut line = Line::from(Span::from(kind).italic());
+        if let Some(connection) = room.connection {
+            line.push_span(Span::from(" │ "));
+            line.push_span(match connection {
+                ConnectionState::Connecting => Span::from("connecting…").yellow(),
+                ConnectionState::Live => Span::from("● connected").green(),
+                ConnectionState::Reconnecting(attempt) => {
+                    Span::from(format!("reconnecting (attempt {attempt})…")).yellow()
+                }
+                ConnectionState::Failed => Span::from("✗ connection lost").red(),
+            });
+        }
+        let policy = self
+            .config
+            .read()
+            .map(|config| config.network.encryption_for(&room.name))
+            .unwrap_or_default();
+        line.push_span(Span::from(" │ "));
+        line.push_span(match (room.encrypted, policy) {
+            (true, _) => Span::from("🔒 encrypted").green(),
+            (false, EncryptionPolicy::RequireE2E) => {
+                Span::from("⚠ waiting for room key, sending is blocked").yellow()
+            }
+            (false, _) => Span::from("⚠ not encrypted").red(),
+        });
+        Some(line.right_aligned())
+    }
+
     fn update_selection(&mut self) {
         // unselect all, then select the message at self.index (if > 0)
-        for m in &mut self.msgs {
+        let Some(room) = self.rooms.get_mut(self.current) else {
+            self.index = 0;
+            return;
+        };
+        for m in &mut room.msgs {
             m.unselect();
         }
         if self.index > 0 {
-            if let Some(m) = self.msgs.get_mut(self.index - 1) {
+            if let Some(m) = room.msgs.get_mut(self.index - 1) {
                 m.select();
             }
             // when a message is selected, style textarea as normal (no special)
@@ -234,7 +539,28 @@ impl Component for Chat<'_> {
                         }
                         Transition::Enter(content) => {
                             debug!("{}", content);
-                            command_tx.send(Action::SendMessage(content.to_owned()))?;
+                            let send_file = send_file_argument(&content);
+                            match self.rooms.get(self.current) {
+                                Some(room) if content.trim() == ROTATE_COMMAND => {
+                                    command_tx.send(Action::RotateKey(room.name.clone()))?
+                                }
+                                Some(room) if content.trim() == SHARE_KEY_COMMAND => {
+                                    command_tx.send(Action::ShareKey(room.name.clone()))?
+                                }
+                                Some(room) if send_file.is_some() => match send_file {
+                                    Some(path) if !path.is_empty() => command_tx
+                                        .send(Action::SendFile(room.name.clone(), path.into()))?,
+                                    _ => command_tx.send(Action::Error(
+                                        format!("Usage: {SEND_FILE_COMMAND} <path>").into(),
+                                    ))?,
+                                },
+                                Some(room) => command_tx.send(Action::SendMessage(
+                                    room.name.clone(),
+                                    content.to_owned(),
+                                ))?,
+                                None => command_tx
+                                    .send(Action::Error("Join a room to send messages".into()))?,
+                            }
                             self.textinput = TextArea::default();
                             self.textinput.set_block(this_vim.mode.highlight_block());
                             self.textinput
@@ -249,6 +575,16 @@ impl Component for Chat<'_> {
                 match key.code {
                     KeyCode::Char('k') => self.up(),
                     KeyCode::Char('j') => self.down(),
+                    KeyCode::Char('s') => {
+                        if let Some(action) = self.save_selected() {
+                            command_tx.send(action)?;
+                        }
+                    }
+                    KeyCode::Char('x') => {
+                        if let Some(action) = self.dismiss_selected() {
+                            command_tx.send(action)?;
+                        }
+                    }
                     _ => {}
                 }
             }
@@ -264,17 +600,130 @@ impl Component for Chat<'_> {
                 self.index = 0;
                 self.update_selection();
             }
-            Action::ReceivedMessage(msg) => {
-                self.msgs.push(msg.into());
+            Action::OpenRoom(room) => {
+                self.active = true;
+                let index = self.room_index(&room);
+                self.show_room(index);
+                if let Some(hit) = self.pending_jump.take_if(|hit| hit.room == room) {
+                    return Ok(self.jump(hit));
+                }
+            }
+            Action::StartSearch if self.active => {
+                let room = self.rooms.get(self.current).map(|room| room.name.clone());
+                return Ok(Some(Action::OpenSearch(room)));
+            }
+            Action::Search(query) => {
+                // reported by the search screen
+                let Ok(matcher) = query.matcher() else {
+                    return Ok(None);
+                };
+                let hits = self
+                    .rooms
+                    .iter()
+                    .flat_map(|room| {
+                        let messages: Vec<Message> =
+                            room.msgs.iter().map(|msg| msg.content.clone()).collect();
+                        search::search(&matcher, &room.name, &messages)
+                            .into_iter()
+                            .map(|(_, hit)| hit)
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
+                return Ok(Some(Action::SearchResults(query, hits)));
+            }
+            Action::JumpTo(hit) => {
+                self.active = true;
+                self.pending_jump = None;
+                return Ok(self.jump(hit));
+            }
+            Action::ReceivedMessage(room, msg) => {
+                let index = self.room_index(&room);
+                let room = &mut self.rooms[index];
+                room.msgs.push(msg.into());
+                if index != self.current {
+                    room.unread += 1;
+                }
                 // keep selection on input, but if a message was selected keep it
-                if self.index > self.msgs.len() {
-                    self.index = self.msgs.len();
+                if self.index > self.msgs().len() {
+                    self.index = self.msgs().len();
                 }
                 self.update_selection();
             }
-            Action::Leave => {
-                self.msgs.clear();
-                self.index = 0;
+            Action::History(room, msgs, start) => {
+                let index = self.room_index(&room);
+                let room = &mut self.rooms[index];
+                // a page asked for twice, while scrolling up quickly
+                if room.history_start.is_some_and(|loaded| start >= loaded) {
+                    return Ok(None);
+                }
+                // on join, a pending jump waits for the room to open
+                let loaded_before = room.history_start.is_some();
+                let count = msgs.len();
+                room.msgs
+                    .splice(0..0, msgs.into_iter().map(MessageComponent::from));
+                room.history_start = Some(start);
+                room.new_from = Some(room.new_from.map_or(count, |from| from + count));
+                // the selected message moved down by the prepended ones
+                if index == self.current && self.index > 0 {
+                    self.index += count;
+                }
+                self.update_selection();
+                let name = &self.rooms[index].name;
+                if loaded_before
+                    && let Some(hit) = self.pending_jump.take_if(|hit| &hit.room == name)
+                {
+                    return Ok(self.jump(hit));
+                }
+            }
+            Action::Attachment(room, msg) => {
+                let index = self.room_index(&room);
+                let room = &mut self.rooms[index];
+                let transfer = msg.attachment.as_ref().map(|view| view.transfer.clone());
+                let card = room.msgs.iter_mut().find(|card| {
+                    card.content.attachment.as_ref().map(|view| &view.transfer) == transfer.as_ref()
+                });
+                match card {
+                    // saving only knows the transfer, the sender stays as it was
+                    Some(card) => card.content.attachment = msg.attachment,
+                    None => {
+                        room.msgs.push(msg.into());
+                        if index != self.current {
+                            room.unread += 1;
+                        }
+                    }
+                }
+                self.update_selection();
+            }
+            Action::ConnectionState(room, state) => {
+                let index = self.room_index(&room);
+                self.rooms[index].connection = Some(state);
+            }
+            Action::RoomEncrypted(room) => {
+                let index = self.room_index(&room);
+                self.rooms[index].encrypted = true;
+            }
+            Action::Leave(room) => {
+                if let Some(index) = self.rooms.iter().position(|r| r.name == room) {
+                    self.rooms.remove(index);
+                    if self.current > index || self.current >= self.rooms.len() {
+                        self.current = self.current.saturating_sub(1);
+                    }
+                    self.show_room(self.current);
+                }
+                if self.active && self.rooms.is_empty() {
+                    return Ok(Some(Action::OpenHome));
+                }
+            }
+            Action::NextRoom if self.active && !self.rooms.is_empty() => {
+                self.show_room((self.current + 1) % self.rooms.len());
+            }
+            Action::PreviousRoom if self.active && !self.rooms.is_empty() => {
+                self.show_room((self.current + self.rooms.len() - 1) % self.rooms.len());
+            }
+            Action::CloseRoom if self.active => {
+                if let Some(room) = self.rooms.get(self.current) {
+                    return Ok(Some(Action::Leave(room.name.clone())));
+                }
             }
             Action::Tick => {}
             Action::Render => {}
@@ -299,18 +748,70 @@ impl Component for Chat<'_> {
                     .split(area)[1],
                 );
 
+            if !self.rooms.is_empty() {
+                let [header_area, rest] =
+                    Layout::vertical([Constraint::Length(1), Constraint::Fill(1)]).areas(chat_area);
+                chat_area = rest;
+                let status = self.status_line().unwrap_or_default();
+                let [tabs_area, status_area] = Layout::horizontal([
+                    Constraint::Fill(1),
+                    Constraint::Length(status.width() as u16),
+                ])
+                .areas(header_area);
+                let titles = self.rooms.iter().map(|room| {
+                    let name = room
+                        .name
+                        .parse::<RoomAddress>()
+                        .map(|address| address.name)
+                        .unwrap_or(room.name.clone());
+                    if room.unread > 0 {
+                        format!("{} ({})", name, room.unread)
+                    } else {
+                        name
+                    }
+                });
+                Tabs::new(titles)
+                    .select(self.current)
+                    .highlight_style(Style::new().bold().reversed())
+                    .render(tabs_area, buf);
+                status.render(status_area, buf);
+            }
+
+            let new_from = self.rooms.get(self.current).and_then(|room| room.new_from);
+            let msgs = self.msgs();
+            // a selected message is shown with the few after it, otherwise the newest are
+            let end = match self.index {
+                0 => msgs.len(),
+                i => (i + search::CONTEXT).min(msgs.len()),
+            };
             // render messages from newest at bottom; compute rows conservatively
-            for msg in self.msgs.iter().rev() {
+            for (i, msg) in msgs[..end].iter().enumerate().rev() {
                 // approximate rows needed: message length divided by width, plus padding
                 let a = msg.content.content.len() as u16;
                 let b = chat_area.width.max(1);
                 let rows = a.div_ceil(b);
-                let max_rows = rows.saturating_add(2);
+                let max_rows = match msg.content.event {
+                    Some(RoomEvent::KeyChanged) => rows,
+                    Some(_) => 1,
+                    // name and progress, in a border
+                    None if msg.content.attachment.is_some() => 4,
+                    None => rows.saturating_add(2),
+                };
                 let [new_chat, msg_area] =
                     Layout::vertical([Constraint::Fill(1), Constraint::Max(max_rows)])
                         .areas(chat_area);
                 msg.render(msg_area, buf);
                 chat_area = new_chat;
+                if new_from == Some(i) {
+                    let [new_chat, separator] =
+                        Layout::vertical([Constraint::Fill(1), Constraint::Max(1)])
+                            .areas(chat_area);
+                    Line::from("── new messages ──")
+                        .yellow()
+                        .centered()
+                        .render(separator, buf);
+                    chat_area = new_chat;
+                }
             }
 
             self.textinput.render(input_area, buf);
@@ -17,7 +17,9 @@ pub struct Home {
     config: Arc<RwLock<Config>>,
     home_theme: PageColors,
     join: Button,
+    rooms: Button,
     random: Button,
+    search: Button,
     login: Button,
     settings: Button,
     raw_settings: Button,
@@ -28,7 +30,7 @@ pub struct Home {
 }
 
 impl Home {
-    pub const MAX_ELEMENTS: usize = 7;
+    pub const MAX_ELEMENTS: usize = 9;
 
     pub fn new() -> Self {
         Self::default()
@@ -59,7 +61,9 @@ impl Home {
     const fn get_buttons(&mut self) -> [&mut Button; Self::MAX_ELEMENTS] {
         [
             &mut self.join,
+            &mut self.rooms,
             &mut self.random,
+            &mut self.search,
             &mut self.login,
             &mut self.settings,
             &mut self.raw_settings,
@@ -94,12 +98,24 @@ impl Component for Home {
             };
             self.login = Button::new("Login", "", theme.buttons.accepting, Action::OpenLogin);
             self.join = Button::new("Join", "", theme.buttons.mid_accept, Action::OpenJoin);
+            self.rooms = Button::new(
+                "Browse Rooms",
+                "",
+                theme.buttons.mid_accept,
+                Action::OpenRooms,
+            );
             self.random = Button::new(
                 "Join Random",
                 "",
                 theme.buttons.mid_accept,
                 Action::JoinRandom,
             );
+            self.search = Button::new(
+                "Search History",
+                "",
+                theme.buttons.mid_accept,
+                Action::OpenSearch(None),
+            );
             self.settings = Button::new("Settings", "", theme.buttons.normal, Action::OpenSettings);
             self.raw_settings = Button::new(
                 "Settings File",
@@ -152,6 +168,7 @@ impl Component for Home {
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
             Action::OpenHome => self.active = true,
+            Action::StartSearch if self.active => return Ok(Some(Action::OpenSearch(None))),
             Action::Tick => {
                 // add any logic here that should run on every tick
                 for button in self.get_buttons() {
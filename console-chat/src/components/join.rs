@@ -1,6 +1,7 @@
 use crate::LockErrorExt;
 use crate::action::Result;
 use crate::components::{button::*, theme::*, vim::*};
+use crate::secret::Secret;
 use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{prelude::*, widgets::*};
 use std::sync::{Arc, RwLock};
@@ -14,13 +15,20 @@ use crate::{action::Action, action::AppError, config::Config};
 
 const STYLE_KEY: crate::app::Mode = crate::app::Mode::Join;
 
+const PASSPHRASE_TITLE: &str =
+    "Passphrase (only for key protected rooms, the server can test guesses of it)";
+
 #[derive(Default, Debug)]
 pub struct Join<'a> {
     active: bool,
     command_tx: Option<UnboundedSender<Action>>,
     config: Arc<RwLock<Config>>,
     room: TextArea<'a>,
+    /// Only for rooms protected by a key, the room key is derived from it.
+    passphrase: TextArea<'a>,
     join: Button,
+    /// Opens the import of a key shared in person, instead of asking the room for it.
+    import: Button,
     cancel: Button,
     vim: Option<Vim>,
     index: usize,
@@ -28,7 +36,7 @@ pub struct Join<'a> {
 }
 
 impl Join<'_> {
-    pub const MAX_ELEMENTS: usize = 3;
+    pub const MAX_ELEMENTS: usize = 5;
 
     pub fn new() -> Self {
         Self::default()
@@ -38,6 +46,7 @@ impl Join<'_> {
         let size = self.size;
         self.index = 0;
         self.room = TextArea::default();
+        self.passphrase = TextArea::default();
         self.init(size)
     }
 
@@ -57,15 +66,30 @@ impl Join<'_> {
 
     fn update_elements(&mut self) {
         self.join.set_state(ButtonState::Normal);
+        self.import.set_state(ButtonState::Normal);
         self.cancel.set_state(ButtonState::Normal);
-        self.room
-            .set_block(Block::default().borders(Borders::ALL).title("Room"));
+        self.room.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Room (static:<name> for persistent rooms)"),
+        );
+        self.passphrase.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(PASSPHRASE_TITLE),
+        );
         match self.index {
             0 => self.room.set_block(VimMode::Normal.highlight_block()),
-            1 => {
+            1 => self
+                .passphrase
+                .set_block(VimMode::Normal.highlight_block().title(PASSPHRASE_TITLE)),
+            2 => {
                 self.join.set_state(ButtonState::Selected);
             }
-            2 => {
+            3 => {
+                self.import.set_state(ButtonState::Selected);
+            }
+            4 => {
                 self.cancel.set_state(ButtonState::Selected);
             }
             _ => {
@@ -74,8 +98,8 @@ impl Join<'_> {
         }
     }
 
-    const fn get_buttons(&mut self) -> [&mut Button; 2] {
-        [&mut self.join, &mut self.cancel]
+    const fn get_buttons(&mut self) -> [&mut Button; 3] {
+        [&mut self.join, &mut self.import, &mut self.cancel]
     }
 
     fn send(&mut self, action: Action) -> Result<()> {
@@ -91,6 +115,7 @@ impl<'a> Join<'a> {
         let vim = self.vim.take().unwrap_or_default();
         match self.index {
             0 => Some((&mut self.room, vim)),
+            1 => Some((&mut self.passphrase, vim)),
             _ => None,
         }
     }
@@ -123,8 +148,18 @@ impl Component for Join<'_> {
             self.room.set_cursor_line_style(Style::default());
             self.room.set_style(Style::default().fg(Color::LightGreen));
             self.room.set_block(VimMode::Normal.highlight_block());
+            self.passphrase.set_cursor_line_style(Style::default());
+            self.passphrase
+                .set_style(Style::default().fg(Color::LightGreen));
+            self.passphrase.set_mask_char('\u{2022}');
 
             self.join = Button::new("Join", "", theme.buttons.accepting, Action::TriggerJoin);
+            self.import = Button::new(
+                "Import key",
+                "words or QR code",
+                theme.buttons.normal,
+                Action::OpenKeyImport(String::new()),
+            );
             self.cancel = Button::new("Abort", "<q>", theme.buttons.denying, Action::OpenHome);
         }
         self.update_elements();
@@ -167,13 +202,20 @@ impl Component for Join<'_> {
                 }
                 None => match key.code {
                     KeyCode::Enter => {
-                        let i = self.index - 1;
+                        let i = self.index - 2;
                         let room = self.room.lines()[0].clone();
+                        let passphrase = self.passphrase.lines().join("\n");
                         let buttons = self.get_buttons();
                         buttons[i].set_state(ButtonState::Active);
                         let button_action = buttons[i].trigger();
                         let result = match button_action {
-                            Some(Action::TriggerJoin) => Some(Action::PerformJoin(room)),
+                            Some(Action::TriggerJoin) if passphrase.is_empty() => {
+                                Some(Action::PerformJoin(room))
+                            }
+                            Some(Action::TriggerJoin) => {
+                                Some(Action::JoinWithPassphrase(room, Secret::new(passphrase)))
+                            }
+                            Some(Action::OpenKeyImport(_)) => Some(Action::OpenKeyImport(room)),
                             _ => button_action,
                         };
                         self.reset()?;
@@ -201,11 +243,23 @@ impl Component for Join<'_> {
     fn update(&mut self, action: Action) -> Result<Option<Action>> {
         match action {
             Action::OpenJoin => self.active = true,
+            Action::PromptJoin(room) => {
+                self.active = true;
+                self.room = TextArea::from([room]);
+                self.room.set_cursor_line_style(Style::default());
+                self.room.set_style(Style::default().fg(Color::LightGreen));
+                // the room is known, what is missing is the passphrase
+                self.index = 1;
+                self.update_elements();
+            }
             Action::Tick => {
                 // add any logic here that should run on every tick
                 if self.join.is_active() {
                     self.join.set_state(ButtonState::Selected);
                 }
+                if self.import.is_active() {
+                    self.import.set_state(ButtonState::Selected);
+                }
                 if self.cancel.is_active() {
                     self.cancel.set_state(ButtonState::Selected);
                 }
@@ -226,7 +280,7 @@ impl Component for Join<'_> {
 
             let center = Layout::vertical([
                 Constraint::Fill(1),
-                Constraint::Max(3 * 3),
+                Constraint::Max(3 * 5),
                 Constraint::Fill(1),
             ])
             .split(
@@ -242,14 +296,21 @@ impl Component for Join<'_> {
             let block = Block::new().bg(Color::DarkGray);
             block.render(center, buf);
 
-            let [a, b, c] =
-                Layout::vertical([Constraint::Max(3), Constraint::Max(3), Constraint::Max(3)])
-                    .areas(center);
+            let [a, b, c, d, e] = Layout::vertical([
+                Constraint::Max(3),
+                Constraint::Max(3),
+                Constraint::Max(3),
+                Constraint::Max(3),
+                Constraint::Max(3),
+            ])
+            .areas(center);
 
             self.room.render(a, buf);
+            self.passphrase.render(b, buf);
 
-            self.join.draw_button(b, buf);
-            self.cancel.draw_button(c, buf);
+            self.join.draw_button(c, buf);
+            self.import.draw_button(d, buf);
+            self.cancel.draw_button(e, buf);
         }
         Ok(())
     }
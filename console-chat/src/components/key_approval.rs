@@ -0,0 +1,95 @@
+use super::Component;
+use crate::action::Action;
+use crate::action::Result;
+use crate::network::approval::{KeyDecision, KeyRequestPrompt};
+use ratatui::{prelude::*, widgets::*};
+use std::collections::VecDeque;
+
+/// Asks the user whether a key request should be answered, shown on top of every screen.
+#[derive(Debug, Default)]
+pub struct KeyApproval {
+    prompts: VecDeque<KeyRequestPrompt>,
+}
+
+impl KeyApproval {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn answer(&mut self, decision: KeyDecision) -> Option<Action> {
+        let prompt = self.prompts.pop_front()?;
+        // the same key may have asked again while the prompt was open
+        self.prompts
+            .retain(|p| p.room != prompt.room || p.public_key != prompt.public_key);
+        Some(Action::AnswerKeyRequest(prompt, decision))
+    }
+}
+
+impl Component for KeyApproval {
+    fn hide(&mut self) {}
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        Ok(match action {
+            Action::KeyRequestPrompt(prompt) => {
+                if !self.prompts.contains(&prompt) {
+                    self.prompts.push_back(prompt);
+                }
+                None
+            }
+            Action::ApproveKeyRequest => self.answer(KeyDecision::Approve),
+            Action::DenyKeyRequest => self.answer(KeyDecision::Deny),
+            Action::TrustKeyRequest => self.answer(KeyDecision::AlwaysTrust),
+            _ => None,
+        })
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if let Some(prompt) = self.prompts.front() {
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Length(60),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+            let [_, center, _] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Length(7),
+                Constraint::Fill(1),
+            ])
+            .areas(center);
+
+            let requester = prompt.username.as_deref().unwrap_or("An unknown user");
+            let mut title = Line::from(" Key request ");
+            if self.prompts.len() > 1 {
+                title.push_span(format!("(1 of {}) ", self.prompts.len()));
+            }
+            let text = vec![
+                Line::from(vec![
+                    Span::from(requester).bold(),
+                    Span::from(" asks for the key of "),
+                    Span::from(prompt.room.as_str()).bold(),
+                ]),
+                Line::from(""),
+                Line::from(vec![
+                    Span::from("Fingerprint "),
+                    Span::from(prompt.fingerprint.as_str()).yellow(),
+                ]),
+            ];
+            frame.render_widget(Clear, center);
+            let display = Paragraph::new(text)
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .border_type(BorderType::Double)
+                        .title(title)
+                        .title_bottom(
+                            " <Alt-y> approve once  <Alt-n> always deny  <Alt-t> always trust ",
+                        )
+                        .padding(Padding::horizontal(1)),
+                )
+                .on_dark_gray();
+            frame.render_widget(display, center);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::{button::*, theme::*, vim::*};
+use crate::secret::Secret;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::trace;
+use tui_textarea::TextArea;
+
+use super::Component;
+use crate::{action::Action, action::AppError, config::Config};
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::KeyImport;
+
+const WORDS_TITLE: &str = "Key words or scanned code";
+
+const EXPLANATION: &str = "Type the words another member shows with /share-key, or paste the text \
+of their QR code. The room key is installed before joining, no key request is sent.";
+
+/// Joins a room with a key shared out of band.
+#[derive(Default, Debug)]
+pub struct KeyImport<'a> {
+    active: bool,
+    command_tx: Option<UnboundedSender<Action>>,
+    config: Arc<RwLock<Config>>,
+    room: String,
+    words: TextArea<'a>,
+    join: Button,
+    cancel: Button,
+    vim: Option<Vim>,
+    index: usize,
+}
+
+impl KeyImport<'_> {
+    pub const MAX_ELEMENTS: usize = 3;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn reset_words(&mut self) {
+        self.words = TextArea::default();
+        self.words.set_cursor_line_style(Style::default());
+        self.words.set_style(Style::default().fg(Color::LightGreen));
+        self.update_elements();
+    }
+
+    fn up(&mut self) {
+        self.index = if self.index == 0 {
+            Self::MAX_ELEMENTS - 1
+        } else {
+            self.index - 1
+        };
+        self.update_elements();
+    }
+
+    fn down(&mut self) {
+        self.index = (self.index + 1) % Self::MAX_ELEMENTS;
+        self.update_elements();
+    }
+
+    fn update_elements(&mut self) {
+        self.join.set_state(ButtonState::Normal);
+        self.cancel.set_state(ButtonState::Normal);
+        self.words
+            .set_block(Block::default().borders(Borders::ALL).title(WORDS_TITLE));
+        match self.index {
+            0 => self
+                .words
+                .set_block(VimMode::Normal.highlight_block().title(WORDS_TITLE)),
+            1 => self.join.set_state(ButtonState::Selected),
+            2 => self.cancel.set_state(ButtonState::Selected),
+            _ => self.index %= Self::MAX_ELEMENTS,
+        }
+    }
+
+    const fn get_buttons(&mut self) -> [&mut Button; 2] {
+        [&mut self.join, &mut self.cancel]
+    }
+
+    fn send(&mut self, action: Action) -> Result<()> {
+        trace!("sending action: {action}");
+        let action_tx = self.command_tx.as_ref().ok_or(AppError::MissingActionTX)?;
+
+        Ok(action_tx.send(action)?)
+    }
+}
+
+impl<'a> KeyImport<'a> {
+    fn get_selected_input(&mut self) -> Option<(&mut TextArea<'a>, Vim)> {
+        let vim = self.vim.take().unwrap_or_default();
+        match self.index {
+            0 => Some((&mut self.words, vim)),
+            _ => None,
+        }
+    }
+}
+
+impl Component for KeyImport<'_> {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+
+    fn init(&mut self, _: Size) -> Result<()> {
+        {
+            let mut config = self.config.write().error()?;
+            let theme = match config.themes.get(&STYLE_KEY) {
+                Some(themes) => themes,
+                None => match config.themes.get(&crate::app::Mode::Global) {
+                    Some(themes) => themes,
+                    None => {
+                        config
+                            .themes
+                            .insert(crate::app::Mode::Global, Theme::default());
+                        config
+                            .themes
+                            .get(&crate::app::Mode::Global)
+                            .ok_or("This is bad")?
+                    }
+                },
+            };
+
+            self.vim = Some(Vim::default());
+            self.join = Button::new("Join", "", theme.buttons.accepting, Action::Render);
+            self.cancel = Button::new("Abort", "<q>", theme.buttons.denying, Action::OpenJoin);
+        }
+        self.reset_words();
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        if !self.active {
+            return Ok(None);
+        }
+        match self.get_selected_input() {
+            Some((textinput, this_vim)) => {
+                self.vim = Some(match this_vim.transition(key.into(), textinput) {
+                    Transition::Mode(mode) if this_vim.mode != mode => {
+                        textinput.set_block(mode.highlight_block());
+                        textinput.set_cursor_style(mode.cursor_style(this_vim.style));
+                        match mode {
+                            VimMode::Insert => self.send(Action::Insert)?,
+                            VimMode::Normal if this_vim.mode == VimMode::Insert => {
+                                self.send(Action::Normal)?
+                            }
+                            _ => {}
+                        };
+                        this_vim.update_mode(mode)
+                    }
+                    Transition::Nop | Transition::Mode(_) | Transition::Store => this_vim,
+                    Transition::Pending(input) => this_vim.with_pending(input),
+                    Transition::Up => {
+                        self.up();
+                        this_vim
+                    }
+                    Transition::Down => {
+                        self.down();
+                        this_vim
+                    }
+                    Transition::Enter(_) => {
+                        self.down();
+                        this_vim.update_mode(VimMode::Normal)
+                    }
+                });
+            }
+            None => match key.code {
+                KeyCode::Enter => {
+                    let i = self.index - 1;
+                    let buttons = self.get_buttons();
+                    buttons[i].set_state(ButtonState::Active);
+                    if i == 1 {
+                        return Ok(buttons[i].trigger());
+                    }
+                    let words = Secret::new(self.words.lines().join(" "));
+                    self.reset_words();
+                    return Ok(Some(Action::JoinWithKey(self.room.clone(), words)));
+                }
+                KeyCode::Char('k') => self.up(),
+                KeyCode::Char('j') => self.down(),
+                _ => {}
+            },
+        }
+        Ok(None)
+    }
+
+    fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<()> {
+        self.command_tx = Some(tx);
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenKeyImport(room) => {
+                self.active = true;
+                self.room = room;
+                self.index = 0;
+                self.reset_words();
+            }
+            Action::Tick => {
+                if self.join.is_active() {
+                    self.join.set_state(ButtonState::Selected);
+                }
+                if self.cancel.is_active() {
+                    self.cancel.set_state(ButtonState::Selected);
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            let block = Block::new().bg(Color::Blue);
+            block.render(area, buf);
+
+            let explanation_height = 5;
+            let center = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Max(3 * 3 + explanation_height),
+                Constraint::Fill(1),
+            ])
+            .split(
+                Layout::horizontal([
+                    Constraint::Fill(1),
+                    Constraint::Percentage(40),
+                    Constraint::Fill(1),
+                ])
+                .split(area)[1],
+            )[1];
+
+            Clear.render(center, buf);
+            let block = Block::new().bg(Color::DarkGray);
+            block.render(center, buf);
+
+            let [explanation, a, b, c] = Layout::vertical([
+                Constraint::Length(explanation_height),
+                Constraint::Max(3),
+                Constraint::Max(3),
+                Constraint::Max(3),
+            ])
+            .areas(center);
+
+            Paragraph::new(EXPLANATION)
+                .wrap(Wrap { trim: true })
+                .block(Block::bordered().title(format!("Import the key of {}", self.room)))
+                .render(explanation, buf);
+            self.words.render(a, buf);
+            self.join.draw_button(b, buf);
+            self.cancel.draw_button(c, buf);
+        }
+        Ok(())
+    }
+}
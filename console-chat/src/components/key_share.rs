@@ -0,0 +1,103 @@
+use super::Component;
+use crate::action::Action;
+use crate::action::Result;
+use crate::qr::QrCode;
+use crate::secret::Secret;
+use ratatui::{prelude::*, widgets::*};
+
+/// Words per line, so they can be read out in groups.
+const WORDS_PER_LINE: usize = 6;
+
+/// Shows the current room key as a QR code and as words, to hand it over in person.
+///
+/// The key is only kept while the screen is open.
+#[derive(Debug, Default)]
+pub struct KeyShare {
+    active: bool,
+    room: String,
+    words: Secret,
+    code: Secret,
+}
+
+impl KeyShare {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Component for KeyShare {
+    fn hide(&mut self) {
+        self.active = false;
+        self.words = Secret::default();
+        self.code = Secret::default();
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        if let Action::SharedKey(room, words, code) = action {
+            self.active = true;
+            self.room = room;
+            self.words = words;
+            self.code = code;
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            Block::new().bg(Color::Blue).render(area, buf);
+
+            let qr = QrCode::encode(self.code.expose().as_bytes())
+                .map(|code| code.to_half_blocks())
+                .unwrap_or_default();
+            let words: Vec<&str> = self.words.expose().split(' ').collect();
+            let mut lines: Vec<Line> = qr
+                .iter()
+                .map(|line| Line::from(line.as_str()).white().on_black())
+                .collect();
+            lines.push(Line::from(""));
+            lines.extend(
+                words
+                    .chunks(WORDS_PER_LINE)
+                    .map(|chunk| Line::from(chunk.join(" ")).bold()),
+            );
+            lines.push(Line::from(""));
+            lines.push(
+                Line::from("Anyone who sees this can read the room, only show it to members.")
+                    .yellow(),
+            );
+
+            let width = qr
+                .first()
+                .map(|line| line.chars().count())
+                .unwrap_or_default()
+                .max(WORDS_PER_LINE * 9)
+                + 4;
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Length(width as u16),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+            let [_, center, _] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Length(lines.len() as u16 + 2),
+                Constraint::Fill(1),
+            ])
+            .areas(center);
+
+            Clear.render(center, buf);
+            Paragraph::new(lines)
+                .alignment(Alignment::Center)
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .title(format!(" Key of {} ", self.room))
+                        .title_bottom(" <q> back "),
+                )
+                .on_dark_gray()
+                .render(center, buf);
+        }
+        Ok(())
+    }
+}
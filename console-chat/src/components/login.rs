@@ -2,6 +2,7 @@ use crate::LockErrorExt;
 use crate::action::AppError;
 use crate::action::Result;
 use crate::components::{button::*, theme::*, vim::*};
+use crate::secret::Secret;
 use crossterm::event::{KeyCode, KeyEvent};
 use ratatui::{prelude::*, widgets::*};
 use std::sync::{Arc, RwLock};
@@ -164,8 +165,9 @@ impl Component for Login<'_> {
                             self.down();
                             this_vim
                         }
-                        Transition::Enter(content) => {
-                            debug!("{}", content);
+                        Transition::Enter(_) => {
+                            // not the content, it may be the password
+                            debug!("Confirmed input {}", i);
                             self.down();
                             this_vim.update_mode(VimMode::Normal)
                         }
@@ -176,7 +178,7 @@ impl Component for Login<'_> {
                         if self.index == 2 {
                             self.login.set_state(ButtonState::Active);
                             let username = self.username.lines()[0].clone().trim().to_owned();
-                            let password = self.password.lines()[0].clone().trim().to_owned();
+                            let password = Secret::new(self.password.lines()[0].trim().to_owned());
                             let login_action = match (username.is_empty(), password.is_empty()) {
                                 (true, true) => Action::Error(AppError::MissingPasswordAndUsername),
                                 (false, true) => Action::Error(AppError::MissingPassword),
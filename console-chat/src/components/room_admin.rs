@@ -0,0 +1,468 @@
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::{button::*, theme::*, vim::*};
+use crate::network::USER_TYPE;
+use crossterm::event::{KeyCode, KeyEvent};
+use openapi::models::{
+    CreateRoom, CreateRoomInviteInner, RoomLevel, StaticRoomPublic, UpdateRoom, UserType,
+};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::debug;
+use tui_textarea::TextArea;
+
+use super::Component;
+use crate::{action::Action, config::Config};
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::RoomAdmin;
+
+const LEVELS: [RoomLevel; 4] = [
+    RoomLevel::Free,
+    RoomLevel::Key,
+    RoomLevel::InviteOnly,
+    RoomLevel::InviteAndKey,
+];
+
+const GUEST_EXPLANATION: &str = "Creating and managing rooms needs a permanent account.\n\n\
+Guest accounts are temporary, rooms owned by them would disappear together with the guest. \
+Log in with a registered account from the home screen to create rooms.";
+
+/// Creates a static room, or edits and deletes one owned by the current user.
+#[derive(Default)]
+pub struct RoomAdmin<'a> {
+    active: bool,
+    command_tx: Option<UnboundedSender<Action>>,
+    config: Arc<RwLock<Config>>,
+    /// The room being edited, `None` while creating a new one.
+    editing: Option<StaticRoomPublic>,
+    guest: bool,
+    confirm_delete: bool,
+    name: TextArea<'a>,
+    level: usize, // index into LEVELS
+    key: TextArea<'a>,
+    invite: TextArea<'a>,
+    save: Button,
+    delete: Button,
+    cancel: Button,
+    vim: [Option<Vim>; 3],
+    index: usize,
+    size: Size,
+}
+
+impl RoomAdmin<'_> {
+    pub const MAX_ELEMENTS: usize = 7;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn reset(&mut self) -> Result<()> {
+        let size = self.size;
+        let config = self.config.clone();
+        let command_tx = self.command_tx.take();
+        *self = Self::default();
+        self.config = config;
+        self.command_tx = command_tx;
+        self.init(size)
+    }
+
+    fn open(&mut self, room: Option<StaticRoomPublic>) -> Result<()> {
+        self.reset()?;
+        self.active = true;
+        self.guest = USER_TYPE
+            .read()
+            .map(|user_type| *user_type != Some(UserType::Permanent))
+            .unwrap_or(true);
+        if let Some(room) = room {
+            self.name = TextArea::from([room.name.clone()]);
+            self.name.set_cursor_line_style(Style::default());
+            self.name.set_style(Style::default().fg(Color::LightGreen));
+            self.level = LEVELS.iter().position(|l| *l == room.level).unwrap_or(0);
+            self.editing = Some(room);
+            self.index = 1;
+        }
+        self.update_elements();
+        Ok(())
+    }
+
+    /// The name can't change once the room exists, and there is nothing to delete before.
+    fn enabled(&self, index: usize) -> bool {
+        match index {
+            0 => self.editing.is_none(),
+            5 => self.editing.is_some(),
+            _ => true,
+        }
+    }
+
+    fn up(&mut self) {
+        loop {
+            self.index = if self.index == 0 {
+                Self::MAX_ELEMENTS - 1
+            } else {
+                self.index - 1
+            };
+            if self.enabled(self.index) {
+                break;
+            }
+        }
+        self.update_elements();
+    }
+
+    fn down(&mut self) {
+        loop {
+            self.index = (self.index + 1) % Self::MAX_ELEMENTS;
+            if self.enabled(self.index) {
+                break;
+            }
+        }
+        self.update_elements();
+    }
+
+    fn update_elements(&mut self) {
+        for button in [&mut self.save, &mut self.delete, &mut self.cancel] {
+            button.set_state(ButtonState::Normal);
+        }
+        let name_title = if self.editing.is_some() {
+            "Room (fixed)"
+        } else {
+            "Room"
+        };
+        // the server gets a key derived from it, which can be used to guess a weak one
+        let key_title = if self.editing.is_some() {
+            "New Key (empty keeps the current one, the server can test guesses of it)"
+        } else {
+            "Key (the server can test guesses of it, choose a strong one)"
+        };
+        self.name
+            .set_block(Block::default().borders(Borders::ALL).title(name_title));
+        self.key
+            .set_block(Block::default().borders(Borders::ALL).title(key_title));
+        let members = self
+            .editing
+            .iter()
+            .flat_map(|room| room.users.iter())
+            .filter_map(|user| user.username.as_deref())
+            .collect::<Vec<_>>()
+            .join(", ");
+        self.invite.set_block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title("Invite (usernames or ids, comma separated)")
+                .title_bottom(if members.is_empty() {
+                    String::new()
+                } else {
+                    format!("members: {members}")
+                }),
+        );
+        match self.index {
+            0 => self.name.set_block(VimMode::Normal.highlight_block()),
+            2 => self.key.set_block(VimMode::Normal.highlight_block()),
+            3 => self.invite.set_block(VimMode::Normal.highlight_block()),
+            4 => self.save.set_state(ButtonState::Selected),
+            5 => self.delete.set_state(ButtonState::Selected),
+            6 => self.cancel.set_state(ButtonState::Selected),
+            _ => {}
+        }
+    }
+
+    fn invites(&self) -> Option<Vec<CreateRoomInviteInner>> {
+        let invites: Vec<_> = self.invite.lines()[0]
+            .split(',')
+            .map(str::trim)
+            .filter(|user| !user.is_empty())
+            .map(CreateRoomInviteInner::new)
+            .collect();
+        (!invites.is_empty()).then_some(invites)
+    }
+
+    fn submit(&mut self) -> Result<Option<Action>> {
+        let key = self.key.lines()[0].trim().to_owned();
+        let key = (!key.is_empty()).then_some(key);
+        let level = LEVELS[self.level];
+        if matches!(level, RoomLevel::Key | RoomLevel::InviteAndKey)
+            && key.is_none()
+            && self.editing.is_none()
+        {
+            return Ok(Some(Action::Error(
+                format!("A {level} room needs a key").into(),
+            )));
+        }
+        let action = match &self.editing {
+            Some(room) => Action::UpdateRoom(
+                room.name.clone(),
+                UpdateRoom {
+                    private_level: Some(Some(level)),
+                    invite: self.invites().map(Some),
+                    key: key.map(Some),
+                },
+            ),
+            None => {
+                let name = self.name.lines()[0].trim().to_owned();
+                if name.is_empty() {
+                    return Ok(Some(Action::Error("The room needs a name".into())));
+                }
+                Action::CreateRoom(
+                    name,
+                    CreateRoom {
+                        private_level: level,
+                        invite: self.invites().map(Some),
+                        key: key.map(Some),
+                    },
+                )
+            }
+        };
+        self.hide();
+        Ok(Some(action))
+    }
+}
+
+impl<'a> RoomAdmin<'a> {
+    fn get_selected_input(&mut self) -> Option<(&mut TextArea<'a>, Vim, usize)> {
+        let (textarea, i) = match self.index {
+            0 if self.editing.is_none() => (&mut self.name, 0),
+            2 => (&mut self.key, 1),
+            3 => (&mut self.invite, 2),
+            _ => return None,
+        };
+        Some((textarea, self.vim[i].take().unwrap_or_default(), i))
+    }
+}
+
+impl Component for RoomAdmin<'_> {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+    fn init(&mut self, size: Size) -> Result<()> {
+        self.size = size;
+        {
+            let mut config = self.config.write().error()?;
+            let theme = match config.themes.get(&STYLE_KEY) {
+                Some(themes) => themes,
+                None => match config.themes.get(&crate::app::Mode::Global) {
+                    Some(themes) => themes,
+                    None => {
+                        config
+                            .themes
+                            .insert(crate::app::Mode::Global, Theme::default());
+                        config
+                            .themes
+                            .get(&crate::app::Mode::Global)
+                            .ok_or("This is bad")?
+                    }
+                },
+            };
+            self.vim = [
+                Some(Vim::default()),
+                Some(Vim::default()),
+                Some(Vim::default()),
+            ];
+            for textarea in [&mut self.name, &mut self.key, &mut self.invite] {
+                textarea.set_cursor_line_style(Style::default());
+                textarea.set_style(Style::default().fg(Color::LightGreen));
+            }
+            self.key.set_mask_char('\u{2022}');
+
+            self.save = Button::new("Save", "", theme.buttons.accepting, Action::Render);
+            self.delete = Button::new("Delete", "", theme.buttons.denying, Action::Render);
+            self.cancel = Button::new("Abort", "<q>", theme.buttons.normal, Action::OpenRooms);
+        }
+        self.update_elements();
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        if !self.active || self.guest {
+            return Ok(None);
+        }
+        if self.confirm_delete {
+            self.confirm_delete = false;
+            if let (KeyCode::Char('y'), Some(room)) = (key.code, self.editing.as_ref()) {
+                let action = Action::DeleteRoom(room.name.clone());
+                self.hide();
+                return Ok(Some(action));
+            }
+            return Ok(None);
+        }
+        match self.get_selected_input() {
+            Some((textinput, this_vim, i)) => {
+                self.vim[i] = Some(match this_vim.transition(key.into(), textinput) {
+                    Transition::Mode(mode) if this_vim.mode != mode => {
+                        textinput.set_block(mode.highlight_block());
+                        textinput.set_cursor_style(mode.cursor_style(this_vim.style));
+                        if let Some(command_tx) = self.command_tx.as_ref() {
+                            match mode {
+                                VimMode::Insert => command_tx.send(Action::Insert)?,
+                                VimMode::Normal if this_vim.mode == VimMode::Insert => {
+                                    command_tx.send(Action::Normal)?
+                                }
+                                _ => {}
+                            };
+                        }
+                        this_vim.update_mode(mode)
+                    }
+                    Transition::Nop | Transition::Mode(_) | Transition::Store => this_vim,
+                    Transition::Pending(input) => this_vim.with_pending(input),
+                    Transition::Up => {
+                        self.up();
+                        this_vim
+                    }
+                    Transition::Down => {
+                        self.down();
+                        this_vim
+                    }
+                    Transition::Enter(content) => {
+                        debug!("{}", content);
+                        self.down();
+                        this_vim.update_mode(VimMode::Normal)
+                    }
+                });
+            }
+            None => match key.code {
+                KeyCode::Char('h') | KeyCode::Left if self.index == 1 => {
+                    self.level = (self.level + LEVELS.len() - 1) % LEVELS.len();
+                }
+                KeyCode::Char('l') | KeyCode::Right | KeyCode::Enter if self.index == 1 => {
+                    self.level = (self.level + 1) % LEVELS.len();
+                }
+                KeyCode::Enter => match self.index {
+                    4 => {
+                        self.save.set_state(ButtonState::Active);
+                        return self.submit();
+                    }
+                    5 => {
+                        self.delete.set_state(ButtonState::Active);
+                        self.confirm_delete = true;
+                    }
+                    6 => {
+                        self.cancel.set_state(ButtonState::Active);
+                        return Ok(self.cancel.trigger());
+                    }
+                    _ => {}
+                },
+                KeyCode::Char('k') => self.up(),
+                KeyCode::Char('j') => self.down(),
+                _ => {}
+            },
+        }
+        Ok(None)
+    }
+
+    fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<()> {
+        self.command_tx = Some(tx);
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenRoomAdmin => self.open(None)?,
+            Action::EditRoom(room) => self.open(Some(room))?,
+            Action::Tick => {
+                for button in [&mut self.save, &mut self.delete, &mut self.cancel] {
+                    if button.is_active() {
+                        button.set_state(ButtonState::Selected);
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            let block = Block::new().bg(Color::Blue);
+            block.render(area, buf);
+
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Percentage(40),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+
+            if self.guest {
+                let [_, center, _] = Layout::vertical([
+                    Constraint::Fill(1),
+                    Constraint::Length(9),
+                    Constraint::Fill(1),
+                ])
+                .areas(center);
+                Clear.render(center, buf);
+                Paragraph::new(GUEST_EXPLANATION)
+                    .wrap(Wrap { trim: true })
+                    .block(
+                        Block::bordered()
+                            .title("Room administration unavailable")
+                            .title_bottom("<q> back"),
+                    )
+                    .on_dark_gray()
+                    .render(center, buf);
+                return Ok(());
+            }
+
+            let [_, center, _] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Max(3 * 7),
+                Constraint::Fill(1),
+            ])
+            .areas(center);
+
+            Clear.render(center, buf);
+            let block = Block::new().bg(Color::DarkGray);
+            block.render(center, buf);
+
+            let [name, level, key, invite, save, delete, cancel] =
+                Layout::vertical([Constraint::Max(3); 7]).areas(center);
+
+            self.name.render(name, buf);
+            let level_block = if self.index == 1 {
+                VimMode::Normal.highlight_block()
+            } else {
+                Block::default().borders(Borders::ALL)
+            };
+            Paragraph::new(format!("◀ {} ▶", LEVELS[self.level]))
+                .centered()
+                .block(level_block.title("Level <h/l>"))
+                .render(level, buf);
+            self.key.render(key, buf);
+            self.invite.render(invite, buf);
+
+            self.save.draw_button(save, buf);
+            if self.editing.is_some() {
+                self.delete.draw_button(delete, buf);
+            }
+            self.cancel.draw_button(cancel, buf);
+
+            if let (true, Some(room)) = (self.confirm_delete, self.editing.as_ref()) {
+                let [_, popup, _] = Layout::vertical([
+                    Constraint::Fill(1),
+                    Constraint::Length(5),
+                    Constraint::Fill(1),
+                ])
+                .areas(center);
+                Clear.render(popup, buf);
+                Paragraph::new(format!(
+                    "Delete {} for everyone? This can't be undone.",
+                    room.name
+                ))
+                .centered()
+                .wrap(Wrap { trim: true })
+                .block(
+                    Block::bordered()
+                        .title("Confirm")
+                        .title_bottom("<y> delete, any other key keeps it"),
+                )
+                .on_red()
+                .render(popup, buf);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,329 @@
+use super::Component;
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::theme::*;
+use crate::network::{RoomAddress, USERNAME};
+use crate::{action::Action, config::Config};
+use crossterm::event::{KeyCode, KeyEvent};
+use openapi::models::{RoomLevel, StaticRoomPublic};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+use strum::Display;
+use tokio::sync::mpsc::UnboundedSender;
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::Rooms;
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Display)]
+enum SortBy {
+    #[default]
+    Name,
+    Owner,
+    Members,
+    Level,
+}
+
+impl SortBy {
+    const fn next(self) -> Self {
+        match self {
+            Self::Name => Self::Owner,
+            Self::Owner => Self::Members,
+            Self::Members => Self::Level,
+            Self::Level => Self::Name,
+        }
+    }
+}
+
+struct RoomEntry {
+    room: StaticRoomPublic,
+    /// Owned by or shared with the current user.
+    mine: bool,
+}
+
+impl RoomEntry {
+    fn owner(&self) -> &str {
+        self.room.owner.username.as_deref().unwrap_or("guest")
+    }
+}
+
+/// Lists public rooms and the rooms of the current user.
+#[derive(Default)]
+pub struct RoomBrowser {
+    active: bool,
+    command_tx: Option<UnboundedSender<Action>>,
+    config: Arc<RwLock<Config>>,
+    page: PageColors,
+    rooms: Vec<RoomEntry>,
+    /// Indices into `rooms` that pass the filter, in display order.
+    visible: Vec<usize>,
+    table: TableState,
+    filter: String,
+    filtering: bool,
+    only_mine: bool,
+    sort_by: SortBy,
+    descending: bool,
+}
+
+impl RoomBrowser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_rooms(&mut self, public: Vec<StaticRoomPublic>, mine: Vec<StaticRoomPublic>) {
+        let me = USERNAME.read().ok().and_then(|me| me.clone());
+        let is_member = |room: &StaticRoomPublic| {
+            me.is_some()
+                && (room.owner.username == me || room.users.iter().any(|u| u.username == me))
+        };
+        let mut rooms: Vec<RoomEntry> = mine
+            .into_iter()
+            .map(|room| RoomEntry { room, mine: true })
+            .collect();
+        for room in public {
+            if !rooms.iter().any(|entry| entry.room.id == room.id) {
+                let mine = is_member(&room);
+                rooms.push(RoomEntry { room, mine });
+            }
+        }
+        self.rooms = rooms;
+        self.update_visible();
+    }
+
+    fn update_visible(&mut self) {
+        let filter = self.filter.to_lowercase();
+        let mut visible: Vec<usize> = self
+            .rooms
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !self.only_mine || entry.mine)
+            .filter(|(_, entry)| {
+                filter.is_empty()
+                    || entry.room.name.to_lowercase().contains(&filter)
+                    || entry.owner().to_lowercase().contains(&filter)
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let rooms = &self.rooms;
+        visible.sort_by(|&a, &b| {
+            let (a, b) = (&rooms[a], &rooms[b]);
+            let order = match self.sort_by {
+                SortBy::Name => a.room.name.cmp(&b.room.name),
+                SortBy::Owner => a.owner().cmp(b.owner()),
+                SortBy::Members => a.room.users.len().cmp(&b.room.users.len()),
+                SortBy::Level => a.room.level.cmp(&b.room.level),
+            };
+            if self.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+        self.visible = visible;
+        let selected = self
+            .table
+            .selected()
+            .unwrap_or_default()
+            .min(self.visible.len().saturating_sub(1));
+        self.table
+            .select((!self.visible.is_empty()).then_some(selected));
+    }
+
+    fn selected(&self) -> Option<&RoomEntry> {
+        let i = *self.visible.get(self.table.selected()?)?;
+        self.rooms.get(i)
+    }
+
+    /// What Enter does for the selected room, depending on its level.
+    ///
+    /// Invites aren't listed, whether an invite only room lets the user in is up to the server.
+    fn open(&self) -> Option<Action> {
+        let entry = self.selected()?;
+        let name = RoomAddress::new_static(&entry.room.name).to_string();
+        Some(match entry.room.level {
+            RoomLevel::Free | RoomLevel::InviteOnly => Action::PerformJoin(name),
+            RoomLevel::Key | RoomLevel::InviteAndKey => Action::PromptJoin(name),
+        })
+    }
+
+    fn edit(&self) -> Option<Action> {
+        let entry = self.selected()?;
+        let me = USERNAME.read().ok().and_then(|me| me.clone());
+        Some(if me.is_some() && entry.room.owner.username == me {
+            Action::EditRoom(entry.room.clone())
+        } else {
+            Action::Error(format!("Only {} can edit {}", entry.owner(), entry.room.name).into())
+        })
+    }
+
+    fn send(&self, action: Action) -> Result<()> {
+        if let Some(command_tx) = self.command_tx.as_ref() {
+            command_tx.send(action)?;
+        }
+        Ok(())
+    }
+}
+
+impl Component for RoomBrowser {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+
+    fn init(&mut self, _: Size) -> Result<()> {
+        let mut config = self.config.write().error()?;
+        let theme = match config.themes.get(&STYLE_KEY) {
+            Some(themes) => themes,
+            None => match config.themes.get(&crate::app::Mode::Global) {
+                Some(themes) => themes,
+                None => {
+                    config
+                        .themes
+                        .insert(crate::app::Mode::Global, Theme::default());
+                    config
+                        .themes
+                        .get(&crate::app::Mode::Global)
+                        .ok_or("This is bad")?
+                }
+            },
+        };
+        self.page = theme.page;
+        Ok(())
+    }
+
+    fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<()> {
+        self.command_tx = Some(tx);
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        if !self.active {
+            return Ok(None);
+        }
+        if self.filtering {
+            match key.code {
+                KeyCode::Char(c) => self.filter.push(c),
+                KeyCode::Backspace => {
+                    self.filter.pop();
+                }
+                KeyCode::Enter | KeyCode::Esc => {
+                    self.filtering = false;
+                    self.send(Action::Normal)?;
+                }
+                _ => {}
+            }
+            self.update_visible();
+            return Ok(None);
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.table.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.table.select_previous(),
+            KeyCode::Char('/') => {
+                self.filtering = true;
+                self.send(Action::Insert)?;
+            }
+            KeyCode::Char('s') => {
+                self.sort_by = self.sort_by.next();
+                self.update_visible();
+            }
+            KeyCode::Char('r') => {
+                self.descending = !self.descending;
+                self.update_visible();
+            }
+            KeyCode::Tab => {
+                self.only_mine = !self.only_mine;
+                self.update_visible();
+            }
+            KeyCode::Enter => return Ok(self.open()),
+            KeyCode::Char('n') => return Ok(Some(Action::OpenRoomAdmin)),
+            KeyCode::Char('e') => return Ok(self.edit()),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenRooms => {
+                self.active = true;
+                return Ok(Some(Action::RefreshRooms));
+            }
+            Action::RoomList(public, mine) => self.set_rooms(public, mine),
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            let block = Block::new().bg(Color::Blue);
+            block.render(area, buf);
+
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Percentage(70),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+            let [table_area, filter_area] =
+                Layout::vertical([Constraint::Fill(1), Constraint::Length(3)]).areas(center);
+
+            let header = Row::new(["", "Room", "Owner", "Members", "Level"]).bold();
+            let rows = self.visible.iter().map(|&i| {
+                let entry = &self.rooms[i];
+                Row::new([
+                    if entry.mine { "★" } else { "" }.to_owned(),
+                    entry.room.name.clone(),
+                    entry.owner().to_owned(),
+                    entry.room.users.len().to_string(),
+                    entry.room.level.to_string(),
+                ])
+            });
+            let scope = if self.only_mine {
+                "My Rooms"
+            } else {
+                "All Rooms"
+            };
+            let order = if self.descending { "↓" } else { "↑" };
+            let table = Table::new(
+                rows,
+                [
+                    Constraint::Length(1),
+                    Constraint::Fill(2),
+                    Constraint::Fill(1),
+                    Constraint::Length(7),
+                    Constraint::Length(14),
+                ],
+            )
+            .header(header)
+            .row_highlight_style(Style::new().reversed())
+            .block(
+                Block::bordered()
+                    .title(format!("{scope} (sorted by {} {order})", self.sort_by))
+                    .title_bottom(
+                        "<Tab> mine/all  </> filter  <s> sort  <r> reverse  <n> new  <e> edit",
+                    ),
+            )
+            .style(
+                Style::new()
+                    .bg(self.page.background)
+                    .fg(self.page.foreground),
+            );
+            StatefulWidget::render(table, table_area, buf, &mut self.table);
+
+            let filter_style = if self.filtering {
+                Style::new().fg(Color::LightGreen)
+            } else {
+                Style::new().fg(self.page.muted)
+            };
+            Paragraph::new(self.filter.as_str())
+                .block(Block::bordered().title("Filter"))
+                .style(filter_style.bg(self.page.background))
+                .render(filter_area, buf);
+        }
+        Ok(())
+    }
+}
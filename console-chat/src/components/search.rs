@@ -0,0 +1,337 @@
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::chat::highlighted;
+use crate::components::{theme::*, vim::*};
+use crate::network::Message;
+use crate::network::search::{self, Hit, Query};
+use chrono::Local;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tui_textarea::TextArea;
+
+use super::Component;
+use crate::{action::Action, config::Config};
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::Search;
+
+/// Searches the chat and the local history, of one room or of every room.
+#[derive(Default)]
+pub struct Search<'a> {
+    active: bool,
+    command_tx: Option<UnboundedSender<Action>>,
+    config: Arc<RwLock<Config>>,
+    /// The room searched from the chat, `None` searches every room.
+    room: Option<String>,
+    input: TextArea<'a>,
+    vim: Option<Vim>,
+    /// 0 is the query, 1 the hits.
+    index: usize,
+    /// The query the hits are for.
+    query: Option<Query>,
+    hits: Vec<Hit>,
+    list: ListState,
+    error: Option<String>,
+}
+
+impl Search<'_> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn title(&self) -> String {
+        match &self.room {
+            Some(room) => format!("Search {room}"),
+            None => "Search every room".to_owned(),
+        }
+    }
+
+    fn reset_input(&mut self) {
+        self.input = TextArea::default();
+        self.input.set_cursor_line_style(Style::default());
+        self.input.set_placeholder_text(search::SYNTAX);
+        self.update_elements();
+    }
+
+    fn update_elements(&mut self) {
+        let mode = self.vim.as_ref().map_or(VimMode::Normal, |vim| vim.mode);
+        let block = match self.index {
+            0 => mode.highlight_block(),
+            _ => Block::default().borders(Borders::ALL),
+        };
+        self.input.set_block(block.title(self.title()));
+    }
+
+    /// Runs the typed query, a broken one is shown instead.
+    fn run(&mut self, input: &str) -> Option<Action> {
+        let query = Query::parse(input).and_then(|mut query| {
+            query.room = self.room.clone();
+            query.matcher().map(|_| query)
+        });
+        match query {
+            Ok(query) => {
+                self.error = None;
+                self.hits.clear();
+                self.list.select(None);
+                self.query = Some(query.clone());
+                self.index = 1;
+                self.update_elements();
+                Some(Action::Search(query))
+            }
+            Err(e) => {
+                self.error = Some(e.to_string());
+                None
+            }
+        }
+    }
+
+    fn selected(&self) -> Option<&Hit> {
+        self.hits.get(self.list.selected()?)
+    }
+}
+
+/// Time, sender and content of a message on one line.
+fn message_line(message: &Message, matches: &[std::ops::Range<usize>]) -> Line<'static> {
+    let time = message
+        .send_at
+        .map(|send_at| {
+            send_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M ")
+                .to_string()
+        })
+        .unwrap_or_default();
+    let sender = message
+        .user
+        .as_ref()
+        .and_then(|user| user.username.clone())
+        .unwrap_or("System".to_owned());
+    let mut line = Line::from(vec![
+        Span::from(time).gray(),
+        Span::from(format!("{sender}: ")).bold(),
+    ]);
+    line.spans
+        .extend(highlighted(&message.content, matches).spans);
+    line
+}
+
+impl Component for Search<'_> {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+
+    fn init(&mut self, _: Size) -> Result<()> {
+        let mut config = self.config.write().error()?;
+        let theme = match config.themes.get(&STYLE_KEY) {
+            Some(themes) => themes,
+            None => match config.themes.get(&crate::app::Mode::Global) {
+                Some(themes) => themes,
+                None => {
+                    config
+                        .themes
+                        .insert(crate::app::Mode::Global, Theme::default());
+                    config
+                        .themes
+                        .get(&crate::app::Mode::Global)
+                        .ok_or("This is bad")?
+                }
+            },
+        };
+        let vim = Vim::new(VimMode::Normal, VimType::SingleLine, theme.vi);
+        self.input.set_cursor_style(vim.mode.cursor_style(theme.vi));
+        self.vim = Some(vim);
+        drop(config);
+        self.reset_input();
+        Ok(())
+    }
+
+    fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<()> {
+        self.command_tx = Some(tx);
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        let Some(command_tx) = self.command_tx.clone().filter(|_| self.active) else {
+            return Ok(None);
+        };
+        if self.index == 0 {
+            let this_vim = self.vim.take().unwrap_or_default();
+            self.vim = Some(match this_vim.transition(key.into(), &mut self.input) {
+                Transition::Mode(mode) if this_vim.mode != mode => {
+                    self.input
+                        .set_block(mode.highlight_block().title(self.title()));
+                    self.input
+                        .set_cursor_style(mode.cursor_style(this_vim.style));
+                    match mode {
+                        VimMode::Insert => command_tx.send(Action::Insert)?,
+                        VimMode::Normal if this_vim.mode == VimMode::Insert => {
+                            command_tx.send(Action::Normal)?
+                        }
+                        _ => {}
+                    };
+                    this_vim.update_mode(mode)
+                }
+                Transition::Store | Transition::Nop | Transition::Mode(_) => this_vim,
+                Transition::Pending(input) => this_vim.with_pending(input),
+                Transition::Up => this_vim,
+                Transition::Down => {
+                    if !self.hits.is_empty() {
+                        self.index = 1;
+                        self.list.select(Some(0));
+                    }
+                    this_vim
+                }
+                Transition::Enter(content) => {
+                    // the hits are browsed with j and k, the keymap of this screen is back
+                    command_tx.send(Action::Normal)?;
+                    self.input
+                        .set_cursor_style(VimMode::Normal.cursor_style(this_vim.style));
+                    self.vim = Some(this_vim.update_mode(VimMode::Normal));
+                    let action = self.run(&content);
+                    self.update_elements();
+                    return Ok(action);
+                }
+            });
+            self.update_elements();
+        } else {
+            match key.code {
+                KeyCode::Char('j') if !self.hits.is_empty() => {
+                    let next = self.list.selected().map_or(0, |i| i + 1);
+                    self.list.select(Some(next.min(self.hits.len() - 1)));
+                }
+                KeyCode::Char('k') => match self.list.selected() {
+                    Some(0) | None => {
+                        self.index = 0;
+                        self.list.select(None);
+                        self.update_elements();
+                    }
+                    Some(i) => self.list.select(Some(i - 1)),
+                },
+                KeyCode::Char('/') | KeyCode::Char('i') => {
+                    self.index = 0;
+                    self.update_elements();
+                }
+                KeyCode::Enter => {
+                    if let Some(hit) = self.selected() {
+                        return Ok(Some(Action::JumpTo(hit.clone())));
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenSearch(room) => {
+                self.active = true;
+                if self.room != room {
+                    self.room = room;
+                    self.query = None;
+                    self.hits.clear();
+                    self.list.select(None);
+                    self.error = None;
+                    self.index = 0;
+                    self.reset_input();
+                }
+            }
+            Action::SearchResults(query, hits) if self.query.as_ref() == Some(&query) => {
+                search::merge(&mut self.hits, hits);
+                if self.index == 1 && self.list.selected().is_none() && !self.hits.is_empty() {
+                    self.list.select(Some(0));
+                }
+            }
+            Action::CloseSearch if self.active => {
+                return Ok(Some(match self.room {
+                    Some(_) => Action::OpenChat,
+                    None => Action::OpenHome,
+                }));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            Block::new().bg(Color::Blue).render(area, buf);
+
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Percentage(80),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+            let [_, center, _] = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Percentage(80),
+                Constraint::Fill(1),
+            ])
+            .areas(center);
+            Clear.render(center, buf);
+            Block::new().bg(Color::DarkGray).render(center, buf);
+
+            let [input_area, body] =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(center);
+            let [hits_area, context_area] =
+                Layout::horizontal([Constraint::Percentage(55), Constraint::Fill(1)]).areas(body);
+            self.input.render(input_area, buf);
+
+            let title = match (&self.error, &self.query) {
+                (Some(e), _) => Line::from(format!(" {e} ")).red(),
+                (None, Some(_)) => Line::from(format!(" {} found ", self.hits.len())),
+                (None, None) => Line::from(" <Enter> to search "),
+            };
+            let items: Vec<ListItem> = self
+                .hits
+                .iter()
+                .map(|hit| {
+                    let mut line = message_line(&hit.message, &hit.matches);
+                    if self.room.is_none() {
+                        line.spans
+                            .insert(0, Span::from(format!("[{}] ", hit.room)).cyan());
+                    }
+                    ListItem::new(line)
+                })
+                .collect();
+            StatefulWidget::render(
+                List::new(items)
+                    .block(
+                        Block::bordered()
+                            .title(title)
+                            .title_bottom(" <Enter> jump  </> edit query  <q> back "),
+                    )
+                    .highlight_style(Style::new().reversed()),
+                hits_area,
+                buf,
+                &mut self.list,
+            );
+
+            let context: Vec<Line> = self
+                .selected()
+                .map(|hit| {
+                    let around = |message: &Message| message_line(message, &[]).dark_gray();
+                    hit.before
+                        .iter()
+                        .map(around)
+                        .chain([message_line(&hit.message, &hit.matches)])
+                        .chain(hit.after.iter().map(around))
+                        .collect()
+                })
+                .unwrap_or_default();
+            Paragraph::new(context)
+                .wrap(Wrap { trim: false })
+                .block(Block::bordered().title(" Context "))
+                .render(context_area, buf);
+        }
+        Ok(())
+    }
+}
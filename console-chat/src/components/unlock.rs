@@ -0,0 +1,272 @@
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::{button::*, theme::*, vim::*};
+use crate::network::keystore::Keystore;
+use crate::secret::Secret;
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+use tokio::sync::mpsc::UnboundedSender;
+use tracing::trace;
+use tui_textarea::TextArea;
+
+use super::Component;
+use crate::{action::Action, action::AppError, config::Config};
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::Unlock;
+
+const CREATE_EXPLANATION: &str = "Keys are kept in memory only and lost on exit. \
+Choose a passphrase to keep your identity and room keys in an encrypted keystore, \
+or skip to continue without one.";
+
+/// Asks for the keystore passphrase at startup, or for a new one if there is no keystore yet.
+#[derive(Default, Debug)]
+pub struct Unlock<'a> {
+    active: bool,
+    command_tx: Option<UnboundedSender<Action>>,
+    config: Arc<RwLock<Config>>,
+    /// A keystore exists and needs to be unlocked, otherwise one is created.
+    exists: bool,
+    passphrase: TextArea<'a>,
+    unlock: Button,
+    skip: Button,
+    vim: Option<Vim>,
+    index: usize,
+}
+
+impl Unlock<'_> {
+    pub const MAX_ELEMENTS: usize = 3;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn reset_passphrase(&mut self) {
+        self.passphrase = TextArea::default();
+        self.passphrase.set_cursor_line_style(Style::default());
+        self.passphrase
+            .set_style(Style::default().fg(Color::LightGreen));
+        self.passphrase.set_mask_char('\u{2022}');
+        self.update_elements();
+    }
+
+    fn up(&mut self) {
+        self.index = if self.index == 0 {
+            Self::MAX_ELEMENTS - 1
+        } else {
+            self.index - 1
+        };
+        self.update_elements();
+    }
+
+    fn down(&mut self) {
+        self.index = (self.index + 1) % Self::MAX_ELEMENTS;
+        self.update_elements();
+    }
+
+    fn update_elements(&mut self) {
+        self.unlock.set_state(ButtonState::Normal);
+        self.skip.set_state(ButtonState::Normal);
+        let title = if self.exists {
+            "Keystore passphrase"
+        } else {
+            "New keystore passphrase"
+        };
+        self.passphrase
+            .set_block(Block::default().borders(Borders::ALL).title(title));
+        match self.index {
+            0 => self
+                .passphrase
+                .set_block(VimMode::Normal.highlight_block().title(title)),
+            1 => self.unlock.set_state(ButtonState::Selected),
+            2 => self.skip.set_state(ButtonState::Selected),
+            _ => self.index %= Self::MAX_ELEMENTS,
+        }
+    }
+
+    const fn get_buttons(&mut self) -> [&mut Button; 2] {
+        [&mut self.unlock, &mut self.skip]
+    }
+
+    fn send(&mut self, action: Action) -> Result<()> {
+        trace!("sending action: {action}");
+        let action_tx = self.command_tx.as_ref().ok_or(AppError::MissingActionTX)?;
+
+        Ok(action_tx.send(action)?)
+    }
+}
+
+impl<'a> Unlock<'a> {
+    fn get_selected_input(&mut self) -> Option<(&mut TextArea<'a>, Vim)> {
+        let vim = self.vim.take().unwrap_or_default();
+        match self.index {
+            0 => Some((&mut self.passphrase, vim)),
+            _ => None,
+        }
+    }
+}
+
+impl Component for Unlock<'_> {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+
+    fn init(&mut self, _: Size) -> Result<()> {
+        {
+            let mut config = self.config.write().error()?;
+            let theme = match config.themes.get(&STYLE_KEY) {
+                Some(themes) => themes,
+                None => match config.themes.get(&crate::app::Mode::Global) {
+                    Some(themes) => themes,
+                    None => {
+                        config
+                            .themes
+                            .insert(crate::app::Mode::Global, Theme::default());
+                        config
+                            .themes
+                            .get(&crate::app::Mode::Global)
+                            .ok_or("This is bad")?
+                    }
+                },
+            };
+
+            self.vim = Some(Vim::default());
+            self.unlock = Button::new("Continue", "", theme.buttons.accepting, Action::Render);
+            self.skip = Button::new("Skip", "", theme.buttons.normal, Action::OpenHome);
+        }
+        self.reset_passphrase();
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        if !self.active {
+            return Ok(None);
+        }
+        match self.get_selected_input() {
+            Some((textinput, this_vim)) => {
+                self.vim = Some(match this_vim.transition(key.into(), textinput) {
+                    Transition::Mode(mode) if this_vim.mode != mode => {
+                        textinput.set_block(mode.highlight_block());
+                        textinput.set_cursor_style(mode.cursor_style(this_vim.style));
+                        match mode {
+                            VimMode::Insert => self.send(Action::Insert)?,
+                            VimMode::Normal if this_vim.mode == VimMode::Insert => {
+                                self.send(Action::Normal)?
+                            }
+                            _ => {}
+                        };
+                        this_vim.update_mode(mode)
+                    }
+                    Transition::Nop | Transition::Mode(_) | Transition::Store => this_vim,
+                    Transition::Pending(input) => this_vim.with_pending(input),
+                    Transition::Up => {
+                        self.up();
+                        this_vim
+                    }
+                    Transition::Down => {
+                        self.down();
+                        this_vim
+                    }
+                    Transition::Enter(_) => {
+                        self.down();
+                        this_vim.update_mode(VimMode::Normal)
+                    }
+                });
+            }
+            None => match key.code {
+                KeyCode::Enter => {
+                    let i = self.index - 1;
+                    let buttons = self.get_buttons();
+                    buttons[i].set_state(ButtonState::Active);
+                    if i == 1 {
+                        return Ok(buttons[i].trigger());
+                    }
+                    let passphrase = Secret::new(self.passphrase.lines().join("\n"));
+                    self.reset_passphrase();
+                    return Ok(Some(Action::UnlockKeystore(passphrase)));
+                }
+                KeyCode::Char('k') => self.up(),
+                KeyCode::Char('j') => self.down(),
+                _ => {}
+            },
+        }
+        Ok(None)
+    }
+
+    fn register_action_handler(&mut self, tx: UnboundedSender<Action>) -> Result<()> {
+        self.command_tx = Some(tx);
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenUnlock => {
+                self.active = true;
+                self.exists = Keystore::default_path().exists();
+                self.index = 0;
+                self.update_elements();
+            }
+            Action::Tick => {
+                if self.unlock.is_active() {
+                    self.unlock.set_state(ButtonState::Selected);
+                }
+                if self.skip.is_active() {
+                    self.skip.set_state(ButtonState::Selected);
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            let block = Block::new().bg(Color::Blue);
+            block.render(area, buf);
+
+            let explanation_height = if self.exists { 0 } else { 5 };
+            let center = Layout::vertical([
+                Constraint::Fill(1),
+                Constraint::Max(3 * 3 + explanation_height),
+                Constraint::Fill(1),
+            ])
+            .split(
+                Layout::horizontal([
+                    Constraint::Fill(1),
+                    Constraint::Percentage(40),
+                    Constraint::Fill(1),
+                ])
+                .split(area)[1],
+            )[1];
+
+            Clear.render(center, buf);
+            let block = Block::new().bg(Color::DarkGray);
+            block.render(center, buf);
+
+            let [explanation, a, b, c] = Layout::vertical([
+                Constraint::Length(explanation_height),
+                Constraint::Max(3),
+                Constraint::Max(3),
+                Constraint::Max(3),
+            ])
+            .areas(center);
+
+            if !self.exists {
+                Paragraph::new(CREATE_EXPLANATION)
+                    .wrap(Wrap { trim: true })
+                    .block(Block::bordered().title("No keystore yet"))
+                    .render(explanation, buf);
+            }
+            self.passphrase.render(a, buf);
+            self.unlock.draw_button(b, buf);
+            self.skip.draw_button(c, buf);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use super::Component;
+use crate::LockErrorExt;
+use crate::action::Result;
+use crate::components::theme::*;
+use crate::network::trust::IdentityView;
+use crate::{action::Action, config::Config};
+use crossterm::event::{KeyCode, KeyEvent};
+use ratatui::{prelude::*, widgets::*};
+use std::sync::{Arc, RwLock};
+
+const STYLE_KEY: crate::app::Mode = crate::app::Mode::Verify;
+
+/// Known identities with their safety numbers, to compare with the other person out of band.
+#[derive(Default)]
+pub struct Verify {
+    active: bool,
+    config: Arc<RwLock<Config>>,
+    page: PageColors,
+    own_fingerprint: String,
+    identities: Vec<IdentityView>,
+    list: ListState,
+}
+
+impl Verify {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn set_identities(&mut self, own_fingerprint: String, identities: Vec<IdentityView>) {
+        self.own_fingerprint = own_fingerprint;
+        self.identities = identities;
+        let selected = self
+            .list
+            .selected()
+            .unwrap_or_default()
+            .min(self.identities.len().saturating_sub(1));
+        self.list
+            .select((!self.identities.is_empty()).then_some(selected));
+    }
+
+    fn selected(&self) -> Option<&IdentityView> {
+        self.identities.get(self.list.selected()?)
+    }
+
+    fn details<'a>(&'a self, identity: &'a IdentityView) -> Vec<Line<'a>> {
+        let mut lines = vec![
+            Line::from(vec![
+                Span::from("Your fingerprint    "),
+                Span::from(self.own_fingerprint.as_str()).yellow(),
+            ]),
+            Line::from(vec![
+                Span::from(format!("{:<20}", identity.username)),
+                Span::from(identity.fingerprint.as_str()).yellow(),
+            ]),
+        ];
+        if let Some(pending) = &identity.pending_fingerprint {
+            lines.push(Line::from(vec![
+                Span::from("Changed to          "),
+                Span::from(pending.as_str()).red().bold(),
+            ]));
+            lines.push(Line::from(""));
+            lines.push(
+                Line::from("The key changed, compare the numbers below before accepting it.").red(),
+            );
+        }
+        lines.push(Line::from(""));
+        lines.push(Line::from("Safety number").bold());
+        lines.push(Line::from(identity.safety_number.as_str()));
+        lines.push(Line::from(""));
+        lines.push(Line::from("Safety words").bold());
+        lines.push(Line::from(identity.safety_words.join(" ")));
+        lines.push(Line::from(""));
+        lines.push(if identity.verified {
+            Line::from("✓ verified").green()
+        } else {
+            Line::from("not verified yet").gray()
+        });
+        lines
+    }
+}
+
+impl Component for Verify {
+    fn hide(&mut self) {
+        self.active = false;
+    }
+
+    fn init(&mut self, _: Size) -> Result<()> {
+        let mut config = self.config.write().error()?;
+        let theme = match config.themes.get(&STYLE_KEY) {
+            Some(themes) => themes,
+            None => match config.themes.get(&crate::app::Mode::Global) {
+                Some(themes) => themes,
+                None => {
+                    config
+                        .themes
+                        .insert(crate::app::Mode::Global, Theme::default());
+                    config
+                        .themes
+                        .get(&crate::app::Mode::Global)
+                        .ok_or("This is bad")?
+                }
+            },
+        };
+        self.page = theme.page;
+        Ok(())
+    }
+
+    fn register_config_handler(&mut self, config: Arc<RwLock<Config>>) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn handle_key_event(&mut self, key: KeyEvent) -> Result<Option<Action>> {
+        if !self.active {
+            return Ok(None);
+        }
+        match key.code {
+            KeyCode::Char('j') | KeyCode::Down => self.list.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => self.list.select_previous(),
+            KeyCode::Char('a') => {
+                return Ok(self
+                    .selected()
+                    .map(|identity| Action::AcceptIdentity(identity.username.clone())));
+            }
+            KeyCode::Char('v') => {
+                return Ok(self
+                    .selected()
+                    .map(|identity| Action::VerifyIdentity(identity.username.clone())));
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn update(&mut self, action: Action) -> Result<Option<Action>> {
+        match action {
+            Action::OpenVerify => self.active = true,
+            Action::Identities(own_fingerprint, identities) => {
+                self.set_identities(own_fingerprint, identities)
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn draw(&mut self, frame: &mut Frame, area: Rect) -> Result<()> {
+        if self.active {
+            let buf = frame.buffer_mut();
+            let block = Block::new().bg(Color::Blue);
+            block.render(area, buf);
+
+            let [_, center, _] = Layout::horizontal([
+                Constraint::Fill(1),
+                Constraint::Percentage(80),
+                Constraint::Fill(1),
+            ])
+            .areas(area);
+            let [list_area, details_area] =
+                Layout::horizontal([Constraint::Percentage(30), Constraint::Fill(1)]).areas(center);
+            let style = Style::new()
+                .bg(self.page.background)
+                .fg(self.page.foreground);
+
+            let items = self.identities.iter().map(|identity| {
+                let marker = if identity.pending_fingerprint.is_some() {
+                    Span::from("⚠ ").red()
+                } else if identity.verified {
+                    Span::from("✓ ").green()
+                } else {
+                    Span::from("  ")
+                };
+                ListItem::new(Line::from(vec![
+                    marker,
+                    Span::from(identity.username.as_str()),
+                ]))
+            });
+            let list = List::new(items)
+                .highlight_style(Style::new().reversed())
+                .block(Block::bordered().title("Known identities"))
+                .style(style);
+            StatefulWidget::render(list, list_area, buf, &mut self.list);
+
+            let details = match self.selected() {
+                Some(identity) => self.details(identity),
+                None => vec![Line::from(
+                    "Nobody yet, identities are recorded when keys are exchanged in a room.",
+                )],
+            };
+            Paragraph::new(details)
+                .wrap(Wrap { trim: false })
+                .block(
+                    Block::bordered()
+                        .title("Verification")
+                        .title_bottom("<a> accept changed key  <v> mark verified")
+                        .padding(Padding::horizontal(1)),
+                )
+                .style(style)
+                .render(details_area, buf);
+        }
+        Ok(())
+    }
+}
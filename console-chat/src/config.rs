@@ -1,12 +1,16 @@
 #![allow(clippy::unwrap_used)]
-use color_eyre::Result;
+use color_eyre::{Result, eyre::eyre};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use derive_deref::{Deref, DerefMut};
-use directories::ProjectDirs;
+use directories::{ProjectDirs, UserDirs};
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize, Serializer, de::Deserializer};
 use std::sync::{Arc, RwLock};
-use std::{collections::HashMap, env, path::PathBuf};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
 use tracing::error;
 use url::Url;
 
@@ -55,7 +59,104 @@ pub struct NetworkConfig {
 
     #[serde(default, skip_serializing_if = "is_false")]
     pub disable_hostname_verification: bool,
+
+    #[serde(default)]
+    pub transport: RoomTransport,
+
+    /// Policy for rooms without an entry in `room_encryption`.
+    #[serde(default)]
+    pub encryption: EncryptionPolicy,
+
+    /// Per-room policies, keyed by room address (`lobby`, `static:team`).
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub room_encryption: HashMap<String, EncryptionPolicy>,
+
+    /// Ask before sending the room key to whoever requests it.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub approve_key_requests: bool,
+
+    /// Rotate the key of rooms we hold the key for every this many minutes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate_key_minutes: Option<u64>,
+
+    /// Don't rotate the room key when someone leaves.
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub keep_key_on_leave: bool,
+
+    /// Where received attachments are saved, the user's download folder if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downloads_dir: Option<PathBuf>,
+
+    /// Largest attachment that is sent or accepted, 16 MiB if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_attachment_mb: Option<u64>,
+
+    /// Days messages are kept in the local history, 30 if not set. 0 keeps no history.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history_days: Option<u32>,
+
+    /// Per-room retention in days, keyed by room address.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub room_history_days: HashMap<String, u32>,
 }
+
+impl NetworkConfig {
+    pub fn encryption_for(&self, room: &str) -> EncryptionPolicy {
+        self.room_encryption
+            .get(room)
+            .copied()
+            .unwrap_or(self.encryption)
+    }
+
+    pub fn downloads_dir(&self) -> PathBuf {
+        self.downloads_dir
+            .clone()
+            .or_else(|| UserDirs::new().and_then(|dirs| dirs.download_dir().map(Path::to_path_buf)))
+            .unwrap_or_else(|| get_data_dir().join("downloads"))
+    }
+
+    /// In bytes, an error if `max_attachment_mb` doesn't fit.
+    pub fn max_attachment_size(&self) -> Result<u64> {
+        let mb = self.max_attachment_mb.unwrap_or(16);
+        mb.checked_mul(1024 * 1024)
+            .ok_or_else(|| eyre!("max_attachment_mb {} is too large", mb))
+    }
+
+    /// How long the messages of `room` are kept in the local history, `None` keeps none.
+    pub fn history_retention(&self, room: &str) -> Option<chrono::TimeDelta> {
+        let days = self
+            .room_history_days
+            .get(room)
+            .copied()
+            .or(self.history_days)
+            .unwrap_or(30);
+        (days > 0).then(|| chrono::TimeDelta::days(days.into()))
+    }
+}
+
+/// What happens to messages sent to a room before its key is established.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum EncryptionPolicy {
+    /// Refuse to send until the room key is known.
+    RequireE2E,
+    /// Send in plaintext, but say so in the room.
+    #[default]
+    PreferE2E,
+    /// Send in plaintext without further notice.
+    PlaintextAllowed,
+}
+
+/// How a joined room talks to the server.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum RoomTransport {
+    /// Server-sent events for receiving, one POST per sent message.
+    #[default]
+    Sse,
+    /// One bidirectional WebSocket per room, needs the `websocket` feature and a server with
+    /// `/ws/{room}` routes, which the backend in this repository doesn't have.
+    WebSocket,
+}
+
 fn is_false(b: &bool) -> bool {
     !*b
 }
@@ -70,6 +171,16 @@ impl Default for NetworkConfig {
             client_cert_path: None,
             client_key_path: None,
             disable_hostname_verification: false,
+            transport: RoomTransport::default(),
+            encryption: EncryptionPolicy::default(),
+            room_encryption: HashMap::new(),
+            approve_key_requests: false,
+            rotate_key_minutes: None,
+            keep_key_on_leave: false,
+            downloads_dir: None,
+            max_attachment_mb: None,
+            history_days: None,
+            room_history_days: HashMap::new(),
         }
     }
 }
@@ -453,6 +564,56 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_encryption_policy() -> Result<()> {
+        let network: NetworkConfig = json5::from_str(
+            r#"{
+                host: "https://localhost",
+                encryption: "RequireE2E",
+                room_encryption: { "static:lobby": "PlaintextAllowed" },
+            }"#,
+        )?;
+        assert_eq!(network.encryption_for("team"), EncryptionPolicy::RequireE2E);
+        assert_eq!(
+            network.encryption_for("static:lobby"),
+            EncryptionPolicy::PlaintextAllowed
+        );
+        assert_eq!(
+            NetworkConfig::default().encryption_for("team"),
+            EncryptionPolicy::PreferE2E
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_attachment_size() {
+        let mut network = NetworkConfig::default();
+        assert_eq!(network.max_attachment_size().ok(), Some(16 * 1024 * 1024));
+        network.max_attachment_mb = Some(u64::MAX / 1024);
+        assert!(network.max_attachment_size().is_err());
+    }
+
+    #[test]
+    fn test_history_retention() -> Result<()> {
+        let network: NetworkConfig = json5::from_str(
+            r#"{
+                host: "https://localhost",
+                history_days: 7,
+                room_history_days: { "static:secret": 0 },
+            }"#,
+        )?;
+        assert_eq!(
+            network.history_retention("team"),
+            Some(chrono::TimeDelta::days(7))
+        );
+        assert_eq!(network.history_retention("static:secret"), None);
+        assert_eq!(
+            NetworkConfig::default().history_retention("team"),
+            Some(chrono::TimeDelta::days(30))
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_simple_keys() {
         assert_eq!(
@@ -15,6 +15,8 @@ mod error;
 mod errors;
 mod logging;
 mod network;
+mod qr;
+mod secret;
 mod tui;
 mod util;
 pub(crate) use error::LockErrorExt;
@@ -33,6 +35,9 @@ async fn actual_main() -> Result<()> {
     crate::errors::init()?;
     crate::logging::init()?;
     let args = Cli::parse();
+    if let Some(command) = args.command.clone() {
+        return cli::run_command(command);
+    }
     let mut app = App::new(args)?;
     app.run().await?;
     Ok(())
@@ -0,0 +1,135 @@
+use super::Result;
+use alkali::asymmetric::cipher::PublicKey;
+use alkali::hash::generic;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use strum::Display;
+
+/// Bytes of the key hash shown to the user.
+const FINGERPRINT_LENGTH: usize = 8;
+
+/// Answer of the user to an incoming key request, remembered per room and key across restarts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum KeyDecision {
+    /// Send the room key this time and with every rotation, ask again on the next request.
+    Approve,
+    /// Ignore requests from this key in this room.
+    Deny,
+    /// Send the room key to this key in this room without asking.
+    AlwaysTrust,
+}
+
+/// A key request waiting for the user, carries everything needed to answer it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyRequestPrompt {
+    pub room: String,
+    pub username: Option<String>,
+    /// Base64 public key of the requester.
+    pub public_key: String,
+    /// Only shown to the user, the decision is kept for `public_key`.
+    pub fingerprint: String,
+}
+
+/// Short, human comparable form of a public key, e.g. `3f1a:9c04:77de:0b12`.
+pub fn fingerprint(public_key: &PublicKey) -> Result<String> {
+    let digest = generic::hash(public_key, None)?;
+    Ok(digest[..FINGERPRINT_LENGTH]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+/// Decisions on key requests by room and base64 public key of the requester, kept in a file.
+///
+/// Never by fingerprint, that is short enough to be matched by another key.
+#[derive(Debug, Default)]
+pub struct KeyDecisions {
+    path: Option<PathBuf>,
+    decisions: BTreeMap<String, BTreeMap<String, KeyDecision>>,
+}
+
+impl KeyDecisions {
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let decisions = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(eyre!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            decisions,
+        })
+    }
+
+    pub fn get(&self, room: &str, public_key: &str) -> Option<KeyDecision> {
+        self.decisions.get(room)?.get(public_key).copied()
+    }
+
+    pub fn set(&mut self, room: &str, public_key: &str, decision: KeyDecision) -> Result<()> {
+        let previous = self
+            .decisions
+            .entry(room.to_owned())
+            .or_default()
+            .insert(public_key.to_owned(), decision);
+        if previous != Some(decision) {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| eyre!("Cannot create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&self.decisions)?)
+            .map_err(|e| eyre!("Cannot write {}: {}", path.display(), e).into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alkali::asymmetric::cipher::Keypair;
+
+    #[test]
+    fn test_fingerprint() -> Result<()> {
+        let key = Keypair::generate()?;
+        let print = fingerprint(&key.public_key)?;
+        assert_eq!(print.len(), 19);
+        assert_eq!(print, fingerprint(&key.public_key)?);
+        assert_ne!(print, fingerprint(&Keypair::generate()?.public_key)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_decisions_are_remembered() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("trusted_keys.json");
+        let mut decisions = KeyDecisions::load(path.clone())?;
+        decisions.set("lobby", "alice", KeyDecision::AlwaysTrust)?;
+        decisions.set("lobby", "bob", KeyDecision::Deny)?;
+        decisions.set("static:team", "carol", KeyDecision::Approve)?;
+        assert_eq!(decisions.get("lobby", "bob"), Some(KeyDecision::Deny));
+
+        let reloaded = KeyDecisions::load(path)?;
+        assert_eq!(
+            reloaded.get("lobby", "alice"),
+            Some(KeyDecision::AlwaysTrust)
+        );
+        assert_eq!(reloaded.get("lobby", "bob"), Some(KeyDecision::Deny));
+        assert_eq!(
+            reloaded.get("static:team", "carol"),
+            Some(KeyDecision::Approve)
+        );
+        // decisions are per room
+        assert_eq!(reloaded.get("static:team", "alice"), None);
+        Ok(())
+    }
+}
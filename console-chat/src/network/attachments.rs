@@ -0,0 +1,526 @@
+use super::{Result, from_base64, to_base64};
+use alkali::hash::generic;
+use color_eyre::eyre::eyre;
+use openapi::models::{Encrypted, FileChunk};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// File bytes per chunk, one chunk stays well below the frame limits after encryption and base64.
+pub const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Unfinished transfers kept at once, a new one beyond that is refused.
+const MAX_PENDING_TRANSFERS: usize = 16;
+
+/// Bytes one sender may have in memory at once, in attachments of the largest allowed size.
+const MAX_PER_SENDER: u64 = 4;
+
+/// Bytes all senders together may have in memory at once, in attachments of the largest size.
+const MAX_IN_TOTAL: u64 = 8;
+
+/// What every chunk carries inside its ciphertext, so a transfer can start from any chunk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Derived from name and content, sending the same file again resumes the transfer.
+    pub transfer: String,
+    pub name: String,
+    pub size: u64,
+    /// Base64 BLAKE2b hash of the whole file.
+    pub hash: String,
+    pub count: u32,
+}
+
+impl Manifest {
+    pub fn for_file(name: &str, content: &[u8]) -> Result<Self> {
+        let hash = generic::hash(content, None)?;
+        let transfer = generic::hash(&[name.as_bytes(), b"\0", &hash].concat(), None)?;
+        Ok(Self {
+            transfer: to_base64(&transfer[..12]),
+            name: name.to_owned(),
+            size: content.len() as u64,
+            hash: to_base64(&hash),
+            count: chunk_count(content.len() as u64)?,
+        })
+    }
+
+    fn check(&self, max_size: u64) -> Result<()> {
+        if self.size > max_size {
+            return Err(eyre!(
+                "Attachment {} has {} bytes, more than the {} allowed",
+                self.name,
+                self.size,
+                max_size
+            )
+            .into());
+        }
+        if self.count != chunk_count(self.size)? {
+            return Err(eyre!("Attachment {} has the wrong number of chunks", self.name).into());
+        }
+        if file_name(&self.name).is_none() {
+            return Err(eyre!("Attachment name {:?} is not a file name", self.name).into());
+        }
+        Ok(())
+    }
+}
+
+fn chunk_count(size: u64) -> Result<u32> {
+    Ok(u32::try_from(size.div_ceil(CHUNK_SIZE as u64).max(1))
+        .map_err(|_| eyre!("Attachment is too large"))?)
+}
+
+/// The plaintext of a `FileChunk`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkPayload {
+    pub manifest: Manifest,
+    pub index: u32,
+    /// Base64 part of the file.
+    pub data: String,
+}
+
+pub fn split<'a>(
+    manifest: &'a Manifest,
+    content: &'a [u8],
+) -> impl Iterator<Item = ChunkPayload> + 'a {
+    // an empty file is still one (empty) chunk
+    let chunks = content
+        .chunks(CHUNK_SIZE)
+        .chain(content.is_empty().then_some(content));
+    chunks.enumerate().map(|(index, data)| ChunkPayload {
+        manifest: manifest.clone(),
+        index: index as u32,
+        data: to_base64(data),
+    })
+}
+
+/// A chunk travels like an `Encrypted` message, only its type differs so older clients skip it.
+pub fn as_encrypted(chunk: &FileChunk) -> Encrypted {
+    let mut encrypted = Encrypted::new(chunk.content_base64.clone(), chunk.nonce.clone());
+    encrypted.signature = chunk.signature.clone();
+    encrypted.key_epoch = chunk.key_epoch;
+    encrypted
+}
+
+pub fn into_chunk(encrypted: Encrypted) -> FileChunk {
+    let mut chunk = FileChunk::new(encrypted.content_base64, encrypted.nonce);
+    chunk.signature = encrypted.signature;
+    chunk.key_epoch = encrypted.key_epoch;
+    chunk
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) enum AttachmentState {
+    Sending,
+    /// Every chunk was handed to the server.
+    Sent,
+    Receiving,
+    /// Every chunk arrived and the hash matches, it can be saved.
+    Complete,
+    Saved(PathBuf),
+    /// Thrown away without saving.
+    Dismissed,
+}
+
+/// An attachment as shown in the chat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct AttachmentView {
+    pub transfer: String,
+    pub name: String,
+    pub size: u64,
+    /// Chunks sent or received so far.
+    pub done: u32,
+    pub count: u32,
+    pub state: AttachmentState,
+}
+
+impl AttachmentView {
+    pub fn new(manifest: &Manifest, done: u32, state: AttachmentState) -> Self {
+        Self {
+            transfer: manifest.transfer.clone(),
+            name: manifest.name.clone(),
+            size: manifest.size,
+            done,
+            count: manifest.count,
+            state,
+        }
+    }
+}
+
+struct Transfer {
+    manifest: Manifest,
+    room: String,
+    sender: String,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    /// Set once every chunk arrived and the hash matched.
+    content: Option<Vec<u8>>,
+}
+
+/// Received transfers by id, chunks may come in any order and more than once.
+///
+/// Kept until they are saved or dismissed, so a transfer interrupted by leaving a room or a lost
+/// connection completes when the sender sends the file again. The space a transfer takes is
+/// counted from its first chunk on, by the size its manifest announces.
+#[derive(Default)]
+pub struct Incoming {
+    transfers: HashMap<String, Transfer>,
+    /// Rooms and senders whose last chunk couldn't be read, reported once until one can be.
+    failed: HashSet<(String, String)>,
+}
+
+impl Incoming {
+    /// Adds a chunk from `sender` in `room`, returns `None` if it was already received.
+    pub fn add(
+        &mut self,
+        chunk: ChunkPayload,
+        room: &str,
+        sender: &str,
+        max_size: u64,
+    ) -> Result<Option<AttachmentView>> {
+        self.failed.remove(&(room.to_owned(), sender.to_owned()));
+        let manifest = &chunk.manifest;
+        manifest.check(max_size)?;
+        if chunk.index >= manifest.count {
+            return Err(eyre!("Chunk {} of {} is out of range", chunk.index, manifest.name).into());
+        }
+        let pending = self
+            .transfers
+            .values()
+            .filter(|transfer| transfer.content.is_none())
+            .count();
+        if !self.transfers.contains_key(&manifest.transfer) {
+            if pending >= MAX_PENDING_TRANSFERS {
+                return Err(eyre!(
+                    "Too many unfinished attachments, {} is ignored",
+                    manifest.name
+                )
+                .into());
+            }
+            let held = |from: Option<&str>| -> u64 {
+                self.transfers
+                    .values()
+                    .filter(|transfer| from.is_none_or(|from| transfer.sender == from))
+                    .map(|transfer| transfer.manifest.size)
+                    .sum()
+            };
+            if held(Some(sender)) + manifest.size > MAX_PER_SENDER * max_size {
+                return Err(eyre!(
+                    "{} has too many attachments waiting to be saved, {} is ignored",
+                    sender,
+                    manifest.name
+                )
+                .into());
+            }
+            if held(None) + manifest.size > MAX_IN_TOTAL * max_size {
+                return Err(eyre!(
+                    "Too many attachments are waiting to be saved, {} is ignored",
+                    manifest.name
+                )
+                .into());
+            }
+        }
+        let transfer = self
+            .transfers
+            .entry(manifest.transfer.clone())
+            .or_insert_with(|| Transfer {
+                manifest: manifest.clone(),
+                room: room.to_owned(),
+                sender: sender.to_owned(),
+                chunks: BTreeMap::new(),
+                content: None,
+            });
+        if transfer.manifest != *manifest {
+            return Err(eyre!(
+                "Chunk doesn't belong to attachment {}",
+                transfer.manifest.name
+            )
+            .into());
+        }
+        if transfer.content.is_some() || transfer.chunks.contains_key(&chunk.index) {
+            return Ok(None);
+        }
+        let data = from_base64(&chunk.data)?;
+        let expected = if chunk.index + 1 == manifest.count {
+            manifest.size as usize - chunk.index as usize * CHUNK_SIZE
+        } else {
+            CHUNK_SIZE
+        };
+        if data.len() != expected {
+            return Err(eyre!(
+                "Chunk {} of {} has the wrong size",
+                chunk.index,
+                manifest.name
+            )
+            .into());
+        }
+        transfer.chunks.insert(chunk.index, data);
+
+        let done = transfer.chunks.len() as u32;
+        if done < manifest.count {
+            return Ok(Some(AttachmentView::new(
+                manifest,
+                done,
+                AttachmentState::Receiving,
+            )));
+        }
+        let content = std::mem::take(&mut transfer.chunks)
+            .into_values()
+            .flatten()
+            .collect::<Vec<_>>();
+        if to_base64(&generic::hash(&content, None)?) != manifest.hash {
+            // start over, a new attempt may bring intact chunks
+            let name = manifest.name.clone();
+            self.transfers.remove(&chunk.manifest.transfer);
+            return Err(eyre!(
+                "Attachment {} doesn't match its hash, it is discarded",
+                name
+            )
+            .into());
+        }
+        transfer.content = Some(content);
+        Ok(Some(AttachmentView::new(
+            manifest,
+            done,
+            AttachmentState::Complete,
+        )))
+    }
+
+    /// Writes a complete transfer into `dir`, next to files of the same name, and forgets it.
+    pub fn save(&mut self, id: &str, dir: &Path) -> Result<AttachmentView> {
+        let transfer = self
+            .transfers
+            .get(id)
+            .ok_or_else(|| eyre!("Unknown attachment"))?;
+        let content = transfer
+            .content
+            .as_ref()
+            .ok_or_else(|| eyre!("{} isn't complete yet", transfer.manifest.name))?;
+        let name = file_name(&transfer.manifest.name)
+            .ok_or_else(|| eyre!("{:?} is not a file name", transfer.manifest.name))?;
+        std::fs::create_dir_all(dir)
+            .map_err(|e| eyre!("Cannot create {}: {}", dir.display(), e))?;
+        let path = free_path(dir, name);
+        std::fs::write(&path, content)
+            .map_err(|e| eyre!("Cannot write {}: {}", path.display(), e))?;
+        let view = AttachmentView::new(
+            &transfer.manifest,
+            transfer.manifest.count,
+            AttachmentState::Saved(path),
+        );
+        self.transfers.remove(id);
+        Ok(view)
+    }
+
+    /// Drops the unfinished transfers of `sender` in `room` after one of its chunks couldn't be
+    /// decrypted. Which transfer the chunk belonged to is inside it, and the transfer can't
+    /// complete without it.
+    ///
+    /// Returns the dropped transfers, or `None` if this was already reported since the last
+    /// chunk that could be read.
+    pub fn fail(&mut self, room: &str, sender: &str) -> Option<Vec<AttachmentView>> {
+        if !self.failed.insert((room.to_owned(), sender.to_owned())) {
+            return None;
+        }
+        let dropped: Vec<String> = self
+            .transfers
+            .iter()
+            .filter(|(_, transfer)| {
+                transfer.room == room && transfer.sender == sender && transfer.content.is_none()
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        Some(
+            dropped
+                .iter()
+                .filter_map(|id| self.dismiss(id).ok())
+                .collect(),
+        )
+    }
+
+    /// Forgets a transfer, complete or not, without saving it.
+    pub fn dismiss(&mut self, id: &str) -> Result<AttachmentView> {
+        let transfer = self
+            .transfers
+            .remove(id)
+            .ok_or_else(|| eyre!("Unknown attachment"))?;
+        let done = match transfer.content {
+            Some(_) => transfer.manifest.count,
+            None => transfer.chunks.len() as u32,
+        };
+        Ok(AttachmentView::new(
+            &transfer.manifest,
+            done,
+            AttachmentState::Dismissed,
+        ))
+    }
+}
+
+/// Only the last component, a sender can't pick the directory.
+fn file_name(name: &str) -> Option<&str> {
+    let base = Path::new(name).file_name()?.to_str()?;
+    (base == name).then_some(base)
+}
+
+/// `name`, or `name (2)` and so on if that exists already.
+fn free_path(dir: &Path, name: &str) -> PathBuf {
+    let path = dir.join(name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{extension}")),
+        _ => (name, String::new()),
+    };
+    (2..)
+        .map(|i| dir.join(format!("{stem} ({i}){extension}")))
+        .find(|path| !path.exists())
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_reassembly_out_of_order() -> Result<()> {
+        let content: Vec<u8> = (0..CHUNK_SIZE * 2 + 100).map(|i| i as u8).collect();
+        let manifest = Manifest::for_file("notes.txt", &content)?;
+        assert_eq!(manifest.count, 3);
+        let mut chunks: Vec<_> = split(&manifest, &content).collect();
+        chunks.reverse();
+
+        let mut incoming = Incoming::default();
+        let last = chunks.remove(0);
+        let view = incoming
+            .add(last.clone(), "lobby", "alice", MAX)?
+            .expect("new chunk");
+        assert_eq!(view.state, AttachmentState::Receiving);
+        assert_eq!(incoming.add(last, "lobby", "alice", MAX)?, None);
+        assert!(incoming.save(&manifest.transfer, Path::new(".")).is_err());
+
+        let mut views = Vec::new();
+        for chunk in chunks {
+            views.push(
+                incoming
+                    .add(chunk, "lobby", "alice", MAX)?
+                    .expect("new chunk"),
+            );
+        }
+        assert_eq!(
+            views.last().map(|view| &view.state),
+            Some(&AttachmentState::Complete)
+        );
+
+        let dir = tempfile::tempdir()?;
+        let saved = incoming.save(&manifest.transfer, dir.path())?;
+        let AttachmentState::Saved(path) = saved.state else {
+            panic!("not saved");
+        };
+        assert_eq!(std::fs::read(&path)?, content);
+        // saved transfers are forgotten, sending the file again starts over
+        assert!(incoming.transfers.is_empty());
+        assert!(incoming.save(&manifest.transfer, dir.path()).is_err());
+        for chunk in split(&manifest, &content) {
+            incoming.add(chunk, "lobby", "alice", MAX)?;
+        }
+        let again = incoming.save(&manifest.transfer, dir.path())?;
+        assert_eq!(
+            again.state,
+            AttachmentState::Saved(dir.path().join("notes (2).txt"))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_chunks() -> Result<()> {
+        let content = vec![7u8; 100];
+        let manifest = Manifest::for_file("a.bin", &content)?;
+        let chunk = split(&manifest, &content).next().expect("one chunk");
+        let mut incoming = Incoming::default();
+
+        assert!(incoming.add(chunk.clone(), "lobby", "alice", 50).is_err());
+        let mut outside = chunk.clone();
+        outside.manifest.name = "../../.bashrc".to_owned();
+        assert!(incoming.add(outside, "lobby", "alice", MAX).is_err());
+        let mut truncated = chunk.clone();
+        truncated.data = to_base64(&content[..10]);
+        assert!(incoming.add(truncated, "lobby", "alice", MAX).is_err());
+
+        let mut tampered = chunk;
+        tampered.data = to_base64(&[8u8; 100]);
+        assert!(incoming.add(tampered, "lobby", "alice", MAX).is_err());
+        // the broken transfer was dropped, it can start over
+        assert!(incoming.transfers.is_empty());
+
+        let empty = Manifest::for_file("empty", &[])?;
+        let chunk = split(&empty, &[]).next().expect("one chunk");
+        let view = incoming
+            .add(chunk, "lobby", "alice", MAX)?
+            .expect("new chunk");
+        assert_eq!(view.state, AttachmentState::Complete);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bytes_in_flight() -> Result<()> {
+        const SIZE: u64 = 100;
+        fn send(incoming: &mut Incoming, sender: &str, name: &str) -> Result<String> {
+            let content = vec![1u8; SIZE as usize];
+            let manifest = Manifest::for_file(name, &content)?;
+            let chunk = split(&manifest, &content).next().expect("one chunk");
+            incoming
+                .add(chunk, "lobby", sender, SIZE)
+                .map(|_| manifest.transfer)
+        }
+        let mut incoming = Incoming::default();
+        let mut alice = Vec::new();
+        for i in 0..MAX_PER_SENDER {
+            alice.push(send(&mut incoming, "alice", &format!("a{i}"))?);
+        }
+        assert!(send(&mut incoming, "alice", "one too many").is_err());
+        for i in 0..MAX_IN_TOTAL - MAX_PER_SENDER {
+            send(&mut incoming, "bob", &format!("b{i}"))?;
+        }
+        assert!(send(&mut incoming, "carol", "c0").is_err());
+
+        let view = incoming.dismiss(&alice[0])?;
+        assert_eq!(view.state, AttachmentState::Dismissed);
+        assert!(incoming.dismiss(&alice[0]).is_err());
+        send(&mut incoming, "carol", "c0")?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_undecryptable_chunks() -> Result<()> {
+        let content = vec![3u8; CHUNK_SIZE + 10];
+        let first = |name: &str| -> Result<ChunkPayload> {
+            let manifest = Manifest::for_file(name, &content)?;
+            Ok(split(&manifest, &content).next().expect("two chunks"))
+        };
+        let mut incoming = Incoming::default();
+        let unfinished = first("a.bin")?;
+        incoming.add(unfinished.clone(), "lobby", "alice", MAX)?;
+        let complete = Manifest::for_file("b.bin", &content[..10])?;
+        for chunk in split(&complete, &content[..10]) {
+            incoming.add(chunk, "lobby", "alice", MAX)?;
+        }
+        incoming.add(first("c.bin")?, "static:team", "alice", MAX)?;
+        incoming.add(first("d.bin")?, "lobby", "bob", MAX)?;
+
+        let dropped = incoming.fail("lobby", "alice").expect("first failure");
+        assert_eq!(dropped.len(), 1);
+        assert_eq!(dropped[0].transfer, unfinished.manifest.transfer);
+        assert_eq!(dropped[0].state, AttachmentState::Dismissed);
+        // reported once, the rest is kept
+        assert_eq!(incoming.fail("lobby", "alice"), None);
+        assert_eq!(incoming.transfers.len(), 3);
+
+        // a chunk that could be read starts over
+        incoming.add(unfinished, "lobby", "alice", MAX)?;
+        assert_eq!(
+            incoming.fail("lobby", "alice").map(|views| views.len()),
+            Some(1)
+        );
+        Ok(())
+    }
+}
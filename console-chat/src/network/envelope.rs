@@ -0,0 +1,163 @@
+use super::Result;
+use color_eyre::eyre::eyre;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::io::{Read, Write};
+
+/// First byte of every envelope. Text and JSON never start with it, so anything else was sent by
+/// a client from before the envelope.
+const MAGIC: u8 = 0;
+
+pub const VERSION: u8 = 1;
+
+/// Flag bit for a deflate compressed body.
+const COMPRESSED: u8 = 1;
+
+/// Magic, version, flags and the body length as big endian `u32`.
+const HEADER_LENGTH: usize = 7;
+
+/// Bodies up to this size aren't worth compressing.
+const COMPRESS_ABOVE: usize = 256;
+
+/// Upper limit for a body, also after decompression.
+const MAX_BODY: usize = 1 << 20;
+
+/// Smallest padded size, a short answer looks like any other short message.
+const MIN_BUCKET: usize = 64;
+
+/// Padded sizes double up to here and grow in steps of this size after.
+const MAX_DOUBLING_BUCKET: usize = 4096;
+
+/// The plaintext of an encrypted message, with the envelope taken off.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Opened {
+    /// `None` if the sender doesn't use the envelope yet.
+    pub version: Option<u8>,
+    pub body: Vec<u8>,
+}
+
+/// Wraps `body` in an envelope padded to the next bucket size, compressed if that is shorter.
+pub fn seal(body: &[u8]) -> Result<Vec<u8>> {
+    if body.len() > MAX_BODY {
+        return Err(eyre!("Message is larger than {} bytes", MAX_BODY).into());
+    }
+    let mut flags = 0;
+    let mut compressed = None;
+    if body.len() > COMPRESS_ABOVE {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body)?;
+        let deflated = encoder.finish()?;
+        if deflated.len() < body.len() {
+            flags |= COMPRESSED;
+            compressed = Some(deflated);
+        }
+    }
+    let body = compressed.as_deref().unwrap_or(body);
+
+    let mut sealed = Vec::with_capacity(bucket(HEADER_LENGTH + body.len()));
+    sealed.extend([MAGIC, VERSION, flags]);
+    sealed.extend((body.len() as u32).to_be_bytes());
+    sealed.extend(body);
+    sealed.resize(bucket(sealed.len()), 0);
+    Ok(sealed)
+}
+
+/// Takes the envelope off a decrypted plaintext, returns plaintexts of older clients as they are.
+pub fn open(plaintext: &[u8]) -> Result<Opened> {
+    if plaintext.first() != Some(&MAGIC) {
+        return Ok(Opened {
+            version: None,
+            body: plaintext.to_vec(),
+        });
+    }
+    if plaintext.len() < HEADER_LENGTH {
+        return Err(eyre!("Message envelope is truncated").into());
+    }
+    let version = plaintext[1];
+    if version > VERSION {
+        return Err(eyre!(
+            "Message uses envelope version {}, this client only reads up to {}",
+            version,
+            VERSION
+        )
+        .into());
+    }
+    let flags = plaintext[2];
+    let length = u32::from_be_bytes([plaintext[3], plaintext[4], plaintext[5], plaintext[6]]);
+    let body = plaintext[HEADER_LENGTH..]
+        .get(..length as usize)
+        .ok_or_else(|| eyre!("Message envelope is shorter than its body length"))?;
+    let body = if flags & COMPRESSED != 0 {
+        let mut inflated = Vec::new();
+        DeflateDecoder::new(body)
+            .take(MAX_BODY as u64 + 1)
+            .read_to_end(&mut inflated)?;
+        if inflated.len() > MAX_BODY {
+            return Err(eyre!("Message inflates to more than {} bytes", MAX_BODY).into());
+        }
+        inflated
+    } else {
+        body.to_vec()
+    };
+    Ok(Opened {
+        version: Some(version),
+        body,
+    })
+}
+
+/// The padded size for `length` bytes.
+fn bucket(length: usize) -> usize {
+    if length <= MAX_DOUBLING_BUCKET {
+        length.next_power_of_two().max(MIN_BUCKET)
+    } else {
+        length.div_ceil(MAX_DOUBLING_BUCKET) * MAX_DOUBLING_BUCKET
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope() -> Result<()> {
+        // "yes" and "no" can't be told apart by their length
+        let yes = seal(b"yes")?;
+        let no = seal(b"no")?;
+        assert_eq!(yes.len(), MIN_BUCKET);
+        assert_eq!(no.len(), MIN_BUCKET);
+        assert_eq!(open(&yes)?.body, b"yes");
+        assert_eq!(open(&no)?.version, Some(VERSION));
+
+        let long = "all work and no play ".repeat(100);
+        let sealed = seal(long.as_bytes())?;
+        assert_eq!(sealed[2] & COMPRESSED, COMPRESSED);
+        assert!(sealed.len() < long.len());
+        assert_eq!(open(&sealed)?.body, long.as_bytes());
+
+        assert_eq!(bucket(5000), 2 * MAX_DOUBLING_BUCKET);
+        Ok(())
+    }
+
+    #[test]
+    fn test_envelope_from_other_clients() -> Result<()> {
+        let legacy = br#"{"text": "hi"}"#;
+        assert_eq!(
+            open(legacy)?,
+            Opened {
+                version: None,
+                body: legacy.to_vec()
+            }
+        );
+
+        let mut newer = seal(b"hi")?;
+        newer[1] = VERSION + 1;
+        assert!(open(&newer).is_err());
+
+        let mut lying = seal(b"hi")?;
+        lying[3..7].copy_from_slice(&1000u32.to_be_bytes());
+        assert!(open(&lying).is_err());
+        assert!(open(&[MAGIC, VERSION]).is_err());
+        Ok(())
+    }
+}
@@ -1,3 +1,4 @@
+use super::payload::MalformedPayload;
 use crate::error::print_recursive_error;
 use crate::util::TypeErasedWrapper;
 use alkali::AlkaliError;
@@ -18,6 +19,10 @@ pub enum NetworkError {
     AlkaliError(AlkaliError),
     Base64Error(DecodeError),
     Utf8Error(std::str::Utf8Error),
+    /// A peer sent a payload that doesn't decode, see [`MalformedPayload`].
+    Malformed(MalformedPayload),
+    #[cfg(feature = "websocket")]
+    WebSocket(Arc<tokio_tungstenite::tungstenite::Error>),
 }
 
 impl std::fmt::Display for NetworkError {
@@ -28,10 +33,13 @@ impl std::fmt::Display for NetworkError {
             Self::Utf8Error(e) => ("utf8", print_recursive_error(e)),
             Self::AlkaliError(e) => ("alkali", print_recursive_error(e)),
             Self::Base64Error(e) => ("base64", print_recursive_error(e)),
+            Self::Malformed(e) => ("payload", e.to_string()),
             Self::ReqwestEventSource(e) => ("reqwest-eventsource", print_recursive_error(e)),
             Self::Serde(e) => ("serde", print_recursive_error(e)),
             Self::Io(e) => ("IO", print_recursive_error(e)),
             Self::CannotCloneRequestError(e) => ("event source", print_recursive_error(e)),
+            #[cfg(feature = "websocket")]
+            Self::WebSocket(e) => ("websocket", print_recursive_error(e)),
             Self::ResponseError(e) => (
                 "response",
                 if let Some(entity) = e.entity.as_ref() {
@@ -84,12 +92,25 @@ impl From<AlkaliError> for NetworkError {
     }
 }
 
+impl From<MalformedPayload> for NetworkError {
+    fn from(value: MalformedPayload) -> NetworkError {
+        Self::Malformed(value)
+    }
+}
+
 impl From<std::str::Utf8Error> for NetworkError {
     fn from(value: std::str::Utf8Error) -> NetworkError {
         Self::Utf8Error(value)
     }
 }
 
+#[cfg(feature = "websocket")]
+impl From<tokio_tungstenite::tungstenite::Error> for NetworkError {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> NetworkError {
+        Self::WebSocket(Arc::new(value))
+    }
+}
+
 pub trait ToNetworkError: Into<OpenapiError<()>> {}
 
 impl ToNetworkError for reqwest::Error {}
@@ -0,0 +1,450 @@
+use super::search::{self, Hit, Matcher};
+use super::{Message, Result, from_base64, to_base64};
+use alkali::hash::generic;
+use alkali::mem::FullAccess;
+use alkali::symmetric::cipher::{self as symetric_cipher, Key, MAC_LENGTH, Nonce};
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use zeroize::Zeroizing;
+
+pub const HISTORY_DIR: &str = "history";
+
+/// Messages loaded at once, on join and whenever the user scrolls past the oldest one.
+pub const PAGE_SIZE: usize = 50;
+
+/// A stored message and when it arrived, which is what retention goes by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    /// The file only has a hash of it, a search over every room needs it.
+    pub room: String,
+    pub received_at: DateTime<Utc>,
+    pub message: Message,
+}
+
+/// Where a line of an opened room starts.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+}
+
+/// The local message history, one file per room in which every line is a message encrypted on
+/// its own, so appending never rewrites the file.
+///
+/// Opening a room reads the file once without decrypting it and keeps an index of line
+/// offsets, pages are then read by seeking. Only the expired lines at the start are decrypted.
+///
+/// Each line starts with a keyed fingerprint of the message. The backlog a server replays on
+/// join is recognised by it without decrypting anything.
+pub struct History {
+    dir: PathBuf,
+    key: Key<FullAccess>,
+    /// Fingerprints of the stored messages, for the rooms opened so far.
+    seen: HashMap<String, HashSet<String>>,
+    /// One entry per line, for the rooms opened so far.
+    index: HashMap<String, Vec<Entry>>,
+}
+
+impl History {
+    pub fn new(dir: PathBuf, key: Key<FullAccess>) -> Self {
+        Self {
+            dir,
+            key,
+            seen: HashMap::new(),
+            index: HashMap::new(),
+        }
+    }
+
+    pub fn default_dir() -> PathBuf {
+        crate::config::get_data_dir().join(HISTORY_DIR)
+    }
+
+    /// Drops the messages that arrived before `keep` ago and returns how many are left.
+    ///
+    /// `None` keeps nothing and deletes the room's history.
+    pub fn open(
+        &mut self,
+        room: &str,
+        keep: Option<TimeDelta>,
+        now: DateTime<Utc>,
+    ) -> Result<usize> {
+        let path = self.path(room)?;
+        let Some(keep) = keep else {
+            self.seen.remove(room);
+            self.index.remove(room);
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .map_err(|e| eyre!("Cannot delete {}: {}", path.display(), e))?;
+            }
+            return Ok(0);
+        };
+        let lines = read_lines(&path)?;
+        // lines are appended as messages arrive, the expired ones come first
+        let expired = lines
+            .iter()
+            .take_while(|line| {
+                self.open_line(line)
+                    .is_ok_and(|(_, record)| now - record.received_at > keep)
+            })
+            .count();
+        let kept = &lines[expired..];
+        if expired > 0 {
+            let content: String = kept.iter().map(|line| format!("{line}\n")).collect();
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, content)
+                .map_err(|e| eyre!("Cannot write {}: {}", tmp.display(), e))?;
+            std::fs::rename(&tmp, &path)
+                .map_err(|e| eyre!("Cannot write {}: {}", path.display(), e))?;
+        }
+        let seen = kept
+            .iter()
+            .filter_map(|line| {
+                line.split_once(' ')
+                    .map(|(fingerprint, _)| fingerprint.to_owned())
+            })
+            .collect();
+        self.seen.insert(room.to_owned(), seen);
+        let mut offset = 0;
+        let index = kept
+            .iter()
+            .map(|line| {
+                let entry = Entry { offset };
+                offset += line.len() as u64 + 1;
+                entry
+            })
+            .collect();
+        self.index.insert(room.to_owned(), index);
+        Ok(kept.len())
+    }
+
+    /// Whether `room` was opened, messages of other rooms aren't stored.
+    pub fn is_open(&self, room: &str) -> bool {
+        self.seen.contains_key(room)
+    }
+
+    /// Stores `message`, returns `false` if it was stored before.
+    pub fn append(
+        &mut self,
+        room: &str,
+        message: &Message,
+        received_at: DateTime<Utc>,
+    ) -> Result<bool> {
+        let fingerprint = self.fingerprint(message)?;
+        let path = self.path(room)?;
+        let Some(seen) = self.seen.get(room) else {
+            return Err(eyre!("History of {} isn't open", room).into());
+        };
+        if seen.contains(&fingerprint) {
+            return Ok(false);
+        }
+        let record = Record {
+            room: room.to_owned(),
+            received_at,
+            message: message.clone(),
+        };
+        let line = self.seal(&fingerprint, &record)?;
+
+        std::fs::create_dir_all(&self.dir)
+            .map_err(|e| eyre!("Cannot create {}: {}", self.dir.display(), e))?;
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| eyre!("Cannot open {}: {}", path.display(), e))?;
+        let offset = file.metadata()?.len();
+        writeln!(file, "{line}")?;
+        self.index
+            .entry(room.to_owned())
+            .or_default()
+            .push(Entry { offset });
+        self.seen
+            .entry(room.to_owned())
+            .or_default()
+            .insert(fingerprint);
+        Ok(true)
+    }
+
+    /// Up to `count` messages stored before index `before`, oldest first, and the index of the
+    /// first one. Damaged entries are left out.
+    pub fn page(&self, room: &str, before: usize, count: usize) -> Result<(Vec<Record>, usize)> {
+        let path = self.path(room)?;
+        let (lines, start) = match self.index.get(room) {
+            Some(index) => {
+                let end = before.min(index.len());
+                let start = end.saturating_sub(count);
+                (read_range(&path, index, start, end)?, start)
+            }
+            None => {
+                let lines = read_lines(&path)?;
+                let end = before.min(lines.len());
+                let start = end.saturating_sub(count);
+                (lines[start..end].to_vec(), start)
+            }
+        };
+        let records = lines
+            .iter()
+            .filter_map(|line| match self.open_line(line) {
+                Ok((_, record)) => Some(record),
+                Err(e) => {
+                    warn!("Skipping a damaged history entry of {room}: {e}");
+                    None
+                }
+            })
+            .collect();
+        Ok((records, start))
+    }
+
+    /// Runs `matcher` over the stored messages of the room it is limited to, or of every room.
+    pub fn search(&self, matcher: &Matcher) -> Result<Vec<Hit>> {
+        let paths = match &matcher.query().room {
+            Some(room) => vec![self.path(room)?],
+            None => match std::fs::read_dir(&self.dir) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+                    .collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(eyre!("Cannot read {}: {}", self.dir.display(), e).into()),
+            },
+        };
+        let mut hits = Vec::new();
+        for path in paths {
+            let lines = read_lines(&path)?;
+            // damaged entries are left out as in `page`, but hits keep their index in the file
+            let (positions, records): (Vec<usize>, Vec<Record>) = lines
+                .iter()
+                .enumerate()
+                .filter_map(|(i, line)| self.open_line(line).ok().map(|(_, record)| (i, record)))
+                .unzip();
+            let Some(room) = records.first().map(|record| record.room.clone()) else {
+                continue;
+            };
+            let messages: Vec<Message> = records.into_iter().map(|record| record.message).collect();
+            hits.extend(search::search(matcher, &room, &messages).into_iter().map(
+                |(i, mut hit)| {
+                    hit.stored_at = Some(positions[i]);
+                    hit
+                },
+            ));
+        }
+        Ok(hits)
+    }
+
+    /// Where the history of `room` is kept, named by a hash so any room name makes a file name.
+    fn path(&self, room: &str) -> Result<PathBuf> {
+        let hash = generic::hash(room.as_bytes(), None)?;
+        let name: String = hash[..16].iter().map(|b| format!("{b:02x}")).collect();
+        Ok(self.dir.join(format!("{name}.log")))
+    }
+
+    /// Keyed, so the fingerprints in the file give nothing away about the messages.
+    fn fingerprint(&self, message: &Message) -> Result<String> {
+        let username = message
+            .user
+            .as_ref()
+            .and_then(|user| user.username.as_deref());
+        let identity =
+            serde_json::to_vec(&(username, message.send_at, &message.content, message.event))?;
+        let hash = generic::hash(&identity, Some(self.key.as_slice()))?;
+        Ok(to_base64(&hash[..16]))
+    }
+
+    /// One line of the file, `fingerprint nonce ciphertext`.
+    fn seal(&self, fingerprint: &str, record: &Record) -> Result<String> {
+        let plaintext = Zeroizing::new(serde_json::to_vec(record)?);
+        let mut ciphertext = vec![0u8; plaintext.len() + MAC_LENGTH];
+        let (_, nonce) = symetric_cipher::encrypt(&plaintext, &self.key, None, &mut ciphertext)?;
+        Ok(format!(
+            "{fingerprint} {} {}",
+            to_base64(&nonce),
+            to_base64(&ciphertext)
+        ))
+    }
+
+    fn open_line(&self, line: &str) -> Result<(String, Record)> {
+        let mut parts = line.splitn(3, ' ');
+        let (Some(fingerprint), Some(nonce), Some(ciphertext)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(eyre!("History entry is truncated").into());
+        };
+        let nonce: Nonce = from_base64(nonce)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| eyre!("History entry has a broken nonce"))?;
+        let ciphertext = from_base64(ciphertext)?;
+        let mut plaintext = Zeroizing::new(vec![0u8; ciphertext.len().saturating_sub(MAC_LENGTH)]);
+        symetric_cipher::decrypt(&ciphertext, &self.key, &nonce, &mut plaintext)?;
+        let record: Record = serde_json::from_slice(&plaintext)?;
+        Ok((fingerprint.to_owned(), record))
+    }
+}
+
+/// Lines `start..end` of an indexed file, read without the lines before them.
+fn read_range(path: &Path, index: &[Entry], start: usize, end: usize) -> Result<Vec<String>> {
+    if start >= end {
+        return Ok(Vec::new());
+    }
+    let mut file =
+        std::fs::File::open(path).map_err(|e| eyre!("Cannot open {}: {}", path.display(), e))?;
+    file.seek(SeekFrom::Start(index[start].offset))?;
+    let mut content = String::new();
+    match index.get(end) {
+        Some(next) => {
+            let mut bytes = vec![0u8; (next.offset - index[start].offset) as usize];
+            file.read_exact(&mut bytes)?;
+            content = String::from_utf8(bytes)
+                .map_err(|e| eyre!("History of {} is damaged: {}", path.display(), e))?;
+        }
+        None => {
+            file.read_to_string(&mut content)?;
+        }
+    }
+    Ok(content.lines().map(str::to_owned).collect())
+}
+
+fn read_lines(path: &Path) -> Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().map(str::to_owned).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(eyre!("Cannot read {}: {}", path.display(), e).into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::search::Query;
+    use openapi::models::{AppearancePublic, UserPublic};
+
+    fn message(content: &str, send_at: DateTime<Utc>) -> Message {
+        let mut user = UserPublic::new(AppearancePublic::new("#c0ffee".to_owned()));
+        user.username = Some("alice".to_owned());
+        Message {
+            content: content.to_owned(),
+            user: Some(user),
+            send_at: Some(send_at),
+            encrypted: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let now = Utc::now();
+        let mut history = History::new(dir.path().to_path_buf(), Key::generate()?);
+        assert_eq!(
+            history.open("static:team", Some(TimeDelta::days(1)), now)?,
+            0
+        );
+
+        for i in 0..5 {
+            let sent = now + TimeDelta::seconds(i);
+            assert!(history.append("static:team", &message(&format!("secret {i}"), sent), now)?);
+        }
+        // the backlog replayed on the next join
+        assert!(!history.append(
+            "static:team",
+            &message("secret 2", now + TimeDelta::seconds(2)),
+            now
+        )?);
+        assert!(history.append("lobby", &message("hi", now), now).is_err());
+
+        let raw = std::fs::read_to_string(history.path("static:team")?)?;
+        assert!(!raw.contains("secret"));
+
+        let (newest, start) = history.page("static:team", usize::MAX, 2)?;
+        assert_eq!(start, 3);
+        assert_eq!(newest.len(), 2);
+        assert_eq!(newest[1].message.content, "secret 4");
+        let (older, start) = history.page("static:team", start, 10)?;
+        assert_eq!(start, 0);
+        assert_eq!(older[0].message.content, "secret 0");
+
+        let hits = history.search(&Query::parse("SECRET 3")?.matcher()?)?;
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].room, "static:team");
+        assert_eq!(hits[0].stored_at, Some(3));
+        assert_eq!(hits[0].before.len(), 2);
+        let mut elsewhere = Query::parse("secret")?;
+        elsewhere.room = Some("lobby".to_owned());
+        assert!(history.search(&elsewhere.matcher()?)?.is_empty());
+
+        let mut reopened = History::new(dir.path().to_path_buf(), history.key.try_clone()?);
+        assert_eq!(
+            reopened.open("static:team", Some(TimeDelta::days(1)), now)?,
+            5
+        );
+        // nothing expired, nothing rewritten
+        assert_eq!(std::fs::read_to_string(history.path("static:team")?)?, raw);
+        assert!(!reopened.append("static:team", &message("secret 0", now), now)?);
+        let later = now + TimeDelta::days(2);
+        assert_eq!(
+            reopened.open("static:team", Some(TimeDelta::days(1)), later)?,
+            0
+        );
+
+        assert!(reopened.append("static:team", &message("kept", later), later)?);
+        // another key reads nothing, but doesn't throw anything away either
+        let mut other = History::new(dir.path().to_path_buf(), Key::generate()?);
+        assert_eq!(
+            other.open("static:team", Some(TimeDelta::days(7)), later)?,
+            1
+        );
+        assert!(other.page("static:team", usize::MAX, 10)?.0.is_empty());
+        assert_eq!(reopened.page("static:team", usize::MAX, 10)?.0.len(), 1);
+
+        assert_eq!(reopened.open("static:team", None, later)?, 0);
+        assert!(!reopened.path("static:team")?.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn test_index() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let now = Utc::now();
+        let mut history = History::new(dir.path().to_path_buf(), Key::generate()?);
+        history.open("team", Some(TimeDelta::days(1)), now)?;
+        for i in 0..6 {
+            let sent = now + TimeDelta::minutes(i);
+            history.append("team", &message(&format!("note {i}"), sent), now)?;
+        }
+        let (records, start) = history.page("team", 4, 2)?;
+        assert_eq!(start, 2);
+        assert_eq!(
+            records
+                .iter()
+                .map(|record| record.message.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["note 2", "note 3"]
+        );
+        assert_eq!(
+            history.page("team", usize::MAX, 1)?.0[0].message.content,
+            "note 5"
+        );
+
+        // the index is read from the file as well as kept up to date while appending
+        let mut reopened = History::new(dir.path().to_path_buf(), history.key.try_clone()?);
+        reopened.open("team", Some(TimeDelta::days(1)), now)?;
+        assert_eq!(reopened.index["team"].len(), 6);
+        assert_eq!(
+            reopened.index["team"][3].offset,
+            history.index["team"][3].offset
+        );
+        assert_eq!(reopened.page("team", 1, 5)?.0[0].message.content, "note 0");
+
+        let mut query = Query::parse("note")?;
+        query.after = Some(now + TimeDelta::minutes(4));
+        let hits = reopened.search(&query.matcher()?)?;
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].stored_at, Some(4));
+        assert_eq!(hits[0].before.len(), 2);
+        assert_eq!(hits[0].before[0].content, "note 2");
+        Ok(())
+    }
+}
@@ -0,0 +1,124 @@
+use super::room_keys::RoomKeys;
+use super::words::{WORDS, to_words};
+use super::{Result, from_base64, to_base64};
+use crate::secret::Secret;
+use alkali::hash::generic;
+use alkali::mem::FullAccess;
+use alkali::symmetric::cipher::{KEY_LENGTH, Key};
+use color_eyre::eyre::eyre;
+use zeroize::Zeroizing;
+
+/// Starts the text of a QR code, a scanned code can be pasted into the import screen as it is.
+const CODE_PREFIX: &str = "console-chat-key:";
+
+const EPOCH_LENGTH: usize = 4;
+
+const CHECKSUM_LENGTH: usize = 2;
+
+const SHARED_LENGTH: usize = EPOCH_LENGTH + KEY_LENGTH + CHECKSUM_LENGTH;
+
+/// Epoch, current key and a checksum over both and the room name.
+///
+/// The checksum catches a misread word as well as words read out for another room.
+fn encode(room: &str, keys: &RoomKeys) -> Result<Zeroizing<Vec<u8>>> {
+    let epoch = u32::try_from(keys.epoch)
+        .map_err(|_| eyre!("The key of {} was rotated too often to be shared", room))?;
+    let mut bytes = Zeroizing::new(epoch.to_be_bytes().to_vec());
+    bytes.extend(keys.current.as_slice());
+    let checksum = checksum(room, &bytes)?;
+    bytes.extend(checksum);
+    Ok(bytes)
+}
+
+fn checksum(room: &str, shared: &[u8]) -> Result<[u8; CHECKSUM_LENGTH]> {
+    let hash = generic::hash(&[room.as_bytes(), b"\0", shared].concat(), None)?;
+    Ok([hash[0], hash[1]])
+}
+
+/// The current key of `room` as words to read out, one per byte.
+pub fn words(room: &str, keys: &RoomKeys) -> Result<Secret> {
+    Ok(Secret::new(to_words(&encode(room, keys)?).join(" ")))
+}
+
+/// The current key of `room` as the text of a QR code.
+pub fn code(room: &str, keys: &RoomKeys) -> Result<Secret> {
+    Ok(Secret::new(format!(
+        "{CODE_PREFIX}{}:{room}",
+        to_base64(&encode(room, keys)?)
+    )))
+}
+
+/// Reads a key shared with [`words`] or [`code`] for `room`, returns it with its epoch.
+pub fn import(room: &str, shared: &Secret) -> Result<(Key<FullAccess>, u64)> {
+    let shared = shared.expose().trim();
+    let bytes = match shared.strip_prefix(CODE_PREFIX) {
+        Some(code) => {
+            let (encoded, code_room) = code
+                .split_once(':')
+                .ok_or_else(|| eyre!("The code has no room name"))?;
+            if code_room != room {
+                return Err(eyre!("The code is for {}, not {}", code_room, room).into());
+            }
+            Zeroizing::new(from_base64(encoded)?)
+        }
+        None => Zeroizing::new(
+            shared
+                .split_whitespace()
+                .map(|word| {
+                    let word = word.to_lowercase();
+                    WORDS
+                        .iter()
+                        .position(|known| *known == word)
+                        .map(|byte| byte as u8)
+                        .ok_or_else(|| eyre!("{:?} is not one of the key words", word))
+                })
+                .collect::<std::result::Result<Vec<_>, _>>()?,
+        ),
+    };
+    if bytes.len() != SHARED_LENGTH {
+        return Err(eyre!(
+            "A shared key has {} words, this has {}",
+            SHARED_LENGTH,
+            bytes.len()
+        )
+        .into());
+    }
+    let (shared, sum) = bytes.split_at(EPOCH_LENGTH + KEY_LENGTH);
+    if checksum(room, shared)? != sum {
+        return Err(eyre!("The checksum doesn't match, check the words and the room name").into());
+    }
+    let (epoch, key_bytes) = shared.split_at(EPOCH_LENGTH);
+    let epoch = u32::from_be_bytes([epoch[0], epoch[1], epoch[2], epoch[3]]);
+    let mut key = Key::new_empty()?;
+    key.copy_from_slice(key_bytes);
+    Ok((key, epoch as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_share_key() -> Result<()> {
+        let keys = RoomKeys::new(Key::generate()?, 3);
+        let words = words("static:team", &keys)?;
+        assert_eq!(words.expose().split(' ').count(), SHARED_LENGTH);
+
+        let (key, epoch) = import("static:team", &words)?;
+        assert_eq!(key.as_slice(), keys.current.as_slice());
+        assert_eq!(epoch, 3);
+        let shouted = Secret::new(format!("  {}\n", words.expose().to_uppercase()));
+        assert_eq!(import("static:team", &shouted)?.1, 3);
+        let (key, _) = import("static:team", &code("static:team", &keys)?)?;
+        assert_eq!(key.as_slice(), keys.current.as_slice());
+
+        assert!(import("lobby", &words).is_err());
+        assert!(import("lobby", &code("static:team", &keys)?).is_err());
+        let mut typo: Vec<_> = words.expose().split(' ').collect();
+        typo[4] = if typo[4] == "acid" { "acorn" } else { "acid" };
+        assert!(import("static:team", &Secret::new(typo.join(" "))).is_err());
+        assert!(import("static:team", &Secret::new("acid toast".to_owned())).is_err());
+        assert!(import("static:team", &Secret::new("acid hunter2".to_owned())).is_err());
+        Ok(())
+    }
+}
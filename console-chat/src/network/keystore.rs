@@ -0,0 +1,407 @@
+use super::room_keys::RoomKeys;
+use super::{Result, from_base64, to_base64};
+use crate::secret::{self, Secret};
+use alkali::asymmetric::cipher::{Keypair, PrivateKey};
+use alkali::asymmetric::sign;
+use alkali::hash::generic;
+use alkali::hash::pbkdf::{self, SALT_LENGTH, Salt};
+use alkali::mem::FullAccess;
+use alkali::symmetric::cipher::{self as symetric_cipher, Key, NONCE_LENGTH, Nonce};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+pub const KEYSTORE_FILE: &str = "keystore.json";
+
+const KEYSTORE_VERSION: u32 = 1;
+
+/// Separates room key salts from other uses of the room name.
+const ROOM_KEY_CONTEXT: &str = "console-chat room key:";
+
+/// Separates the key a room owner hands the server from the room key of the same passphrase.
+const SERVER_KEY_CONTEXT: &str = "console-chat server key:";
+
+/// Separates the history key from the keystore key it is derived from.
+const HISTORY_KEY_CONTEXT: &str = "console-chat history";
+
+pub type Passphrase = Secret;
+
+/// The keystore file, everything but the KDF parameters is encrypted.
+#[derive(Debug, Serialize, Deserialize)]
+struct SealedKeystore {
+    version: u32,
+    salt: String,
+    ops_limit: usize,
+    mem_limit: usize,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// A base64 key in the keystore content, the only place a secret is serialized as it is.
+#[derive(Default, Serialize, Deserialize)]
+struct StoredKey(#[serde(serialize_with = "secret::serialize_exposed")] Secret);
+
+impl StoredKey {
+    fn encode(key: &[u8]) -> Self {
+        Self(Secret::new(to_base64(key)))
+    }
+
+    fn decode(&self) -> Result<Zeroizing<Vec<u8>>> {
+        Ok(Zeroizing::new(from_base64(self.0.expose())?))
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct KeystoreContent {
+    private_key: StoredKey,
+    signing_key: StoredKey,
+    /// Current room keys by room address.
+    rooms: BTreeMap<String, StoredKey>,
+    /// Epoch of the current key of every room in `rooms`.
+    epochs: BTreeMap<String, u64>,
+    /// Keys replaced by a rotation, by room and epoch.
+    retired: BTreeMap<String, BTreeMap<u64, StoredKey>>,
+    /// Rooms whose key was derived from a passphrase.
+    passphrase_rooms: BTreeSet<String>,
+}
+
+/// Everything read from a keystore by `Keystore::unlock`.
+pub struct Unlocked {
+    pub keystore: Keystore,
+    pub keypair: Keypair,
+    pub signing_keypair: sign::Keypair,
+    pub rooms: HashMap<String, RoomKeys>,
+    pub passphrase_rooms: HashSet<String>,
+}
+
+/// An unlocked keystore, holds the passphrase-derived key so changes can be saved.
+pub struct Keystore {
+    path: PathBuf,
+    key: Key<FullAccess>,
+    salt: Salt,
+    ops_limit: usize,
+    mem_limit: usize,
+}
+
+impl Keystore {
+    pub fn default_path() -> PathBuf {
+        crate::config::get_data_dir().join(KEYSTORE_FILE)
+    }
+
+    /// Starts a new keystore at `path`, nothing is written until `save`.
+    pub fn create(path: PathBuf, passphrase: &Passphrase) -> Result<Self> {
+        let salt = pbkdf::generate_salt()?;
+        let (ops_limit, mem_limit) = (pbkdf::OPS_LIMIT_INTERACTIVE, pbkdf::MEM_LIMIT_INTERACTIVE);
+        Ok(Self {
+            key: derive_key(passphrase, &salt, ops_limit, mem_limit)?,
+            path,
+            salt,
+            ops_limit,
+            mem_limit,
+        })
+    }
+
+    /// Opens the keystore at `path`, returns the identity and room keys stored in it.
+    pub fn unlock(path: PathBuf, passphrase: &Passphrase) -> Result<Unlocked> {
+        let sealed = read_sealed(&path)?;
+        let salt: Salt = from_base64(&sealed.salt)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| eyre!("Keystore salt has the wrong length"))?;
+        let nonce: Nonce = from_base64(&sealed.nonce)?
+            .as_slice()
+            .try_into()
+            .map_err(|_| eyre!("Keystore nonce must be {NONCE_LENGTH} bytes"))?;
+        let key = derive_key(passphrase, &salt, sealed.ops_limit, sealed.mem_limit)?;
+
+        let ciphertext = from_base64(&sealed.ciphertext)?;
+        let mut plaintext = Zeroizing::new(vec![
+            0u8;
+            ciphertext
+                .len()
+                .saturating_sub(symetric_cipher::MAC_LENGTH)
+        ]);
+        symetric_cipher::decrypt(&ciphertext, &key, &nonce, &mut plaintext)
+            .map_err(|_| eyre!("Wrong passphrase or damaged keystore"))?;
+        let content: KeystoreContent = serde_json::from_slice(&plaintext)?;
+
+        let mut private_key = PrivateKey::new_empty()?;
+        let bytes = content.private_key.decode()?;
+        if bytes.len() != private_key.len() {
+            return Err(eyre!("Keystore private key has the wrong length").into());
+        }
+        private_key.copy_from_slice(&bytes);
+        let keypair = Keypair::from_private_key(&private_key)?;
+        let mut signing_key = sign::PrivateKey::new_empty()?;
+        let bytes = content.signing_key.decode()?;
+        if bytes.len() != signing_key.len() {
+            return Err(eyre!("Keystore signing key has the wrong length").into());
+        }
+        signing_key.copy_from_slice(&bytes);
+        let signing_keypair = sign::Keypair::from_private_key(&signing_key)?;
+        let mut rooms = HashMap::new();
+        for (room, encoded) in content.rooms {
+            let epoch = *content
+                .epochs
+                .get(&room)
+                .ok_or_else(|| eyre!("Keystore has no key epoch for {}", room))?;
+            let mut keys = RoomKeys::new(decode_room_key(&room, &encoded)?, epoch);
+            for (epoch, encoded) in content.retired.get(&room).into_iter().flatten() {
+                keys.install(decode_room_key(&room, encoded)?, *epoch);
+            }
+            rooms.insert(room, keys);
+        }
+
+        let keystore = Self {
+            path,
+            key,
+            salt,
+            ops_limit: sealed.ops_limit,
+            mem_limit: sealed.mem_limit,
+        };
+        Ok(Unlocked {
+            keystore,
+            keypair,
+            signing_keypair,
+            rooms,
+            passphrase_rooms: content.passphrase_rooms.into_iter().collect(),
+        })
+    }
+
+    /// Key of the local message history. Derived from the keystore key, so it needs no entry of
+    /// its own and is gone with the keystore.
+    pub fn history_key(&self) -> Result<Key<FullAccess>> {
+        let mut key = Key::new_empty()?;
+        generic::hash_custom(
+            HISTORY_KEY_CONTEXT.as_bytes(),
+            Some(self.key.as_slice()),
+            key.as_mut_slice(),
+        )?;
+        Ok(key)
+    }
+
+    pub fn save(
+        &self,
+        keypair: &Keypair,
+        signing_keypair: &sign::Keypair,
+        rooms: &HashMap<String, RoomKeys>,
+        passphrase_rooms: &HashSet<String>,
+    ) -> Result<()> {
+        let content = KeystoreContent {
+            private_key: StoredKey::encode(keypair.private_key.as_slice()),
+            signing_key: StoredKey::encode(signing_keypair.private_key.as_slice()),
+            rooms: rooms
+                .iter()
+                .map(|(room, keys)| (room.clone(), StoredKey::encode(keys.current.as_slice())))
+                .collect(),
+            epochs: rooms
+                .iter()
+                .map(|(room, keys)| (room.clone(), keys.epoch))
+                .collect(),
+            retired: rooms
+                .iter()
+                .map(|(room, keys)| {
+                    let retired = keys
+                        .retired()
+                        .map(|(epoch, key)| (epoch, StoredKey::encode(key.as_slice())))
+                        .collect::<BTreeMap<_, _>>();
+                    (room.clone(), retired)
+                })
+                .filter(|(_, retired)| !retired.is_empty())
+                .collect(),
+            passphrase_rooms: passphrase_rooms.iter().cloned().collect(),
+        };
+        let plaintext = Zeroizing::new(serde_json::to_vec(&content)?);
+        let mut ciphertext = vec![0u8; plaintext.len() + symetric_cipher::MAC_LENGTH];
+        let (_, nonce) = symetric_cipher::encrypt(&plaintext, &self.key, None, &mut ciphertext)?;
+        let sealed = SealedKeystore {
+            version: KEYSTORE_VERSION,
+            salt: to_base64(&self.salt),
+            ops_limit: self.ops_limit,
+            mem_limit: self.mem_limit,
+            nonce: to_base64(&nonce),
+            ciphertext: to_base64(&ciphertext),
+        };
+        write_file(&self.path, &serde_json::to_vec_pretty(&sealed)?)
+    }
+}
+
+/// Derives the key of a passphrase protected room, so every member gets the same key
+/// without a key exchange. The room address salts the passphrase.
+///
+/// The salt is fixed per room address, so guesses can be precomputed for a room name before
+/// anything of the room is seen. Only a strong passphrase protects the room.
+pub fn room_key(room: &str, passphrase: &Passphrase) -> Result<Key<FullAccess>> {
+    salted_key(ROOM_KEY_CONTEXT, room, passphrase)
+}
+
+/// What a room owner sends the server as the key of a key protected room in place of the
+/// passphrase, the room key can't be derived from it.
+pub fn server_key(room: &str, passphrase: &Passphrase) -> Result<String> {
+    Ok(to_base64(
+        salted_key(SERVER_KEY_CONTEXT, room, passphrase)?.as_slice(),
+    ))
+}
+
+fn salted_key(context: &str, room: &str, passphrase: &Passphrase) -> Result<Key<FullAccess>> {
+    let mut salt: Salt = [0u8; SALT_LENGTH];
+    generic::hash_custom(format!("{context}{room}").as_bytes(), None, &mut salt)?;
+    // fixed limits, they have to match on every client
+    derive_key(
+        passphrase,
+        &salt,
+        pbkdf::OPS_LIMIT_INTERACTIVE,
+        pbkdf::MEM_LIMIT_INTERACTIVE,
+    )
+}
+
+fn derive_key(
+    passphrase: &Passphrase,
+    salt: &Salt,
+    ops_limit: usize,
+    mem_limit: usize,
+) -> Result<Key<FullAccess>> {
+    let mut key = Key::new_empty()?;
+    pbkdf::derive_key(
+        passphrase.expose().as_bytes(),
+        salt,
+        ops_limit,
+        mem_limit,
+        key.as_mut_slice(),
+    )?;
+    Ok(key)
+}
+
+fn decode_room_key(room: &str, encoded: &StoredKey) -> Result<Key<FullAccess>> {
+    let mut room_key = Key::new_empty()?;
+    let bytes = encoded.decode()?;
+    if bytes.len() != room_key.len() {
+        return Err(eyre!("Keystore key for {} has the wrong length", room).into());
+    }
+    room_key.copy_from_slice(&bytes);
+    Ok(room_key)
+}
+
+fn read_sealed(path: &Path) -> Result<SealedKeystore> {
+    let content =
+        std::fs::read(path).map_err(|e| eyre!("Cannot read keystore {}: {}", path.display(), e))?;
+    let sealed: SealedKeystore = serde_json::from_slice(&content)
+        .map_err(|e| eyre!("{} is not a keystore: {}", path.display(), e))?;
+    if sealed.version != KEYSTORE_VERSION {
+        return Err(eyre!("Unsupported keystore version {}", sealed.version).into());
+    }
+    Ok(sealed)
+}
+
+/// Writes next to `path` first, so a crash never leaves half a keystore behind.
+fn write_file(path: &Path, content: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| eyre!("Cannot create {}: {}", parent.display(), e))?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, content).map_err(|e| eyre!("Cannot write {}: {}", tmp.display(), e))?;
+    std::fs::rename(&tmp, path).map_err(|e| eyre!("Cannot write {}: {}", path.display(), e).into())
+}
+
+/// Copies the (still encrypted) keystore to `target`.
+pub fn export(target: &Path) -> Result<()> {
+    let path = Keystore::default_path();
+    read_sealed(&path)?;
+    let content = std::fs::read(&path)?;
+    write_file(target, &content)
+}
+
+/// Replaces the keystore with the one at `source`, after checking it is one.
+pub fn import(source: &Path, force: bool) -> Result<()> {
+    let path = Keystore::default_path();
+    if path.exists() && !force {
+        return Err(eyre!(
+            "A keystore already exists at {}, pass --force to replace it",
+            path.display()
+        )
+        .into());
+    }
+    read_sealed(source)?;
+    let content = std::fs::read(source)?;
+    write_file(&path, &content)
+}
+
+pub fn wipe() -> Result<()> {
+    let path = Keystore::default_path();
+    std::fs::remove_file(&path)
+        .map_err(|e| eyre!("Cannot delete keystore {}: {}", path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keystore_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join(KEYSTORE_FILE);
+        let passphrase = Passphrase::new("correct horse battery staple".to_owned());
+        let keypair = Keypair::generate()?;
+        let signing_keypair = sign::Keypair::generate()?;
+        let mut rooms = HashMap::new();
+        let mut team = RoomKeys::new(Key::generate()?, 0);
+        team.rotate()?;
+        rooms.insert("static:team".to_owned(), team);
+
+        let passphrase_rooms = HashSet::from(["static:team".to_owned()]);
+        Keystore::create(path.clone(), &passphrase)?.save(
+            &keypair,
+            &signing_keypair,
+            &rooms,
+            &passphrase_rooms,
+        )?;
+        let raw = std::fs::read_to_string(&path)?;
+        assert!(!raw.contains(&to_base64(keypair.private_key.as_slice())));
+
+        let unlocked = Keystore::unlock(path.clone(), &passphrase)?;
+        assert_eq!(unlocked.keypair.public_key, keypair.public_key);
+        assert_eq!(
+            unlocked.signing_keypair.public_key,
+            signing_keypair.public_key
+        );
+        let (team, saved) = (&unlocked.rooms["static:team"], &rooms["static:team"]);
+        assert_eq!(team.epoch, 1);
+        assert_eq!(team.current.as_slice(), saved.current.as_slice());
+        assert_eq!(
+            team.candidates(0)[0].as_slice(),
+            saved.candidates(0)[0].as_slice()
+        );
+        assert_eq!(unlocked.passphrase_rooms, passphrase_rooms);
+
+        let wrong = Passphrase::new("wrong".to_owned());
+        assert!(Keystore::unlock(path, &wrong).is_err());
+        assert_eq!(format!("{wrong:?}"), "Secret(***)");
+        Ok(())
+    }
+
+    #[test]
+    fn test_room_key() -> Result<()> {
+        let passphrase = Passphrase::new("open sesame".to_owned());
+        let key = room_key("static:team", &passphrase)?;
+        assert_eq!(
+            key.as_slice(),
+            room_key("static:team", &passphrase)?.as_slice()
+        );
+        assert_ne!(
+            key.as_slice(),
+            room_key("static:other", &passphrase)?.as_slice()
+        );
+        assert_ne!(
+            key.as_slice(),
+            room_key("static:team", &Passphrase::new("open sesame!".to_owned()))?.as_slice()
+        );
+        // what the server stores is no room key
+        let server = server_key("static:team", &passphrase)?;
+        assert_eq!(server, server_key("static:team", &passphrase)?);
+        assert_ne!(from_base64(&server)?, key.as_slice());
+        Ok(())
+    }
+}
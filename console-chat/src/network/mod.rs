@@ -1,89 +1,338 @@
 use crate::action::Action;
 use crate::cli::Cli;
+use crate::config::{EncryptionPolicy, NetworkConfig};
+use crate::secret::Secret;
 //use crate::error::print_recursive_error;
-use alkali::asymmetric::cipher::{self, Keypair, PUBLIC_KEY_LENGTH, PublicKey};
+use alkali::asymmetric::cipher::{self, Keypair, PublicKey};
+use alkali::asymmetric::sign;
 use alkali::mem::FullAccess;
-use alkali::symmetric::cipher::{self as symetric_cipher, Key, NONCE_LENGTH};
+use alkali::symmetric::cipher::{self as symetric_cipher, Key};
+use approval::{KeyDecision, KeyRequestPrompt};
 use base64::{Engine as _, engine::general_purpose};
 use chrono::{DateTime, Utc};
-use color_eyre::eyre::OptionExt;
+use color_eyre::eyre::{OptionExt, eyre};
 use futures_util::stream::StreamExt;
+use keystore::{Keystore, Passphrase};
 use lazy_static::lazy_static;
 use openapi::apis::Error as ApiError;
 use openapi::apis::configuration::Configuration;
 use openapi::apis::{rooms_api, users_api};
 use openapi::models::*;
+use room_keys::RoomKeys;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
+use std::time::Duration;
+use strum::Display;
 use tokio::sync::RwLock;
 use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
 use tokio::task::JoinHandle;
-use tracing::{debug, error};
+use tracing::{debug, error, warn};
+pub(crate) mod approval;
+pub(crate) mod attachments;
+pub(crate) mod envelope;
 pub(crate) mod error;
+pub(crate) mod history;
+pub(crate) mod key_share;
+pub(crate) mod keystore;
+pub(crate) mod payload;
+pub(crate) mod replay;
+pub(crate) mod room_keys;
+pub(crate) mod search;
+pub(crate) mod signing;
+pub(crate) mod tls;
+pub(crate) mod transport;
+pub(crate) mod trust;
+pub(crate) mod words;
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
 pub(crate) struct Message {
     pub content: String,
     pub user: Option<UserPublic>,
     pub send_at: Option<DateTime<Utc>>,
+    /// Set for membership changes, which are shown as a single line instead of a message.
+    pub event: Option<RoomEvent>,
+    /// The content arrived end-to-end encrypted.
+    pub encrypted: bool,
+    pub signature: signing::SignatureState,
+    /// Whether the sealed metadata of an encrypted message rules out a replay.
+    pub freshness: replay::Freshness,
+    /// Envelope version of an encrypted message, `None` if the sender's client predates it.
+    pub envelope_version: Option<u8>,
+    /// Set for a file sent in chunks, shown as an attachment card.
+    pub attachment: Option<attachments::AttachmentView>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub(crate) enum RoomEvent {
+    Join,
+    Leave,
+    /// Someone presented a different identity key than before.
+    KeyChanged,
+}
+
+impl RoomEvent {
+    /// `JOIN` and `LEAVE` are either announced by the message type (with `SYSTEM` content, as the
+    /// server does today) or by dedicated content.
+    fn from_message(message: &MessagePublic) -> Option<Self> {
+        match (message.r#type, &message.content) {
+            (_, Some(Content::Join(_))) | (Some(MessageType::Join), _) => Some(Self::Join),
+            (_, Some(Content::Leave(_))) | (Some(MessageType::Leave), _) => Some(Self::Leave),
+            _ => None,
+        }
+    }
 }
 
 type Result<T, E = error::NetworkError> = std::result::Result<T, E>;
 
+/// Plaintext encrypted with the room key inside a `KeyResponse`, lets the receiver verify the key.
+const CHECK_MSG: &[u8] = b"TEST";
+
+/// Answers to key requests by room and public key, in the data directory.
+const TRUSTED_KEYS_FILE: &str = "trusted_keys.json";
+
+/// Public keys first seen per user, in the data directory.
+const IDENTITIES_FILE: &str = "known_identities.json";
+
+/// How many received messages are remembered to drop the ones replayed after a reconnect.
+const SEEN_MESSAGES: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub enum ConnectionState {
+    Connecting,
+    Live,
+    /// Attempt since the connection was last live.
+    Reconnecting(usize),
+    Failed,
+}
+
+/// Ephemeral rooms only keep their latest messages, static rooms are stored by the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, Display)]
+pub enum RoomKind {
+    #[default]
+    Ephemeral,
+    Static,
+}
+
+/// A room as the user addresses it, `static:` in front of the name selects a static room.
+///
+/// The `Display` form is what `Action`s and the session registry use to identify a room.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoomAddress {
+    pub kind: RoomKind,
+    pub name: String,
+}
+
+impl RoomAddress {
+    pub const STATIC_PREFIX: &str = "static:";
+
+    pub fn new_static(name: &str) -> Self {
+        Self {
+            kind: RoomKind::Static,
+            name: name.to_owned(),
+        }
+    }
+}
+
+impl FromStr for RoomAddress {
+    type Err = error::NetworkError;
+
+    fn from_str(room: &str) -> Result<Self> {
+        let room = room.trim();
+        let (kind, name) = match room.strip_prefix(Self::STATIC_PREFIX) {
+            Some(name) => (RoomKind::Static, name.trim()),
+            None => (RoomKind::Ephemeral, room),
+        };
+        if name.is_empty() {
+            return Err(eyre!("Room name is empty").into());
+        }
+        Ok(Self {
+            kind,
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl std::fmt::Display for RoomAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            RoomKind::Ephemeral => write!(f, "{}", self.name),
+            RoomKind::Static => write!(f, "{}{}", Self::STATIC_PREFIX, self.name),
+        }
+    }
+}
+
+/// Bounded set of recently received messages.
+///
+/// The server replays the latest messages on every (re)connect under new event ids, so the
+/// raw payload is what identifies a message.
+#[derive(Default)]
+struct SeenMessages {
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl SeenMessages {
+    /// Returns `false` if `data` was already received.
+    fn insert(&mut self, data: &str) -> bool {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+        if !self.seen.insert(hash) {
+            return false;
+        }
+        self.order.push_back(hash);
+        if self.order.len() > SEEN_MESSAGES
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.seen.remove(&oldest);
+        }
+        true
+    }
+}
+
 pub struct ListenData {
     pub thread: JoinHandle<Result<()>>,
-    pub room: Arc<String>,
+    pub session: Arc<RoomSession>,
+}
+
+/// State of one joined room, shared between its listen task and the senders.
+pub struct RoomSession {
+    pub room: String,
+    transport: Arc<dyn transport::Transport>,
+    pub keys: RwLock<Option<RoomKeys>>,
+    /// We created or last rotated the room key, so we rotate it when someone leaves.
+    ///
+    /// Only cleared once a key from another member is installed, joins leave it alone.
+    pub holds_key: std::sync::atomic::AtomicBool,
+    /// Base64 public key of the member whose `KeyResponse` brought the current key, only they may
+    /// rotate it without being asked. `None` while we hold the key or it came from elsewhere.
+    pub distributor: RwLock<Option<String>>,
+    /// Set while a `KeyRequest` is unanswered, only then `KeyResponse`s are accepted.
+    pub requested: std::sync::atomic::AtomicBool,
+    /// Encrypted messages that arrived before a usable key, decrypted once the key is installed.
+    pub pending: RwLock<Vec<(Message, Encrypted)>>,
+    /// Usernames of the members seen in a key exchange, by base64 public key, they get the key on
+    /// a rotation.
+    pub members: RwLock<HashMap<String, String>>,
+    /// Older events are replayed history and don't trigger a rotation.
+    pub joined_at: DateTime<Utc>,
+    pub replay: RwLock<replay::ReplayGuard>,
+}
+
+impl RoomSession {
+    fn new(room: &str, transport: Arc<dyn transport::Transport>) -> Self {
+        let joined_at = Utc::now();
+        Self {
+            room: room.to_owned(),
+            transport,
+            keys: Default::default(),
+            holds_key: Default::default(),
+            distributor: Default::default(),
+            requested: Default::default(),
+            pending: Default::default(),
+            members: Default::default(),
+            joined_at,
+            replay: RwLock::new(replay::ReplayGuard::new(room, joined_at)),
+        }
+    }
 }
 
 pub struct KeyData {
-    pub symetric_key: RwLock<Option<Key<FullAccess>>>,
-    pub first: std::sync::atomic::AtomicBool,
-    pub key_map: RwLock<HashMap<String, Key<FullAccess>>>,
+    /// Room keys by room name, kept after leaving so rejoining doesn't need a new exchange.
+    pub key_map: RwLock<HashMap<String, RoomKeys>>,
     pub asymetric_key: RwLock<Keypair>,
+    /// Long-term key every encrypted message is signed with, published in key exchanges.
+    pub signing_key: RwLock<sign::Keypair>,
+    /// Answers to key requests, consulted when `approve_key_requests` is set.
+    pub decisions: RwLock<approval::KeyDecisions>,
+    /// Set once the keystore is unlocked or created, key changes are saved to it from then on.
+    pub keystore: RwLock<Option<keystore::Keystore>>,
+    /// Rooms whose key is derived from a shared passphrase, it is never requested or handed out.
+    pub passphrase_rooms: RwLock<HashSet<String>>,
+    pub trust: RwLock<trust::TrustStore>,
 }
 
 impl KeyData {
     pub fn new() -> Result<Self> {
         Ok(Self {
-            symetric_key: Default::default(),
             asymetric_key: RwLock::new(Keypair::generate()?),
-            first: Default::default(),
+            signing_key: RwLock::new(sign::Keypair::generate()?),
             key_map: Default::default(),
+            decisions: Default::default(),
+            keystore: Default::default(),
+            passphrase_rooms: Default::default(),
+            trust: Default::default(),
         })
     }
 }
 
 lazy_static! {
+    /// Everything but the token, requests are made with [`api_configuration`].
     pub static ref CONFIGURATION: Arc<RwLock<Configuration>> =
         Arc::new(RwLock::new(Configuration::new()));
+    /// The bearer token of the session, only copied out of the `Secret` for a request.
+    pub static ref TOKEN: Arc<RwLock<Option<Secret>>> = Default::default();
+    pub static ref NETWORK_CONFIG: Arc<RwLock<NetworkConfig>> = Default::default();
     pub static ref USER: Arc<RwLock<Option<UserPrivate>>> = Arc::new(RwLock::new(None));
     pub static ref USERNAME: Arc<std::sync::RwLock<Option<String>>> =
         Arc::new(std::sync::RwLock::new(None));
-    pub static ref LISTEN_TASK: Arc<RwLock<Option<ListenData>>> = Arc::new(RwLock::new(None));
+    pub static ref USER_TYPE: Arc<std::sync::RwLock<Option<UserType>>> =
+        Arc::new(std::sync::RwLock::new(None));
+    pub static ref SESSIONS: Arc<RwLock<HashMap<String, ListenData>>> = Default::default();
     pub static ref ACTION_TX: Arc<RwLock<UnboundedSender<Action>>> =
         Arc::new(RwLock::new(unbounded_channel().0));
     pub static ref KEYS: Arc<KeyData> = Arc::new(
         KeyData::new()
             .expect("Cannot create Keys for encryption, there is no way to disable this crash.")
     );
+    pub static ref ATTACHMENTS: Arc<RwLock<attachments::Incoming>> = Default::default();
+    /// Set once the keystore is unlocked or created, the history is encrypted with its key.
+    pub static ref HISTORY: Arc<RwLock<Option<history::History>>> = Default::default();
+}
+
+#[cfg(feature = "websocket")]
+lazy_static! {
+    /// TLS settings for WebSocket connections, `None` uses the system defaults.
+    pub static ref TLS_CONNECTOR: Arc<RwLock<Option<native_tls::TlsConnector>>> =
+        Default::default();
 }
 
 #[tracing::instrument]
-pub async fn init(config: Cli, action_tx: UnboundedSender<Action>) -> Result<()> {
-    KEYS.first.store(false, Ordering::Relaxed);
+pub async fn init(
+    config: Cli,
+    network_config: NetworkConfig,
+    action_tx: UnboundedSender<Action>,
+) -> Result<()> {
     *ACTION_TX.write().await = action_tx;
-    let mut client = CONFIGURATION.write().await;
-    if config.accept_invalid_certificate {
-        client.client = reqwest::ClientBuilder::new()
-            .danger_accept_invalid_certs(true)
-            .build()?;
-    }
-    let response = users_api::users_online(&client, None).await?;
-    client.bearer_access_token = Some(response.token.token);
-    let user = users_api::users_get_me(&client).await?;
+    let tls = tls::TlsSettings::load(&network_config, config.accept_invalid_certificate)?;
+    // a size that doesn't fit is a config error, not one of the first attachment
+    network_config.max_attachment_size()?;
+    {
+        let mut client = CONFIGURATION.write().await;
+        client.base_path = network_config
+            .host
+            .as_str()
+            .trim_end_matches('/')
+            .to_owned();
+        client.client = tls.http_client()?;
+    }
+    #[cfg(feature = "websocket")]
+    {
+        *TLS_CONNECTOR.write().await = Some(tls.tls_connector()?);
+    }
+    *NETWORK_CONFIG.write().await = network_config;
+    *KEYS.decisions.write().await =
+        approval::KeyDecisions::load(crate::config::get_data_dir().join(TRUSTED_KEYS_FILE))?;
+    *KEYS.trust.write().await =
+        trust::TrustStore::load(crate::config::get_data_dir().join(IDENTITIES_FILE))?;
+    let response = users_api::users_online(&api_configuration().await, None).await?;
+    *TOKEN.write().await = Some(Secret::new(response.token.token));
+    let user = users_api::users_get_me(&api_configuration().await).await?;
     debug!("{:#?}", user);
     update_user(user).await;
     Ok(())
@@ -97,13 +346,16 @@ pub async fn update_user(new_user: UserPrivate) {
     if let Ok(mut user) = USERNAME.write() {
         *user = new_user.username.clone();
     }
+    if let Ok(mut user_type) = USER_TYPE.write() {
+        *user_type = new_user.user_type;
+    }
 }
 
 pub async fn handle_actions(event: Action) -> Result<Option<Action>> {
     match event {
-        Action::Leave => {
-            let mut task = LISTEN_TASK.write().await;
-            if let Some(task) = task.take() {
+        Action::Leave(room) => {
+            if let Some(task) = SESSIONS.write().await.remove(&room) {
+                debug!("Leaving room {}", task.session.room);
                 task.thread.abort();
             }
         }
@@ -117,74 +369,328 @@ pub async fn handle_actions(event: Action) -> Result<Option<Action>> {
             login(&username, &password).await?;
             return Ok(Some(Action::OpenHome));
         }
+        Action::JoinWithPassphrase(room, passphrase) => {
+            join_with_passphrase(&room, passphrase).await?;
+        }
+        Action::JoinWithKey(room, shared) => {
+            // mistyped words keep the import screen open
+            if let Err(e) = join_with_key(&room, &shared).await {
+                return Ok(Some(Action::Error(e.into())));
+            }
+        }
         Action::PerformJoin(room) => {
             join(&room).await?;
         }
-        Action::SendMessage(msg) => {
-            send_message(&msg).await?;
+        Action::JoinRandom => {
+            let room = random_room().await?;
+            join(&room).await?;
+            send_system_message(&room, format!("Joined random room {room}")).await;
+        }
+        Action::RefreshRooms => {
+            let (public, mine) = list_rooms().await?;
+            return Ok(Some(Action::RoomList(public, mine)));
+        }
+        Action::CreateRoom(room, mut data) => {
+            data.key = server_key(&room, data.key).await?;
+            let conf = api_configuration().await;
+            rooms_api::rooms_create_room(&conf, &room, data).await?;
+            return Ok(Some(Action::OpenRooms));
+        }
+        Action::UpdateRoom(room, mut data) => {
+            data.key = server_key(&room, data.key).await?;
+            let conf = api_configuration().await;
+            rooms_api::rooms_update_room(&conf, &room, data).await?;
+            return Ok(Some(Action::OpenRooms));
+        }
+        Action::DeleteRoom(room) => {
+            let conf = api_configuration().await;
+            rooms_api::rooms_delete_room(&conf, &room).await?;
+            return Ok(Some(Action::OpenRooms));
+        }
+        Action::SendMessage(room, msg) => {
+            send_message(&room, &msg).await?;
+        }
+        Action::SendFile(room, path) => {
+            // a large file would hold up every other action until it is sent
+            tokio::spawn(async move {
+                if let Err(e) = send_file(&room, &path).await {
+                    error!("Sending {} failed: {}", path.display(), e);
+                    let _ = ACTION_TX.read().await.send(Action::Error(e.into()));
+                }
+            });
+        }
+        Action::SaveAttachment(room, transfer) => {
+            let dir = NETWORK_CONFIG.read().await.downloads_dir();
+            // a failed save keeps the chat open
+            return Ok(Some(
+                match ATTACHMENTS.write().await.save(&transfer, &dir) {
+                    Ok(view) => Action::Attachment(
+                        room,
+                        Message {
+                            content: view.name.clone(),
+                            attachment: Some(view),
+                            ..Default::default()
+                        },
+                    ),
+                    Err(e) => Action::Error(e.into()),
+                },
+            ));
+        }
+        Action::DismissAttachment(room, transfer) => {
+            return Ok(Some(match ATTACHMENTS.write().await.dismiss(&transfer) {
+                Ok(view) => Action::Attachment(
+                    room,
+                    Message {
+                        content: view.name.clone(),
+                        attachment: Some(view),
+                        ..Default::default()
+                    },
+                ),
+                Err(e) => Action::Error(e.into()),
+            }));
+        }
+        Action::RotateKey(room) => {
+            let session = SESSIONS
+                .read()
+                .await
+                .get(&room)
+                .map(|task| task.session.clone())
+                .ok_or_eyre("You Havent Joined this room")?;
+            rotate_key(&session).await?;
+        }
+        Action::LoadHistory(room, first, end) => {
+            let history = HISTORY.read().await;
+            let Some(history) = history.as_ref() else {
+                return Ok(None);
+            };
+            // a damaged file shouldn't close the chat
+            return Ok(Some(
+                match history.page(&room, end, end.saturating_sub(first)) {
+                    Ok((records, start)) => Action::History(
+                        room,
+                        records.into_iter().map(|record| record.message).collect(),
+                        start,
+                    ),
+                    Err(e) => Action::Error(e.into()),
+                },
+            ));
+        }
+        Action::Search(query) => {
+            let history = HISTORY.read().await;
+            // a broken query was already reported by the search screen
+            let (Some(history), Ok(matcher)) = (history.as_ref(), query.matcher()) else {
+                return Ok(None);
+            };
+            return Ok(Some(match history.search(&matcher) {
+                Ok(hits) => Action::SearchResults(query, hits),
+                Err(e) => Action::Error(e.into()),
+            }));
+        }
+        Action::ShareKey(room) => {
+            return Ok(Some(match share_key(&room).await {
+                Ok(action) => action,
+                Err(e) => Action::Error(e.into()),
+            }));
+        }
+        Action::UnlockKeystore(passphrase) => {
+            // a wrong passphrase keeps the prompt open instead of going home
+            return Ok(Some(match unlock_keystore(passphrase).await {
+                Ok(report) => {
+                    debug!("{report}");
+                    Action::OpenHome
+                }
+                Err(e) => Action::Error(e.into()),
+            }));
+        }
+        Action::OpenVerify => return Ok(Some(identities().await?)),
+        Action::AcceptIdentity(username) => {
+            KEYS.trust.write().await.accept(&username)?;
+            return Ok(Some(identities().await?));
+        }
+        Action::VerifyIdentity(username) => {
+            KEYS.trust.write().await.set_verified(&username)?;
+            return Ok(Some(identities().await?));
+        }
+        Action::AnswerKeyRequest(prompt, decision) => {
+            answer_key_request(prompt, decision).await?;
         }
         _ => {}
     }
     Ok(None)
 }
 
-#[tracing::instrument]
-async fn join(room: &str) -> Result<()> {
-    let mut listen_task = LISTEN_TASK.write().await;
-    if listen_task.is_none() {
-        let room = Arc::new(room.to_owned());
-        let thread_room = room.clone();
-        let task = ListenData {
-            thread: tokio::task::spawn(async { listen(thread_room).await }),
-            room,
-        };
-        *listen_task = Some(task);
+/// Public rooms and the rooms of the current user, guests have no rooms of their own.
+async fn list_rooms() -> Result<(Vec<StaticRoomPublic>, Vec<StaticRoomPublic>)> {
+    let conf = api_configuration().await;
+    let public = rooms_api::rooms_list_rooms(&conf).await?;
+    let mine = match rooms_api::rooms_get_my_rooms(&conf).await {
+        Ok(mine) => mine,
+        Err(e) => {
+            debug!("Cannot list own rooms: {e}");
+            Vec::new()
+        }
+    };
+    Ok((public, mine))
+}
+
+/// Asks the server for the name of an unused room.
+async fn random_room() -> Result<String> {
+    let conf = api_configuration().await;
+    match rooms_api::rooms_random_room(&conf).await? {
+        serde_json::Value::String(room) if !room.is_empty() => Ok(room),
+        other => Err(eyre!("Server sent no usable random room: {other}").into()),
     }
-    Ok(())
 }
 
 #[tracing::instrument]
-async fn listen(room: Arc<String>) -> Result<()> {
-    let conf = CONFIGURATION.read().await.clone();
-    let mut stream = rooms_api::rooms_listen(&conf, &room).await?;
+async fn join(room: &str) -> Result<()> {
+    let address = RoomAddress::from_str(room)?;
+    let room = &address.to_string();
+    let mut sessions = SESSIONS.write().await;
     let action_tx = ACTION_TX.read().await.clone();
-    let _ = action_tx.send(Action::OpenChat);
-    debug!("Starting listening on room: {}", room);
+    if sessions
+        .get(room)
+        .is_some_and(|task| !task.thread.is_finished())
     {
-        let key_map = KEYS.key_map.read().await;
-        let key = key_map.get(&*room);
-        let key2 = key.and_then(|key| Key::try_from(key.as_ref()).ok());
-        if key2.is_some() {
-            let mut key_write = KEYS.symetric_key.write().await;
-            *key_write = key2;
+        debug!("Already in room {}", room);
+        let _ = action_tx.send(Action::OpenRoom(room.to_owned()));
+        return Ok(());
+    }
+    let _ = action_tx.send(Action::ConnectionState(
+        room.to_owned(),
+        ConnectionState::Connecting,
+    ));
+    let kind = NETWORK_CONFIG.read().await.transport;
+    let conf = api_configuration().await;
+    let connection = match transport::connect(kind, &conf, &address).await {
+        Ok(connection) => connection,
+        Err(e) => {
+            let _ = action_tx.send(Action::ConnectionState(
+                room.to_owned(),
+                ConnectionState::Failed,
+            ));
+            return Err(e);
         }
+    };
+    let session = Arc::new(RoomSession::new(room, connection.outgoing));
+    if let Some(keys) = KEYS.key_map.read().await.get(room) {
+        *session.keys.write().await = Some(keys.try_clone()?);
+        let _ = action_tx.send(Action::RoomEncrypted(room.to_owned()));
     }
-    while let Some(Ok(msg)) = stream.next().await {
-        debug!("Received message: {:#?}", msg);
+    // before listening, so the stored messages come before the new ones
+    if let Err(e) = load_history(room).await {
+        error!("Cannot load the history of {}: {}", room, e);
+    }
+    if let Some(minutes) = NETWORK_CONFIG.read().await.rotate_key_minutes {
+        let every = Duration::from_secs(minutes.max(1) * 60);
+        tokio::task::spawn(rotate_periodically(Arc::downgrade(&session), every));
+    }
+    let thread_session = session.clone();
+    let incoming = connection.incoming;
+    let reconnects = connection.reconnects;
+    let task = ListenData {
+        thread: tokio::task::spawn(
+            async move { listen(thread_session, incoming, reconnects).await },
+        ),
+        session,
+    };
+    sessions.insert(room.to_owned(), task);
+    Ok(())
+}
 
-        if let reqwest_eventsource::Event::Message(event) = msg {
-            match serde_json::from_str::<MessagePublic>(&event.data) {
+#[tracing::instrument(skip(session, stream), fields(room = session.room))]
+async fn listen(
+    session: Arc<RoomSession>,
+    mut stream: futures::stream::BoxStream<'static, Result<transport::Incoming>>,
+    reconnects: bool,
+) -> Result<()> {
+    let room = session.room.clone();
+    let action_tx = ACTION_TX.read().await.clone();
+    let _ = action_tx.send(Action::OpenRoom(room.clone()));
+    debug!("Starting listening on room: {}", room);
+    let mut seen = SeenMessages::default();
+    let mut attempt = 0;
+    let mut last_error = None;
+    while let Some(incoming) = stream.next().await {
+        let data = match incoming {
+            Ok(transport::Incoming::Open) => {
+                attempt = 0;
+                let _ =
+                    action_tx.send(Action::ConnectionState(room.clone(), ConnectionState::Live));
+                if session.keys.read().await.is_some()
+                    && let Err(e) = send_hello(&session, false).await
+                {
+                    warn!("Cannot announce our keys to {}: {}", room, e);
+                }
+                continue;
+            }
+            Ok(transport::Incoming::Message(data)) => data,
+            Err(e) => {
+                warn!("Connection to room {} interrupted: {}", room, e);
+                last_error = Some(e);
+                if !reconnects {
+                    break;
+                }
+                attempt += 1;
+                let _ = action_tx.send(Action::ConnectionState(
+                    room.clone(),
+                    ConnectionState::Reconnecting(attempt),
+                ));
+                continue;
+            }
+        };
+        if !seen.insert(&data) {
+            debug!("Dropping replayed message");
+            continue;
+        }
+        debug!("Received message: {:#?}", data);
+        {
+            match serde_json::from_str::<MessagePublic>(&data) {
                 Ok(message) => {
                     debug!("Parsed Content: {:#?}", message);
                     let mut received_message = Message {
+                        event: RoomEvent::from_message(&message),
+                        encrypted: matches!(
+                            message.content,
+                            Some(Content::Encrypted(_) | Content::FileChunk(_))
+                        ),
                         user: message.sender,
                         send_at: message
                             .send_at
                             .and_then(|send_at| DateTime::<Utc>::from_str(&send_at).ok()),
-                        content: Default::default(),
+                        ..Default::default()
                     };
+                    match &message.content {
+                        Some(Content::Encrypted(encrypted)) => {
+                            received_message.signature =
+                                verify_sender(&room, &received_message, encrypted).await;
+                        }
+                        Some(Content::FileChunk(chunk)) => {
+                            let encrypted = attachments::as_encrypted(chunk);
+                            received_message.signature =
+                                verify_sender(&room, &received_message, &encrypted).await;
+                        }
+                        _ => {}
+                    }
                     match message.content {
-                        Some(content) => match handle_content(&room, content).await {
-                            Err(err) => {
-                                error!("Failed to handle content: {}", err);
-                                let _ = action_tx.send(Action::Error(err.into()));
-                            }
-                            Ok(Some(content)) => {
-                                received_message.content = content;
-                                let _ = action_tx.send(Action::ReceivedMessage(received_message));
+                        Some(content) => {
+                            match handle_content(&session, &mut received_message, content).await {
+                                Err(err) => {
+                                    error!("Failed to handle content: {}", err);
+                                    let _ = action_tx.send(Action::Error(err.into()));
+                                }
+                                Ok(Some(content)) => {
+                                    received_message.content = content;
+                                    if record_history(&room, &received_message).await {
+                                        let _ = action_tx.send(Action::ReceivedMessage(
+                                            room.clone(),
+                                            received_message,
+                                        ));
+                                    }
+                                }
+                                Ok(_) => {}
                             }
-                            Ok(_) => {}
-                        },
+                        }
                         None => {
                             error!("Received message with no content",);
                         }
@@ -196,52 +702,76 @@ async fn listen(room: Arc<String>) -> Result<()> {
                     let _ = action_tx.send(Action::Error(err.into()));
                 }
             }
-        } else {
-            error!("Unexpected message type received: {:#?}", msg);
         }
     }
+    let _ = action_tx.send(Action::ConnectionState(
+        room.clone(),
+        ConnectionState::Failed,
+    ));
+    let err = match last_error {
+        Some(e) => eyre!("Lost connection to room {}: {}", room, e),
+        None => eyre!("Lost connection to room {}", room),
+    };
+    let _ = action_tx.send(Action::Error(error::NetworkError::from(err).into()));
     Ok(())
 }
 
-async fn handle_content(room: &str, content: Content) -> Result<Option<String>> {
+async fn handle_content(
+    session: &RoomSession,
+    meta: &mut Message,
+    content: Content,
+) -> Result<Option<String>> {
+    if meta.event == Some(RoomEvent::Leave) {
+        // the server announces it without a sender, the name is in the text
+        let member = meta
+            .user
+            .as_ref()
+            .and_then(|user| user.username.clone())
+            .or_else(|| match &content {
+                Content::Leave(leave) => event_member(&leave.content).map(str::to_owned),
+                Content::System(system) => event_member(&system.content).map(str::to_owned),
+                _ => None,
+            });
+        member_left(session, meta, member.as_deref()).await?;
+    }
     match content {
         Content::Encrypted(encrypted) => {
-            let key = KEYS.symetric_key.read().await;
-            match key.as_ref() {
-                Some(key) => {
-                    let mut nonce = [0u8; NONCE_LENGTH];
-                    let nonce_vec = from_base64(&encrypted.nonce)?;
-                    let msg_vec = from_base64(&encrypted.content_base64)?;
-                    let mut x = vec![0u8; msg_vec.len() - symetric_cipher::MAC_LENGTH];
-                    nonce.copy_from_slice(&nonce_vec);
-                    match symetric_cipher::decrypt(&msg_vec, key, &nonce, &mut x) {
-                        Ok(_) => {
-                            let msg = str::from_utf8(&x)?;
-                            debug!("Decrypted message content: {}", msg);
-                            return Ok(Some(msg.to_owned()));
-                        }
-                        Err(e) => {
-                            error!("{e}");
-                            let _ = ACTION_TX
-                                .read()
+            // released before unsealing, a hello is answered with the same key
+            let decrypted = session
+                .keys
+                .read()
+                .await
+                .as_ref()
+                .map(|keys| decrypt_message(keys, &encrypted));
+            match decrypted {
+                Some(decrypted) => match decrypted {
+                    Ok(msg) => {
+                        debug!("Decrypted {} bytes", msg.len());
+                        return unseal(session, meta, &encrypted, msg).await;
+                    }
+                    Err(e) => {
+                        error!("{e}");
+                        let _ = ACTION_TX.read().await.clone().send(Action::Error(e.into()));
+                        if !session.holds_key.load(Ordering::Relaxed) {
+                            session
+                                .pending
+                                .write()
                                 .await
-                                .clone()
-                                .send(Action::Error(error::NetworkError::from(e).into()));
-                            if KEYS.first.load(Ordering::Relaxed) {
-                                //   let mut key = KEYS.symetric_key.write().await;
-                                //   if key.is_none() {
-                                //       *key = Some(Key::generate()?);
-                                //   }
-                            } else {
-                                let key_pair = KEYS.asymetric_key.read().await;
-                                let msg = KeyRequest::new(to_base64(&key_pair.public_key));
-                                send_message_from_content(Content::KeyRequest(msg)).await?;
+                                .push((meta.clone(), encrypted));
+                            // a passphrase room key is only ever derived, asking won't fix it
+                            if !KEYS.passphrase_rooms.read().await.contains(&session.room) {
+                                request_key(session).await?;
                             }
                         }
                     }
-                }
+                },
                 None => {
-                    error!("Symmetric key not found for decryption.");
+                    debug!("Symmetric key not found for decryption, keeping message for later.");
+                    session
+                        .pending
+                        .write()
+                        .await
+                        .push((meta.clone(), encrypted));
                 }
             }
         }
@@ -251,62 +781,765 @@ async fn handle_content(room: &str, content: Content) -> Result<Option<String>>
         }
         Content::System(system_message) => {
             debug!("Received system message: {}", system_message.content);
-            if system_message.online_users >= 1 {
-                debug!("Last to join,requesting Key");
-                let key_pair = KEYS.asymetric_key.read().await;
-                let msg = KeyRequest::new(to_base64(&key_pair.public_key));
-                send_message_from_content(Content::KeyRequest(msg)).await?;
-                KEYS.first.store(false, Ordering::Relaxed);
+            let own = USERNAME.read().ok().and_then(|me| me.clone());
+            let someone_else = meta.event == Some(RoomEvent::Join)
+                && event_member(&system_message.content)
+                    .zip(own.as_deref())
+                    .is_some_and(|(member, own)| member != own);
+            if someone_else {
+                debug!("Someone else joined, the key stays where it is");
+            } else if system_message.online_users >= 1 {
+                if session.keys.read().await.is_none() {
+                    debug!("Last to join,requesting Key");
+                    request_key(session).await?;
+                }
             } else {
                 debug!("First to join, generating Key");
-                KEYS.first.store(true, Ordering::Relaxed);
-                let mut key = KEYS.symetric_key.write().await;
-                if key.is_none() {
-                    let new_key = Key::generate()?;
-                    *key = Some((&new_key.clone()).try_into()?);
-                    let mut key_map = KEYS.key_map.write().await;
-                    key_map.insert(room.to_string(), new_key);
+                session.holds_key.store(true, Ordering::Relaxed);
+                let mut keys = session.keys.write().await;
+                if keys.is_none() {
+                    let new_keys = RoomKeys::new(Key::generate()?, 0);
+                    *keys = Some(new_keys.try_clone()?);
+                    store_room_keys(&session.room, new_keys).await?;
+                    let _ = ACTION_TX
+                        .read()
+                        .await
+                        .send(Action::RoomEncrypted(session.room.clone()));
                 }
             }
             return Ok(Some(system_message.content));
         }
-        Content::KeyResponse(_) => {
-            //let _key_pair = KEY_PAIR.read().await;
-            //let mut _key = SYMETRIC_KEY.write().await;
+        Content::Join(join) => return Ok(Some(join.content)),
+        Content::Leave(leave) => return Ok(Some(leave.content)),
+        Content::FileChunk(chunk) => receive_chunk(session, meta, &chunk).await?,
+        Content::Unknown => {
+            debug!("Ignoring content of a type this client does not know");
         }
-        Content::KeyRequest(request_content) => {
-            let key = KEYS.symetric_key.read().await;
-            if let Some(key) = key.as_ref() {
-                let mut public_key: PublicKey = [0u8; PUBLIC_KEY_LENGTH];
-                let public_key_vec = from_base64(&request_content.public_key)?;
-                public_key.copy_from_slice(public_key_vec.as_slice());
+        Content::KeyResponse(response) => {
+            let decoded = payload::decode_key_response(&response)?;
+            // also checked for responses meant for others, they publish the sender's signing key
+            let state = trust_identity(
+                session,
+                meta,
+                &response.sender_public_key,
+                response.signing_key.as_deref(),
+            )
+            .await?;
+            let trusted = state.is_some();
+            let epoch = decoded.epoch;
+            let current = session.keys.read().await.as_ref().map(|keys| keys.epoch);
+            let rotation = current.is_some_and(|current| epoch > current);
+            if !session.requested.load(Ordering::Relaxed) {
+                if !rotation {
+                    debug!("Ignoring key response, no key was requested");
+                    return Ok(None);
+                }
+                // unasked, only the next epoch from whoever sent us the current key
+                let from_distributor = state == Some(trust::TrustState::Known)
+                    && session.distributor.read().await.as_deref()
+                        == Some(response.sender_public_key.as_str());
+                if !from_distributor
+                    || current.is_some_and(|current| epoch != current + 1)
+                    || KEYS.passphrase_rooms.read().await.contains(&session.room)
+                {
+                    debug!("Ignoring a rotated key that wasn't sent by the key distributor");
+                    return Ok(None);
+                }
+            }
+            let key = {
                 let key_pair = KEYS.asymetric_key.read().await;
-                let mut ciphertext = vec![0u8; key.len() + cipher::MAC_LENGTH];
-                let (_, nonce) =
-                    key_pair.encrypt(key.as_slice(), &public_key, None, &mut ciphertext)?;
-                let encrypted_key_str = to_base64(&ciphertext);
-                let my_public_key_str = to_base64(&key_pair.public_key);
-                let nonse_str = to_base64(&nonce);
-                let mut ciphertext = vec![0u8; key.len() + cipher::MAC_LENGTH];
-                symetric_cipher::encrypt("TEST".as_bytes(), key, None, &mut ciphertext)?;
-                let test_msg = to_base64(&ciphertext);
-                let key_response =
-                    KeyResponse::new(encrypted_key_str, test_msg, my_public_key_str, nonse_str);
-                send_message_from_content(Content::KeyResponse(key_response)).await?;
+                open_key_response(&key_pair, &decoded)
+            };
+            match key {
+                Ok(_) if !trusted => {
+                    debug!("Ignoring room key from an untrusted identity");
+                }
+                Ok(key) => {
+                    let sender = meta
+                        .user
+                        .as_ref()
+                        .and_then(|user| user.username.clone())
+                        .unwrap_or("unknown user".to_owned());
+                    let (decrypted, failed) = install_key(session, key, epoch).await?;
+                    // someone else holds the key now, they rotate it from here on
+                    session.holds_key.store(false, Ordering::Relaxed);
+                    *session.distributor.write().await = Some(response.sender_public_key.clone());
+                    let mut report = if rotation {
+                        format!("{sender} rotated the room key")
+                    } else {
+                        format!("Received room key from {sender}")
+                    };
+                    if decrypted + failed > 0 {
+                        report.push_str(&format!(", decrypted {decrypted} pending messages"));
+                    }
+                    if failed > 0 {
+                        report.push_str(&format!(", {failed} could not be decrypted"));
+                    }
+                    send_system_message(&session.room, report).await;
+                }
+                Err(error::NetworkError::AlkaliError(e)) => {
+                    // Every member sees every response, most of them are meant for someone else.
+                    debug!("Key response not addressed to us: {e}");
+                }
+                Err(e) => {
+                    error!("Invalid key response: {e}");
+                    send_system_message(&session.room, format!("Key exchange failed: {e}")).await;
+                    return Err(e);
+                }
             }
         }
+        Content::KeyRequest(request_content) => {
+            if session.keys.read().await.is_none() {
+                return Ok(None);
+            }
+            if KEYS.passphrase_rooms.read().await.contains(&session.room) {
+                debug!("Not handing out the key of a passphrase room");
+                return Ok(None);
+            }
+            let public_key = payload::decode_key_request(&request_content)?.public_key;
+            if public_key == KEYS.asymetric_key.read().await.public_key {
+                return Ok(None);
+            }
+            let trusted = trust_identity(
+                session,
+                meta,
+                &request_content.public_key,
+                request_content.signing_key.as_deref(),
+            )
+            .await?;
+            if trusted.is_none() {
+                debug!("Not sharing the room key with an untrusted identity");
+                return Ok(None);
+            }
+            if NETWORK_CONFIG.read().await.approve_key_requests {
+                let decision = KEYS
+                    .decisions
+                    .read()
+                    .await
+                    .get(&session.room, &to_base64(&public_key));
+                match decision {
+                    Some(KeyDecision::AlwaysTrust) => {}
+                    Some(KeyDecision::Deny) => {
+                        debug!("Ignoring key request from a denied key");
+                        return Ok(None);
+                    }
+                    Some(KeyDecision::Approve) | None => {
+                        let prompt = KeyRequestPrompt {
+                            room: session.room.clone(),
+                            username: meta.user.as_ref().and_then(|user| user.username.clone()),
+                            public_key: to_base64(&public_key),
+                            fingerprint: approval::fingerprint(&public_key)?,
+                        };
+                        let _ = ACTION_TX
+                            .read()
+                            .await
+                            .send(Action::KeyRequestPrompt(prompt));
+                        return Ok(None);
+                    }
+                }
+            }
+            send_key_response(session, &public_key).await?;
+        }
     }
     Ok(None)
 }
 
-#[tracing::instrument]
-async fn send_message_from_content(message_content: Content) -> Result<()> {
+/// Checks the key a user presented against the trust store, warns the room if it changed.
+///
+/// Returns the trust state if keys may be exchanged with it. Key material without a sender name can't be
+/// checked against the trust store and is refused, otherwise the server could hand out its own
+/// keys by leaving the sender out. The signing key published with a trusted key is remembered to
+/// verify messages.
+async fn trust_identity(
+    session: &RoomSession,
+    meta: &Message,
+    public_key: &str,
+    signing_key: Option<&str>,
+) -> Result<Option<trust::TrustState>> {
+    if public_key == to_base64(&KEYS.asymetric_key.read().await.public_key) {
+        return Ok(Some(trust::TrustState::Known));
+    }
+    let Some(username) = meta.user.as_ref().and_then(|user| user.username.as_deref()) else {
+        warn!("Ignoring keys without a sender in {}", session.room);
+        return Ok(None);
+    };
+    let mut trust = KEYS.trust.write().await;
+    let state = trust.observe(username, public_key)?;
+    match state {
+        trust::TrustState::New | trust::TrustState::Known => {
+            if let Some(signing_key) = signing_key
+                && !trust.learn_signing_key(username, public_key, signing_key)?
+            {
+                warn!(
+                    "{} published a different signing key, keeping the known one",
+                    username
+                );
+            }
+            session
+                .members
+                .write()
+                .await
+                .insert(public_key.to_owned(), username.to_owned());
+            Ok(Some(state))
+        }
+        trust::TrustState::Changed | trust::TrustState::Unaccepted => {
+            let message = Message {
+                content: format!(
+                    "The identity key of {username} changed to {}. Key sharing with {username} is \
+                     blocked until you accept the new key on the verification screen.",
+                    trust::key_fingerprint(public_key)?
+                ),
+                event: Some(RoomEvent::KeyChanged),
+                ..Default::default()
+            };
+            let _ = ACTION_TX
+                .read()
+                .await
+                .send(Action::ReceivedMessage(session.room.clone(), message));
+            Ok(None)
+        }
+    }
+}
+
+/// Checks the signature of an encrypted message against the signing key known for its sender.
+async fn verify_sender(
+    room: &str,
+    meta: &Message,
+    encrypted: &Encrypted,
+) -> signing::SignatureState {
+    let Some(username) = meta.user.as_ref().and_then(|user| user.username.as_deref()) else {
+        return signing::SignatureState::Unverified;
+    };
+    let own = USERNAME.read().ok().and_then(|me| me.clone());
+    let signing_key = if own.as_deref() == Some(username) {
+        Some(to_base64(&KEYS.signing_key.read().await.public_key))
+    } else {
+        KEYS.trust
+            .read()
+            .await
+            .signing_key(username)
+            .map(str::to_owned)
+    };
+    signing::verify(signing_key.as_deref(), room, encrypted)
+}
+
+async fn identities() -> Result<Action> {
+    let own_key = to_base64(&KEYS.asymetric_key.read().await.public_key);
+    let views = KEYS.trust.read().await.views(&own_key)?;
+    Ok(Action::Identities(trust::key_fingerprint(&own_key)?, views))
+}
+
+async fn send_key_response(session: &RoomSession, receiver: &PublicKey) -> Result<()> {
+    let key_response = {
+        let keys = session.keys.read().await;
+        let keys = keys.as_ref().ok_or_eyre("No room key to share")?;
+        let key_pair = KEYS.asymetric_key.read().await;
+        let mut response = create_key_response(&key_pair, &keys.current, receiver)?;
+        response.key_epoch = Some(keys.epoch);
+        response.signing_key = Some(to_base64(&KEYS.signing_key.read().await.public_key));
+        response
+    };
+    send_message_from_content(session, Content::KeyResponse(key_response)).await
+}
+
+/// Records the user's answer to a key request and sends the room key if it was granted.
+async fn answer_key_request(prompt: KeyRequestPrompt, decision: KeyDecision) -> Result<()> {
+    KEYS.decisions
+        .write()
+        .await
+        .set(&prompt.room, &prompt.public_key, decision)?;
+    if decision == KeyDecision::Deny {
+        return Ok(());
+    }
+    let session = SESSIONS
+        .read()
+        .await
+        .get(&prompt.room)
+        .map(|task| task.session.clone())
+        .ok_or_eyre("You Havent Joined this room")?;
+    let public_key = decode_public_key(&prompt.public_key)?;
+    send_key_response(&session, &public_key).await?;
+    let requester = prompt.username.as_deref().unwrap_or("unknown user");
+    send_system_message(&prompt.room, format!("Sent room key to {requester}")).await;
+    Ok(())
+}
+
+async fn request_key(session: &RoomSession) -> Result<()> {
+    let msg = {
+        let key_pair = KEYS.asymetric_key.read().await;
+        let mut request = KeyRequest::new(to_base64(&key_pair.public_key));
+        request.signing_key = Some(to_base64(&KEYS.signing_key.read().await.public_key));
+        request
+    };
+    session.requested.store(true, Ordering::Relaxed);
+    send_message_from_content(session, Content::KeyRequest(msg)).await
+}
+
+/// Remembers the keys of `room`, in the keystore too if it is unlocked.
+async fn store_room_keys(room: &str, keys: RoomKeys) -> Result<()> {
+    KEYS.key_map.write().await.insert(room.to_owned(), keys);
+    save_keystore().await
+}
+
+/// Replaces the room key with one in a new epoch and sends it to the members we know.
+///
+/// Members we never exchanged keys with fail to decrypt the next message and ask for it.
+async fn rotate_key(session: &RoomSession) -> Result<()> {
+    if KEYS.passphrase_rooms.read().await.contains(&session.room) {
+        return Err(eyre!(
+            "The key of {} is derived from its passphrase and can't be rotated",
+            session.room
+        )
+        .into());
+    }
+    let keys = {
+        let mut keys = session.keys.write().await;
+        let keys = keys
+            .as_mut()
+            .ok_or_else(|| eyre!("{} has no room key to rotate", session.room))?;
+        keys.rotate()?;
+        keys.try_clone()?
+    };
+    store_room_keys(&session.room, keys).await?;
+    session.holds_key.store(true, Ordering::Relaxed);
+    *session.distributor.write().await = None;
+
+    let members = session.members.read().await.clone();
+    let approve = NETWORK_CONFIG.read().await.approve_key_requests;
+    let mut sent = 0;
+    for public_key in members.keys() {
+        let public_key = decode_public_key(public_key)?;
+        let decision = KEYS
+            .decisions
+            .read()
+            .await
+            .get(&session.room, &to_base64(&public_key));
+        let allowed = match decision {
+            Some(KeyDecision::Deny) => false,
+            Some(KeyDecision::Approve | KeyDecision::AlwaysTrust) => true,
+            None => !approve,
+        };
+        if allowed {
+            send_key_response(session, &public_key).await?;
+            sent += 1;
+        }
+    }
+    send_system_message(
+        &session.room,
+        format!("Rotated the room key, sent it to {sent} members"),
+    )
+    .await;
+    Ok(())
+}
+
+/// The member a join or leave announcement is about, the server writes
+/// "User {name} joined the room".
+fn event_member(content: &str) -> Option<&str> {
+    let rest = content.strip_prefix("User ").unwrap_or(content);
+    let (name, _) = rest
+        .split_once(" joined")
+        .or_else(|| rest.split_once(" left"))?;
+    Some(name.trim()).filter(|name| !name.is_empty())
+}
+
+/// Forgets the `member` that left and rotates the key if we hold it, so they can't read on.
+async fn member_left(session: &RoomSession, meta: &Message, member: Option<&str>) -> Result<()> {
+    if meta
+        .send_at
+        .is_some_and(|send_at| send_at < session.joined_at)
+    {
+        return Ok(());
+    }
+    match member {
+        Some(username) => session
+            .members
+            .write()
+            .await
+            .retain(|_, known| known != username),
+        None => warn!("Cannot tell who left {}", session.room),
+    }
+    let rotate = session.holds_key.load(Ordering::Relaxed)
+        && !NETWORK_CONFIG.read().await.keep_key_on_leave
+        && !KEYS.passphrase_rooms.read().await.contains(&session.room)
+        && session.keys.read().await.is_some();
+    if rotate {
+        rotate_key(session).await?;
+    }
+    Ok(())
+}
+
+/// Rotates the room key every `every` while we hold it, until the session is gone.
+async fn rotate_periodically(session: std::sync::Weak<RoomSession>, every: Duration) {
+    loop {
+        tokio::time::sleep(every).await;
+        let Some(session) = session.upgrade() else {
+            return;
+        };
+        if session.holds_key.load(Ordering::Relaxed)
+            && let Err(e) = rotate_key(&session).await
+        {
+            warn!("Scheduled key rotation of {} failed: {}", session.room, e);
+        }
+    }
+}
+
+async fn save_keystore() -> Result<()> {
+    if let Some(keystore) = KEYS.keystore.read().await.as_ref() {
+        let key_pair = KEYS.asymetric_key.read().await;
+        let signing_key = KEYS.signing_key.read().await;
+        let key_map = KEYS.key_map.read().await;
+        let passphrase_rooms = KEYS.passphrase_rooms.read().await;
+        keystore.save(&key_pair, &signing_key, &key_map, &passphrase_rooms)?;
+    }
+    Ok(())
+}
+
+/// Unlocks the keystore and takes over its keys, or creates one holding the current keys.
+async fn unlock_keystore(passphrase: Passphrase) -> Result<String> {
+    let path = Keystore::default_path();
+    if !path.exists() {
+        let keystore = tokio::task::spawn_blocking(move || Keystore::create(path, &passphrase))
+            .await
+            .map_err(|e| eyre!("Keystore task failed: {}", e))??;
+        *KEYS.keystore.write().await = Some(keystore);
+        save_keystore().await?;
+        open_history().await?;
+        return Ok("Created keystore".to_owned());
+    }
+    let unlocked = tokio::task::spawn_blocking(move || Keystore::unlock(path, &passphrase))
+        .await
+        .map_err(|e| eyre!("Keystore task failed: {}", e))??;
+    let room_count = unlocked.rooms.len();
+    *KEYS.asymetric_key.write().await = unlocked.keypair;
+    *KEYS.signing_key.write().await = unlocked.signing_keypair;
+    KEYS.key_map.write().await.extend(unlocked.rooms);
+    KEYS.passphrase_rooms
+        .write()
+        .await
+        .extend(unlocked.passphrase_rooms);
+    *KEYS.keystore.write().await = Some(unlocked.keystore);
+    open_history().await?;
+    Ok(format!("Unlocked keystore with {room_count} room keys"))
+}
+
+/// Sets up the local history with the key of the keystore, without a keystore nothing is stored.
+async fn open_history() -> Result<()> {
+    if let Some(keystore) = KEYS.keystore.read().await.as_ref() {
+        let history =
+            history::History::new(history::History::default_dir(), keystore.history_key()?);
+        *HISTORY.write().await = Some(history);
+    }
+    Ok(())
+}
+
+/// Prunes the stored messages of `room` and sends the newest page of them to the chat.
+async fn load_history(room: &str) -> Result<()> {
+    let keep = NETWORK_CONFIG.read().await.history_retention(room);
+    let mut history = HISTORY.write().await;
+    let Some(history) = history.as_mut() else {
+        return Ok(());
+    };
+    let stored = history.open(room, keep, Utc::now())?;
+    if stored > 0 {
+        let (records, start) = history.page(room, stored, history::PAGE_SIZE)?;
+        let messages = records.into_iter().map(|record| record.message).collect();
+        let _ = ACTION_TX
+            .read()
+            .await
+            .send(Action::History(room.to_owned(), messages, start));
+    }
+    Ok(())
+}
+
+/// Stores a received message in the local history.
+///
+/// Returns `false` if it was stored before, like the backlog a server replays on join, which the
+/// chat already shows from the history.
+async fn record_history(room: &str, message: &Message) -> bool {
+    let mut history = HISTORY.write().await;
+    let Some(history) = history.as_mut().filter(|history| history.is_open(room)) else {
+        return true;
+    };
+    match history.append(room, message, Utc::now()) {
+        Ok(new) => new,
+        Err(e) => {
+            error!("Cannot store a message of {}: {}", room, e);
+            true
+        }
+    }
+}
+
+/// Joins a passphrase protected room with the key derived from `passphrase`.
+async fn join_with_passphrase(room: &str, passphrase: Passphrase) -> Result<()> {
+    let room = RoomAddress::from_str(room)?.to_string();
+    let key_room = room.clone();
+    let key = tokio::task::spawn_blocking(move || keystore::room_key(&key_room, &passphrase))
+        .await
+        .map_err(|e| eyre!("Key derivation failed: {}", e))??;
+    KEYS.passphrase_rooms.write().await.insert(room.clone());
+    let session = SESSIONS
+        .read()
+        .await
+        .get(&room)
+        .map(|task| task.session.clone());
+    match session {
+        // already joined, e.g. with a mistyped passphrase
+        Some(session) => {
+            install_key(&session, key, 0).await?;
+        }
+        None => store_room_keys(&room, RoomKeys::new(key, 0)).await?,
+    }
+    join(&room).await
+}
+
+/// Replaces the passphrase of a key protected room by a key derived from it, the server must
+/// not learn what the room key is derived from.
+async fn server_key(room: &str, key: Option<Option<String>>) -> Result<Option<Option<String>>> {
+    let Some(Some(passphrase)) = key else {
+        return Ok(key);
+    };
+    let room = RoomAddress::new_static(room).to_string();
+    let passphrase = Passphrase::new(passphrase);
+    let key = tokio::task::spawn_blocking(move || keystore::server_key(&room, &passphrase))
+        .await
+        .map_err(|e| eyre!("Key derivation failed: {}", e))??;
+    Ok(Some(Some(key)))
+}
+
+/// The current key of `room` as words and as QR code text.
+async fn share_key(room: &str) -> Result<Action> {
+    let key_map = KEYS.key_map.read().await;
+    let keys = key_map
+        .get(room)
+        .ok_or_else(|| eyre!("{} has no room key yet", room))?;
+    Ok(Action::SharedKey(
+        room.to_owned(),
+        key_share::words(room, keys)?,
+        key_share::code(room, keys)?,
+    ))
+}
+
+/// Joins `room` with a key shared out of band, it is installed before listening starts so no
+/// key request is sent.
+async fn join_with_key(room: &str, shared: &Secret) -> Result<()> {
+    let room = RoomAddress::from_str(room)?.to_string();
+    let (key, epoch) = key_share::import(&room, shared)?;
+    let session = SESSIONS
+        .read()
+        .await
+        .get(&room)
+        .map(|task| task.session.clone());
+    match session {
+        Some(session) => {
+            install_key(&session, key, epoch).await?;
+        }
+        None => {
+            // keep the epochs known from before
+            let keys = match KEYS.key_map.write().await.remove(&room) {
+                Some(mut keys) => {
+                    keys.install(key, epoch);
+                    keys
+                }
+                None => RoomKeys::new(key, epoch),
+            };
+            store_room_keys(&room, keys).await?;
+        }
+    }
+    join(&room).await
+}
+
+/// Installs `key` as the room key of `epoch` and decrypts all pending messages.
+///
+/// Returns how many pending messages could and could not be decrypted.
+async fn install_key(
+    session: &RoomSession,
+    key: Key<FullAccess>,
+    epoch: u64,
+) -> Result<(usize, usize)> {
+    let first_key = session.keys.read().await.is_none();
+    let keys = {
+        let mut keys = session.keys.write().await;
+        let installed = match keys.take() {
+            Some(mut keys) => {
+                keys.install(key, epoch);
+                keys
+            }
+            None => RoomKeys::new(key, epoch),
+        };
+        *keys = Some(installed.try_clone()?);
+        installed
+    };
+    store_room_keys(&session.room, keys.try_clone()?).await?;
+    session.requested.store(false, Ordering::Relaxed);
+    *session.distributor.write().await = None;
+    if first_key && let Err(e) = send_hello(session, false).await {
+        warn!("Cannot announce our keys to {}: {}", session.room, e);
+    }
+
+    let pending = std::mem::take(&mut *session.pending.write().await);
+    let action_tx = ACTION_TX.read().await.clone();
+    let _ = action_tx.send(Action::RoomEncrypted(session.room.clone()));
+    let mut decrypted = 0;
+    let mut failed = 0;
+    for (mut message, encrypted) in pending {
+        // the sender's signing key may have arrived with the room key
+        message.signature = verify_sender(&session.room, &message, &encrypted).await;
+        match decrypt_message(&keys, &encrypted) {
+            Ok(content) => {
+                decrypted += 1;
+                match unseal(session, &mut message, &encrypted, content).await {
+                    Ok(Some(content)) => {
+                        message.content = content;
+                        if record_history(&session.room, &message).await {
+                            let _ = action_tx
+                                .send(Action::ReceivedMessage(session.room.clone(), message));
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => error!("Rejecting pending message: {e}"),
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                error!("Pending message still not decryptable: {e}");
+            }
+        }
+    }
+    Ok((decrypted, failed))
+}
+
+async fn send_system_message(room: &str, content: String) {
+    let message = Message {
+        content,
+        ..Default::default()
+    };
+    let _ = ACTION_TX
+        .read()
+        .await
+        .send(Action::ReceivedMessage(room.to_owned(), message));
+}
+
+/// Opens the envelope of a decrypted message, checks the metadata sealed into it and returns its
+/// text, `None` for a replay.
+///
+/// The sealed send time replaces the one the server reported.
+async fn unseal(
+    session: &RoomSession,
+    meta: &mut Message,
+    encrypted: &Encrypted,
+    plaintext: Vec<u8>,
+) -> Result<Option<String>> {
+    let opened = envelope::open(&plaintext)?;
+    meta.envelope_version = opened.version;
+    if let Some(hello) = signing::Hello::from_body(&opened.body) {
+        hello_received(session, meta, encrypted, hello).await?;
+        return Ok(None);
+    }
+    let plaintext = String::from_utf8(opened.body).map_err(|e| e.utf8_error())?;
+    let Ok(sealed) = serde_json::from_str::<replay::Sealed>(&plaintext) else {
+        debug!("Message has no sealed metadata, it can't be checked for replays");
+        meta.freshness = replay::Freshness::Unbound;
+        return Ok(Some(plaintext));
+    };
+    let sender = meta.user.as_ref().and_then(|user| user.username.as_deref());
+    let checked = session
+        .replay
+        .write()
+        .await
+        .check(&sealed, sender, Utc::now())?;
+    let Some(freshness) = checked else {
+        warn!(
+            "Dropping replayed message {} from {:?}",
+            sealed.counter, sealed.sender
+        );
+        return Ok(None);
+    };
+    meta.freshness = freshness;
+    meta.send_at = Some(sealed.sent_at);
+    Ok(Some(sealed.text))
+}
+
+/// Decrypts with the key of the message's epoch, falling back to the other known keys.
+fn decrypt_message(keys: &RoomKeys, encrypted: &Encrypted) -> Result<Vec<u8>> {
+    let decoded = payload::decode_encrypted(encrypted)?;
+    let mut x = vec![0u8; decoded.plaintext_len()];
+    let epoch = decoded.epoch;
+    let mut last_error = None;
+    for key in keys.candidates(epoch) {
+        match symetric_cipher::decrypt(&decoded.ciphertext, key, &decoded.nonce, &mut x) {
+            Ok(len) => return Ok(x[..len].to_vec()),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(match last_error {
+        Some(e) => e.into(),
+        None => eyre!("No room key for epoch {}", epoch).into(),
+    })
+}
+
+/// Encrypts `key` for the owner of `receiver`.
+///
+/// `check_msg` is encrypted with the room key under the same nonce, so the receiver can tell a
+/// corrupted key from one that was meant for somebody else.
+fn create_key_response(
+    key_pair: &Keypair,
+    key: &Key<FullAccess>,
+    receiver: &PublicKey,
+) -> Result<KeyResponse> {
+    let mut ciphertext = vec![0u8; key.len() + cipher::MAC_LENGTH];
+    let (_, nonce) = key_pair.encrypt(key.as_slice(), receiver, None, &mut ciphertext)?;
+    let encrypted_key_str = to_base64(&ciphertext);
+    let my_public_key_str = to_base64(&key_pair.public_key);
+    let nonse_str = to_base64(&nonce);
+    let mut check_msg = vec![0u8; CHECK_MSG.len() + symetric_cipher::MAC_LENGTH];
+    symetric_cipher::encrypt(CHECK_MSG, key, Some(&nonce), &mut check_msg)?;
+    let test_msg = to_base64(&check_msg);
+    Ok(KeyResponse::new(
+        encrypted_key_str,
+        test_msg,
+        my_public_key_str,
+        nonse_str,
+    ))
+}
+
+/// Decrypts the room key from a `KeyResponse` and validates it against `check_msg`.
+///
+/// Fails with [`error::NetworkError::AlkaliError`] if the response wasn't encrypted for us.
+fn open_key_response(
+    key_pair: &Keypair,
+    response: &payload::DecodedKeyResponse,
+) -> Result<Key<FullAccess>> {
+    let mut key = Key::new_empty()?;
+    key_pair.decrypt(
+        &response.encrypted_key,
+        &response.sender,
+        &response.nonce,
+        key.as_mut(),
+    )?;
+
+    let mut plaintext = vec![0u8; response.check_msg.len() - symetric_cipher::MAC_LENGTH];
+    match symetric_cipher::decrypt(&response.check_msg, &key, &response.nonce, &mut plaintext) {
+        Ok(_) if plaintext == CHECK_MSG => Ok(key),
+        _ => Err(eyre!("check message doesn't match the received key").into()),
+    }
+}
+
+fn decode_public_key(public_key: &str) -> Result<PublicKey> {
+    Ok(payload::public_key("public_key", public_key)?)
+}
+
+#[tracing::instrument(skip(session), fields(room = session.room))]
+async fn send_message_from_content(session: &RoomSession, message_content: Content) -> Result<()> {
     let r#type = match message_content {
         Content::Encrypted(_) => MessageType::Encrypted,
         Content::KeyResponse(_) => MessageType::KeyResponse,
         Content::KeyRequest(_) => MessageType::KeyRequest,
         Content::Plaintext(_) => MessageType::Plaintext,
         Content::System(_) => MessageType::System,
+        Content::Join(_) => MessageType::Join,
+        Content::Leave(_) => MessageType::Leave,
+        Content::FileChunk(_) => MessageType::FileChunk,
+        Content::Unknown => MessageType::Unknown,
     };
     let now: chrono::DateTime<chrono::Utc> = chrono::DateTime::from(std::time::SystemTime::now());
     let msg = MessageSend {
@@ -315,46 +1548,302 @@ async fn send_message_from_content(message_content: Content) -> Result<()> {
         send_at: Some(now.to_rfc3339()),
         data: Some(None),
     };
-    let listen_task = LISTEN_TASK.read().await;
-    let task = listen_task
-        .as_ref()
-        .ok_or_eyre("You Havent Joined a room")?;
-    let conf = CONFIGURATION.read().await;
-    rooms_api::rooms_send(&conf, &task.room, msg).await?;
-    Ok(())
+    session.transport.send(msg).await
 }
 
-async fn send_message(message_content: &str) -> Result<()> {
-    let key = KEYS.symetric_key.read().await;
-    if let Some(key) = key.as_ref() {
+async fn send_message(room: &str, message_content: &str) -> Result<()> {
+    let session = SESSIONS
+        .read()
+        .await
+        .get(room)
+        .map(|task| task.session.clone())
+        .ok_or_eyre("You Havent Joined this room")?;
+    let keys = session.keys.read().await;
+    if let Some(keys) = keys.as_ref() {
         debug!("Sending encrypted Message");
-        let plaintext = message_content.as_bytes();
-        let mut ciphertext = vec![0u8; plaintext.len() + cipher::MAC_LENGTH];
-        let (_, nonce) = symetric_cipher::encrypt(plaintext, key, None, &mut ciphertext)?;
-
-        let message = Content::Encrypted(Encrypted::new(to_base64(&ciphertext), to_base64(&nonce)));
-        send_message_from_content(message).await?;
+        let own = USERNAME.read().ok().and_then(|me| me.clone());
+        let sealed = replay::Sealed::new(own, &session.room, message_content);
+        // padded, so the length of the ciphertext doesn't give the length of the text away
+        let encrypted = encrypt_for_room(&session, keys, &serde_json::to_vec(&sealed)?).await?;
+        send_message_from_content(&session, Content::Encrypted(encrypted)).await?;
         Ok(())
     } else {
+        let policy = NETWORK_CONFIG.read().await.encryption_for(room);
+        if policy == EncryptionPolicy::RequireE2E {
+            if !session.requested.load(Ordering::Relaxed) {
+                request_key(&session).await?;
+            }
+            return Err(eyre!(
+                "{} requires end-to-end encryption and has no room key yet, the message was not sent",
+                room
+            )
+            .into());
+        }
         debug!("Sending plaintext Message");
         let message = Content::Plaintext(Plaintext::new(message_content.to_owned()));
-        send_message_from_content(message).await?;
+        send_message_from_content(&session, message).await?;
+        if policy == EncryptionPolicy::PreferE2E {
+            send_system_message(
+                room,
+                "Sent unencrypted, no room key has been established yet".to_owned(),
+            )
+            .await;
+        }
         Ok(())
     }
 }
 
-#[tracing::instrument]
-async fn login(username: &str, password: &str) -> Result<()> {
-    let mut conf = CONFIGURATION.write().await;
-    let login = LoginData {
+/// Announces our keys to the members of the room, encrypted with the room key.
+async fn send_hello(session: &RoomSession, reply: bool) -> Result<()> {
+    let hello = signing::Hello {
+        public_key: to_base64(&KEYS.asymetric_key.read().await.public_key),
+        signing_key: to_base64(&KEYS.signing_key.read().await.public_key),
+        reply,
+    };
+    let encrypted = {
+        let keys = session.keys.read().await;
+        let keys = keys
+            .as_ref()
+            .ok_or_eyre("No room key to announce our keys with")?;
+        encrypt_for_room(session, keys, &serde_json::to_vec(&hello)?).await?
+    };
+    send_message_from_content(session, Content::Encrypted(encrypted)).await
+}
+
+/// Learns the keys a member announced and answers with ours if they just joined.
+///
+/// The hello has to be signed with the signing key it announces, the trust store decides
+/// whether that key is taken for the sender.
+async fn hello_received(
+    session: &RoomSession,
+    meta: &Message,
+    encrypted: &Encrypted,
+    hello: signing::Hello,
+) -> Result<()> {
+    if hello.public_key == to_base64(&KEYS.asymetric_key.read().await.public_key) {
+        return Ok(());
+    }
+    if signing::verify(Some(&hello.signing_key), &session.room, encrypted)
+        != signing::SignatureState::Verified
+    {
+        warn!(
+            "Ignoring a hello in {} not signed by its own key",
+            session.room
+        );
+        return Ok(());
+    }
+    let trusted =
+        trust_identity(session, meta, &hello.public_key, Some(&hello.signing_key)).await?;
+    if trusted.is_none() {
+        return Ok(());
+    }
+    // replayed history isn't answered, its senders may be long gone
+    let fresh = meta
+        .send_at
+        .is_none_or(|send_at| send_at >= session.joined_at);
+    if !hello.reply && fresh {
+        send_hello(session, true).await?;
+    }
+    Ok(())
+}
+
+/// Seals `body` into an envelope, encrypts it with the current room key and signs it.
+async fn encrypt_for_room(
+    session: &RoomSession,
+    keys: &RoomKeys,
+    body: &[u8],
+) -> Result<Encrypted> {
+    let plaintext = envelope::seal(body)?;
+    let mut ciphertext = vec![0u8; plaintext.len() + cipher::MAC_LENGTH];
+    let (_, nonce) = symetric_cipher::encrypt(&plaintext, &keys.current, None, &mut ciphertext)?;
+
+    let mut encrypted = Encrypted::new(to_base64(&ciphertext), to_base64(&nonce));
+    encrypted.key_epoch = Some(keys.epoch);
+    let signing_key = KEYS.signing_key.read().await;
+    encrypted.signature = Some(signing::sign(&signing_key, &session.room, &encrypted)?);
+    Ok(encrypted)
+}
+
+/// Sends the file at `path` in encrypted chunks and reports the progress to the chat.
+///
+/// Files are never sent in plaintext, whatever the room's encryption policy.
+async fn send_file(room: &str, path: &Path) -> Result<()> {
+    let session = SESSIONS
+        .read()
+        .await
+        .get(room)
+        .map(|task| task.session.clone())
+        .ok_or_eyre("You Havent Joined this room")?;
+    let max_size = NETWORK_CONFIG.read().await.max_attachment_size()?;
+    let size = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| eyre!("Cannot read {}: {}", path.display(), e))?
+        .len();
+    if size > max_size {
+        return Err(eyre!(
+            "{} has {} bytes, attachments are limited to {}",
+            path.display(),
+            size,
+            max_size
+        )
+        .into());
+    }
+    let content = tokio::fs::read(path)
+        .await
+        .map_err(|e| eyre!("Cannot read {}: {}", path.display(), e))?;
+    let name = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| eyre!("{} is not a file", path.display()))?;
+    let manifest = attachments::Manifest::for_file(name, &content)?;
+
+    let user = USER.read().await.as_ref().map(|me| {
+        let mut user = UserPublic::new(me.appearance.clone());
+        user.username = me.username.clone();
+        user
+    });
+    let action_tx = ACTION_TX.read().await.clone();
+    let progress = |done, state| {
+        Action::Attachment(
+            room.to_owned(),
+            Message {
+                content: manifest.name.clone(),
+                user: user.clone(),
+                send_at: Some(Utc::now()),
+                encrypted: true,
+                signature: signing::SignatureState::Verified,
+                freshness: replay::Freshness::Fresh,
+                envelope_version: Some(envelope::VERSION),
+                attachment: Some(attachments::AttachmentView::new(&manifest, done, state)),
+                ..Default::default()
+            },
+        )
+    };
+    let _ = action_tx.send(progress(0, attachments::AttachmentState::Sending));
+    for chunk in attachments::split(&manifest, &content) {
+        let done = chunk.index + 1;
+        let encrypted = {
+            let keys = session.keys.read().await;
+            let keys = keys.as_ref().ok_or_eyre(
+                "Files are only sent end-to-end encrypted and there is no room key yet",
+            )?;
+            encrypt_for_room(&session, keys, &serde_json::to_vec(&chunk)?).await?
+        };
+        let chunk = Content::FileChunk(attachments::into_chunk(encrypted));
+        send_message_from_content(&session, chunk).await?;
+        let state = if done == manifest.count {
+            attachments::AttachmentState::Sent
+        } else {
+            attachments::AttachmentState::Sending
+        };
+        let _ = action_tx.send(progress(done, state));
+    }
+    Ok(())
+}
+
+/// Adds a received chunk to its transfer and shows the progress.
+///
+/// Chunks that can't be decrypted yet are dropped, the sender can send the file again and the
+/// transfer resumes where it stopped.
+async fn receive_chunk(session: &RoomSession, meta: &Message, chunk: &FileChunk) -> Result<()> {
+    let own = USERNAME.read().ok().and_then(|me| me.clone());
+    let sender = meta.user.as_ref().and_then(|user| user.username.clone());
+    if own.is_some() && sender == own {
+        // the progress of our own files is shown while sending
+        return Ok(());
+    }
+    if meta.signature == signing::SignatureState::Forged {
+        return Err(eyre!("Dropping an attachment chunk with a forged signature").into());
+    }
+    let sender = sender.unwrap_or_default();
+    let payload = {
+        let keys = session.keys.read().await;
+        let Some(keys) = keys.as_ref() else {
+            debug!("No room key yet, dropping an attachment chunk");
+            return Ok(());
+        };
+        open_chunk(keys, chunk)
+    };
+    let payload = match payload {
+        Ok(payload) => payload,
+        Err(e) => {
+            let Some(dropped) = ATTACHMENTS.write().await.fail(&session.room, &sender) else {
+                debug!(
+                    "Another attachment chunk from {} can't be read: {}",
+                    sender, e
+                );
+                return Ok(());
+            };
+            for view in dropped {
+                let message = Message {
+                    content: view.name.clone(),
+                    attachment: Some(view),
+                    ..meta.clone()
+                };
+                let _ = ACTION_TX
+                    .read()
+                    .await
+                    .send(Action::Attachment(session.room.clone(), message));
+            }
+            return Err(eyre!(
+                "An attachment from {} can't be decrypted, it is dropped: {}",
+                sender,
+                e
+            )
+            .into());
+        }
+    };
+    let max_size = NETWORK_CONFIG.read().await.max_attachment_size()?;
+    let Some(view) = ATTACHMENTS
+        .write()
+        .await
+        .add(payload, &session.room, &sender, max_size)?
+    else {
+        return Ok(());
+    };
+    let message = Message {
+        content: view.name.clone(),
+        attachment: Some(view),
+        ..meta.clone()
+    };
+    let _ = ACTION_TX
+        .read()
+        .await
+        .send(Action::Attachment(session.room.clone(), message));
+    Ok(())
+}
+
+fn open_chunk(keys: &RoomKeys, chunk: &FileChunk) -> Result<attachments::ChunkPayload> {
+    let plaintext = decrypt_message(keys, &attachments::as_encrypted(chunk))?;
+    let body = envelope::open(&plaintext)?.body;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// A copy of [`CONFIGURATION`] with the token, the generated client only takes it as a plain
+/// `String`. Built right before a request and dropped with it.
+pub async fn api_configuration() -> Configuration {
+    let mut conf = CONFIGURATION.read().await.clone();
+    conf.bearer_access_token = TOKEN
+        .read()
+        .await
+        .as_ref()
+        .map(|token| token.expose().to_owned());
+    conf
+}
+
+#[tracing::instrument(skip(password))]
+pub(crate) async fn login(username: &str, password: &Secret) -> Result<()> {
+    let conf = CONFIGURATION.read().await.clone();
+    // the generated `LoginData` needs a plain copy, only built right before a request
+    let login = || LoginData {
         username: username.to_owned(),
-        password: password.to_owned(),
+        password: password.expose().to_owned(),
     };
-    match users_api::users_login(&conf, login.clone()).await {
+    match users_api::users_login(&conf, login()).await {
         Ok(response) => {
-            conf.bearer_access_token = Some(response.token.token);
+            *TOKEN.write().await = Some(Secret::new(response.token.token));
 
-            let user = users_api::users_get_me(&conf).await?;
+            let user = users_api::users_get_me(&api_configuration().await).await?;
             debug!("{:#?}", user);
             update_user(user).await;
             Ok(())
@@ -364,13 +1853,11 @@ async fn login(username: &str, password: &str) -> Result<()> {
             if let ApiError::ResponseError(ref e) = e
                 && let Some(users_api::UsersLoginError::Status401(_)) = e.entity
             {
-                if let Ok(string) = serde_json::to_string(&login) {
-                    debug!("{}", string);
-                }
-                let response = users_api::users_register(&conf, login).await?;
-                conf.bearer_access_token = Some(response.token.token);
+                debug!("Login failed, registering {}", username);
+                let response = users_api::users_register(&conf, login()).await?;
+                *TOKEN.write().await = Some(Secret::new(response.token.token));
 
-                let user = users_api::users_get_me(&conf).await?;
+                let user = users_api::users_get_me(&api_configuration().await).await?;
                 debug!("{:#?}", user);
                 update_user(user).await;
 
@@ -387,3 +1874,269 @@ pub fn to_base64(arg: &[u8]) -> String {
 pub fn from_base64(arg: &str) -> Result<Vec<u8>> {
     Ok(general_purpose::STANDARD.decode(arg)?)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_room_address() -> Result<()> {
+        let ephemeral: RoomAddress = " lobby ".parse()?;
+        assert_eq!(ephemeral.kind, RoomKind::Ephemeral);
+        assert_eq!(ephemeral.to_string(), "lobby");
+
+        let persistent: RoomAddress = "static:lobby".parse()?;
+        assert_eq!(persistent, RoomAddress::new_static("lobby"));
+        assert_eq!(persistent.to_string(), "static:lobby");
+
+        assert!("static:".parse::<RoomAddress>().is_err());
+        assert!("".parse::<RoomAddress>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_seen_messages() {
+        let mut seen = SeenMessages::default();
+        assert!(seen.insert("a"));
+        assert!(!seen.insert("a"));
+        for i in 0..SEEN_MESSAGES {
+            assert!(seen.insert(&i.to_string()));
+        }
+        // "a" dropped out of the window
+        assert!(seen.insert("a"));
+        assert_eq!(seen.order.len(), SEEN_MESSAGES);
+    }
+
+    #[test]
+    fn test_message_types() -> Result<()> {
+        let static_join: MessagePublic = serde_json::from_str(
+            r#"{"type": "JOIN", "sender": null,
+                "content": {"type": "SYSTEM", "content": "alice joined", "online_users": 2}}"#,
+        )?;
+        assert_eq!(RoomEvent::from_message(&static_join), Some(RoomEvent::Join));
+
+        let leave: MessagePublic = serde_json::from_str(
+            r#"{"type": "LEAVE", "sender": null, "content": {"type": "LEAVE", "content": "bob left"}}"#,
+        )?;
+        assert_eq!(RoomEvent::from_message(&leave), Some(RoomEvent::Leave));
+        assert_eq!(
+            leave.content,
+            Some(Content::Leave(LeaveMessage::new("bob left".to_owned())))
+        );
+
+        let future: MessagePublic = serde_json::from_str(
+            r#"{"type": "REACTION", "sender": null, "content": {"type": "REACTION", "emoji": "+1"}}"#,
+        )?;
+        assert_eq!(future.r#type, Some(MessageType::Unknown));
+        assert_eq!(future.content, Some(Content::Unknown));
+        assert_eq!(RoomEvent::from_message(&future), None);
+        Ok(())
+    }
+
+    /// Keeps what would have been sent.
+    #[derive(Default)]
+    struct Sent(std::sync::Mutex<Vec<MessageSend>>);
+
+    impl Sent {
+        fn key_responses(&self) -> Result<Vec<KeyResponse>> {
+            Ok(self
+                .0
+                .lock()
+                .map_err(|_| eyre!("poisoned"))?
+                .iter()
+                .filter_map(|message| match &message.content {
+                    Some(Content::KeyResponse(response)) => Some(response.clone()),
+                    _ => None,
+                })
+                .collect())
+        }
+    }
+
+    impl transport::Transport for Sent {
+        fn send(&self, message: MessageSend) -> futures::future::BoxFuture<'_, Result<()>> {
+            if let Ok(mut sent) = self.0.lock() {
+                sent.push(message);
+            }
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    fn announcement(event: RoomEvent, text: &str, online_users: i32) -> (Message, Content) {
+        let meta = Message {
+            content: text.to_owned(),
+            send_at: Some(Utc::now()),
+            event: Some(event),
+            ..Default::default()
+        };
+        (
+            meta,
+            Content::System(SystemMessage::new(text.to_owned(), online_users)),
+        )
+    }
+
+    #[test]
+    fn test_event_member() {
+        assert_eq!(
+            event_member("User TempUser_Ghostly_ej48ur joined the room"),
+            Some("TempUser_Ghostly_ej48ur")
+        );
+        assert_eq!(event_member("User bob left the room"), Some("bob"));
+        assert_eq!(event_member("bob left"), Some("bob"));
+        assert_eq!(event_member("Rotated the room key"), None);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_when_member_leaves() -> Result<()> {
+        let sent = Arc::new(Sent::default());
+        let session = RoomSession::new("test-rotate-on-leave", sent.clone());
+
+        let (mut meta, content) = announcement(RoomEvent::Join, "User alice joined the room", 0);
+        handle_content(&session, &mut meta, content).await?;
+        assert!(session.holds_key.load(Ordering::Relaxed));
+        let bob = Keypair::generate()?;
+        let carol = Keypair::generate()?;
+        session.members.write().await.extend([
+            (to_base64(&bob.public_key), "bob".to_owned()),
+            (to_base64(&carol.public_key), "carol".to_owned()),
+        ]);
+
+        // joins of others don't hand the key over, only a key of theirs does
+        let (mut meta, content) = announcement(RoomEvent::Join, "User bob joined the room", 1);
+        handle_content(&session, &mut meta, content).await?;
+        assert!(session.holds_key.load(Ordering::Relaxed));
+
+        // the server sends it without a sender
+        let (mut meta, content) = announcement(RoomEvent::Leave, "User bob left the room", 1);
+        handle_content(&session, &mut meta, content).await?;
+        assert_eq!(
+            session.keys.read().await.as_ref().map(|keys| keys.epoch),
+            Some(1)
+        );
+        assert_eq!(
+            session.members.read().await.values().collect::<Vec<_>>(),
+            vec!["carol"]
+        );
+        let responses = sent.key_responses()?;
+        assert_eq!(responses.len(), 1);
+        let decoded = payload::decode_key_response(&responses[0])?;
+        assert!(open_key_response(&carol, &decoded).is_ok());
+        assert!(open_key_response(&bob, &decoded).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_key_request_without_sender() -> Result<()> {
+        let sent = Arc::new(Sent::default());
+        let session = RoomSession::new("test-request-without-sender", sent.clone());
+        let (mut meta, content) = announcement(RoomEvent::Join, "User alice joined the room", 0);
+        handle_content(&session, &mut meta, content).await?;
+
+        let requester = Keypair::generate()?;
+        let request = || Content::KeyRequest(KeyRequest::new(to_base64(&requester.public_key)));
+        let mut meta = Message::default();
+        handle_content(&session, &mut meta, request()).await?;
+        assert!(sent.key_responses()?.is_empty());
+        assert!(session.members.read().await.is_empty());
+
+        let mut user = UserPublic::new(AppearancePublic::new("#c0ffee".to_owned()));
+        user.username = Some("test-request-without-sender-dave".to_owned());
+        let mut meta = Message {
+            user: Some(user),
+            ..Default::default()
+        };
+        handle_content(&session, &mut meta, request()).await?;
+        assert_eq!(sent.key_responses()?.len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unrequested_rotation_from_other_member() -> Result<()> {
+        let session = RoomSession::new("test-rotation-from-other", Arc::new(Sent::default()));
+        let bob = Keypair::generate()?;
+        let carol = Keypair::generate()?;
+        async fn respond(
+            session: &RoomSession,
+            sender: &Keypair,
+            name: &str,
+            epoch: u64,
+        ) -> Result<()> {
+            let receiver = KEYS.asymetric_key.read().await.public_key;
+            let mut response = create_key_response(sender, &Key::generate()?, &receiver)?;
+            response.key_epoch = Some(epoch);
+            let mut user = UserPublic::new(AppearancePublic::new("#c0ffee".to_owned()));
+            user.username = Some(name.to_owned());
+            let mut meta = Message {
+                user: Some(user),
+                ..Default::default()
+            };
+            handle_content(session, &mut meta, Content::KeyResponse(response)).await?;
+            Ok(())
+        }
+        let epoch = async || session.keys.read().await.as_ref().map(|keys| keys.epoch);
+
+        session.requested.store(true, Ordering::Relaxed);
+        respond(&session, &bob, "test-rotation-from-other-bob", 0).await?;
+        assert_eq!(epoch().await, Some(0));
+
+        respond(&session, &carol, "test-rotation-from-other-carol", 1).await?;
+        assert_eq!(epoch().await, Some(0));
+        respond(&session, &bob, "test-rotation-from-other-bob", 3).await?;
+        assert_eq!(epoch().await, Some(0));
+        respond(&session, &bob, "test-rotation-from-other-bob", 1).await?;
+        assert_eq!(epoch().await, Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_response_roundtrip() -> Result<()> {
+        let sender = Keypair::generate()?;
+        let receiver = Keypair::generate()?;
+        let key = Key::generate()?;
+
+        let response = create_key_response(&sender, &key, &receiver.public_key)?;
+        let received = open_key_response(&receiver, &payload::decode_key_response(&response)?)?;
+        assert_eq!(received.as_slice(), key.as_slice());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_response_for_someone_else() -> Result<()> {
+        let sender = Keypair::generate()?;
+        let receiver = Keypair::generate()?;
+        let bystander = Keypair::generate()?;
+        let key = Key::generate()?;
+
+        let response = create_key_response(&sender, &key, &receiver.public_key)?;
+        let result = open_key_response(&bystander, &payload::decode_key_response(&response)?);
+        assert!(matches!(result, Err(error::NetworkError::AlkaliError(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_response_bad_check_msg() -> Result<()> {
+        let sender = Keypair::generate()?;
+        let receiver = Keypair::generate()?;
+        let key = Key::generate()?;
+
+        let mut response = create_key_response(&sender, &key, &receiver.public_key)?;
+        let mut check_msg = vec![0u8; CHECK_MSG.len() + symetric_cipher::MAC_LENGTH];
+        symetric_cipher::encrypt(b"NOPE", &key, None, &mut check_msg)?;
+        response.check_msg = to_base64(&check_msg);
+        let result = open_key_response(&receiver, &payload::decode_key_response(&response)?);
+        assert!(matches!(result, Err(error::NetworkError::Eyre(_))));
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_response_truncated() -> Result<()> {
+        let sender = Keypair::generate()?;
+        let receiver = Keypair::generate()?;
+        let key = Key::generate()?;
+
+        let mut response = create_key_response(&sender, &key, &receiver.public_key)?;
+        response.check_msg = to_base64(b"short");
+        let result = payload::decode_key_response(&response).map_err(error::NetworkError::from);
+        assert!(matches!(result, Err(error::NetworkError::Malformed(_))));
+        Ok(())
+    }
+}
@@ -0,0 +1,233 @@
+// Only depends on `openapi` and `alkali`, the fuzz targets include this file as is.
+use alkali::asymmetric::{cipher, sign};
+use alkali::symmetric::cipher as symmetric;
+use base64::{Engine as _, engine::general_purpose};
+use openapi::models::{Encrypted, KeyRequest, KeyResponse};
+
+/// Why a payload from a peer was rejected, before any crypto ran on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MalformedPayload {
+    Base64 {
+        field: &'static str,
+    },
+    /// A fixed size field has the wrong number of bytes.
+    Length {
+        field: &'static str,
+        expected: usize,
+        actual: usize,
+    },
+    /// A ciphertext is shorter than its authentication tag.
+    Truncated {
+        field: &'static str,
+        minimum: usize,
+        actual: usize,
+    },
+}
+
+impl std::fmt::Display for MalformedPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base64 { field } => write!(f, "{field} is not valid base64"),
+            Self::Length {
+                field,
+                expected,
+                actual,
+            } => write!(f, "{field} must be {expected} bytes, got {actual}"),
+            Self::Truncated {
+                field,
+                minimum,
+                actual,
+            } => write!(f, "{field} must be at least {minimum} bytes, got {actual}"),
+        }
+    }
+}
+
+impl std::error::Error for MalformedPayload {}
+
+pub type Result<T> = std::result::Result<T, MalformedPayload>;
+
+#[derive(Debug)]
+pub struct DecodedEncrypted {
+    pub nonce: symmetric::Nonce,
+    /// At least `MAC_LENGTH` bytes.
+    pub ciphertext: Vec<u8>,
+    pub epoch: u64,
+}
+
+impl DecodedEncrypted {
+    pub fn plaintext_len(&self) -> usize {
+        self.ciphertext.len() - symmetric::MAC_LENGTH
+    }
+}
+
+#[derive(Debug)]
+pub struct DecodedKeyRequest {
+    pub public_key: cipher::PublicKey,
+}
+
+#[derive(Debug)]
+pub struct DecodedKeyResponse {
+    pub sender: cipher::PublicKey,
+    pub nonce: symmetric::Nonce,
+    /// Exactly one room key plus the box MAC.
+    pub encrypted_key: Vec<u8>,
+    /// At least `MAC_LENGTH` bytes.
+    pub check_msg: Vec<u8>,
+    pub epoch: u64,
+}
+
+/// No length sent by a peer is trusted, so nothing below has to check again.
+///
+/// The signature is checked on its own, a message with a broken one still decrypts and shows up
+/// as forged.
+pub fn decode_encrypted(encrypted: &Encrypted) -> Result<DecodedEncrypted> {
+    Ok(DecodedEncrypted {
+        nonce: fixed("nonce", &encrypted.nonce)?,
+        ciphertext: sealed(
+            "content_base64",
+            &encrypted.content_base64,
+            symmetric::MAC_LENGTH,
+        )?,
+        epoch: encrypted.key_epoch.unwrap_or_default(),
+    })
+}
+
+/// The signing key is only validated, it is stored as sent.
+pub fn decode_key_request(request: &KeyRequest) -> Result<DecodedKeyRequest> {
+    if let Some(key) = &request.signing_key {
+        signing_key("signing_key", key)?;
+    }
+    Ok(DecodedKeyRequest {
+        public_key: public_key("public_key", &request.public_key)?,
+    })
+}
+
+pub fn decode_key_response(response: &KeyResponse) -> Result<DecodedKeyResponse> {
+    if let Some(key) = &response.signing_key {
+        signing_key("signing_key", key)?;
+    }
+    let encrypted_key = base64("encrypted_symmetric_key", &response.encrypted_symmetric_key)?;
+    let expected = symmetric::KEY_LENGTH + cipher::MAC_LENGTH;
+    if encrypted_key.len() != expected {
+        return Err(MalformedPayload::Length {
+            field: "encrypted_symmetric_key",
+            expected,
+            actual: encrypted_key.len(),
+        });
+    }
+    Ok(DecodedKeyResponse {
+        sender: public_key("sender_public_key", &response.sender_public_key)?,
+        nonce: fixed("nonce", &response.nonce)?,
+        encrypted_key,
+        check_msg: sealed("check_msg", &response.check_msg, symmetric::MAC_LENGTH)?,
+        epoch: response.key_epoch.unwrap_or_default(),
+    })
+}
+
+pub fn public_key(field: &'static str, value: &str) -> Result<cipher::PublicKey> {
+    fixed(field, value)
+}
+
+pub fn signing_key(field: &'static str, value: &str) -> Result<sign::PublicKey> {
+    fixed(field, value)
+}
+
+pub fn signature(field: &'static str, value: &str) -> Result<sign::Signature> {
+    fixed(field, value).map(sign::Signature)
+}
+
+fn base64(field: &'static str, value: &str) -> Result<Vec<u8>> {
+    general_purpose::STANDARD
+        .decode(value)
+        .map_err(|_| MalformedPayload::Base64 { field })
+}
+
+fn fixed<const N: usize>(field: &'static str, value: &str) -> Result<[u8; N]> {
+    let bytes = base64(field, value)?;
+    bytes
+        .as_slice()
+        .try_into()
+        .map_err(|_| MalformedPayload::Length {
+            field,
+            expected: N,
+            actual: bytes.len(),
+        })
+}
+
+/// A ciphertext, which has to hold at least its MAC.
+fn sealed(field: &'static str, value: &str, minimum: usize) -> Result<Vec<u8>> {
+    let bytes = base64(field, value)?;
+    if bytes.len() < minimum {
+        return Err(MalformedPayload::Truncated {
+            field,
+            minimum,
+            actual: bytes.len(),
+        });
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn b64(bytes: &[u8]) -> String {
+        general_purpose::STANDARD.encode(bytes)
+    }
+
+    #[test]
+    fn test_malformed_payloads() {
+        let nonce = b64(&[0; symmetric::NONCE_LENGTH]);
+        let short = Encrypted::new(b64(&[0; 3]), nonce.clone());
+        assert_eq!(
+            decode_encrypted(&short).err(),
+            Some(MalformedPayload::Truncated {
+                field: "content_base64",
+                minimum: symmetric::MAC_LENGTH,
+                actual: 3
+            })
+        );
+        let bad_nonce = Encrypted::new(b64(&[0; 32]), b64(&[0; 5]));
+        assert!(matches!(
+            decode_encrypted(&bad_nonce),
+            Err(MalformedPayload::Length { field: "nonce", .. })
+        ));
+        assert_eq!(
+            signature("signature", "not base64!").err(),
+            Some(MalformedPayload::Base64 { field: "signature" })
+        );
+        let valid = decode_encrypted(&Encrypted::new(b64(&[0; 32]), nonce.clone()));
+        assert_eq!(
+            valid.map(|decoded| decoded.plaintext_len()),
+            Ok(32 - symmetric::MAC_LENGTH)
+        );
+
+        let mut request = KeyRequest::new(b64(&[1; 31]));
+        assert!(matches!(
+            decode_key_request(&request),
+            Err(MalformedPayload::Length {
+                field: "public_key",
+                ..
+            })
+        ));
+        request.public_key = b64(&[1; cipher::PUBLIC_KEY_LENGTH]);
+        request.signing_key = Some(b64(&[1; 8]));
+        assert!(matches!(
+            decode_key_request(&request),
+            Err(MalformedPayload::Length {
+                field: "signing_key",
+                ..
+            })
+        ));
+
+        let public_key = b64(&[0; cipher::PUBLIC_KEY_LENGTH]);
+        let response = KeyResponse::new(b64(&[0; 8]), b64(&[0; 32]), public_key, nonce);
+        assert!(matches!(
+            decode_key_response(&response),
+            Err(MalformedPayload::Length {
+                field: "encrypted_symmetric_key",
+                ..
+            })
+        ));
+    }
+}
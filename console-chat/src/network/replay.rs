@@ -0,0 +1,225 @@
+use super::Result;
+use chrono::{DateTime, TimeDelta, Utc};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use strum::Display;
+
+/// How far the sealed send time may be from the arrival time before a message is flagged.
+const CLOCK_WINDOW: TimeDelta = TimeDelta::minutes(5);
+
+/// [`CLOCK_WINDOW`] in microseconds, the unit of the counters.
+const COUNTER_WINDOW: u64 = CLOCK_WINDOW.num_seconds() as u64 * 1_000_000;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// What an encrypted message carries besides its text, authenticated by the room key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sealed {
+    pub sender: Option<String>,
+    pub room: String,
+    pub sent_at: DateTime<Utc>,
+    /// Strictly increasing per sender, across rooms and restarts.
+    pub counter: u64,
+    pub text: String,
+}
+
+impl Sealed {
+    pub fn new(sender: Option<String>, room: &str, text: &str) -> Self {
+        Self {
+            sender,
+            room: room.to_owned(),
+            sent_at: Utc::now(),
+            counter: next_counter(),
+            text: text.to_owned(),
+        }
+    }
+}
+
+/// Starts at the current time in microseconds, so it keeps increasing after a restart.
+fn next_counter() -> u64 {
+    let now = Utc::now().timestamp_micros().max(0) as u64;
+    let previous = COUNTER
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or_default();
+    now.max(previous + 1)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub(crate) enum Freshness {
+    Fresh,
+    /// Sealed long before it arrived, so it may be an old message sent again.
+    OutOfWindow,
+    /// Carries no sealed metadata, from an older client or not encrypted.
+    #[default]
+    Unbound,
+}
+
+/// Counters seen in one room, by sender.
+#[derive(Debug)]
+pub struct ReplayGuard {
+    room: String,
+    joined_at: DateTime<Utc>,
+    /// Only the counters inside the accepted window, older ones are rejected anyway.
+    senders: HashMap<String, BTreeSet<u64>>,
+}
+
+impl ReplayGuard {
+    pub fn new(room: &str, joined_at: DateTime<Utc>) -> Self {
+        Self {
+            room: room.to_owned(),
+            joined_at,
+            senders: HashMap::new(),
+        }
+    }
+
+    /// Checks `sealed` as delivered by the server for `sender` at `received_at`.
+    ///
+    /// Returns `None` for a message that was already accepted or whose counter is older than
+    /// [`CLOCK_WINDOW`], and an error if it was sealed for another room or by someone else.
+    pub fn check(
+        &mut self,
+        sealed: &Sealed,
+        sender: Option<&str>,
+        received_at: DateTime<Utc>,
+    ) -> Result<Option<Freshness>> {
+        if sealed.room != self.room {
+            return Err(eyre!(
+                "Message was sealed for {} but delivered in {}",
+                sealed.room,
+                self.room
+            )
+            .into());
+        }
+        if let (Some(sealed_by), Some(sender)) = (&sealed.sender, sender)
+            && sealed_by != sender
+        {
+            return Err(eyre!(
+                "Message was sealed by {} but delivered as from {}",
+                sealed_by,
+                sender
+            )
+            .into());
+        }
+        // the backlog the server replays on join is old, but expected
+        let joining = received_at - self.joined_at < CLOCK_WINDOW;
+        let backlog = sealed.sent_at < self.joined_at && joining;
+        let floor = (received_at.timestamp_micros().max(0) as u64).saturating_sub(COUNTER_WINDOW);
+        if !backlog && sealed.counter < floor {
+            return Ok(None);
+        }
+        let seen = self
+            .senders
+            .entry(sealed.sender.clone().unwrap_or_default())
+            .or_default();
+        // the backlog's counters have to be remembered until it can't be replayed anymore
+        if !joining {
+            *seen = seen.split_off(&floor);
+        }
+        if !seen.insert(sealed.counter) {
+            return Ok(None);
+        }
+        if !backlog && (received_at - sealed.sent_at).abs() > CLOCK_WINDOW {
+            return Ok(Some(Freshness::OutOfWindow));
+        }
+        Ok(Some(Freshness::Fresh))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed(sender: &str, counter: u64, sent_at: DateTime<Utc>) -> Sealed {
+        Sealed {
+            sender: Some(sender.to_owned()),
+            room: "lobby".to_owned(),
+            sent_at,
+            counter,
+            text: "hi".to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_replay_guard() -> Result<()> {
+        let joined_at = Utc::now() - TimeDelta::hours(1);
+        let now = Utc::now();
+        let mut guard = ReplayGuard::new("lobby", joined_at);
+        let (first, second, third) = (next_counter(), next_counter(), next_counter());
+
+        let message = sealed("alice", first, now);
+        assert_eq!(
+            guard.check(&message, Some("alice"), now)?,
+            Some(Freshness::Fresh)
+        );
+        assert_eq!(guard.check(&message, Some("alice"), now)?, None);
+        // reordered is fine, once
+        assert_eq!(
+            guard.check(&sealed("alice", third, now), Some("alice"), now)?,
+            Some(Freshness::Fresh)
+        );
+        let late = sealed("alice", second, now);
+        assert_eq!(
+            guard.check(&late, Some("alice"), now)?,
+            Some(Freshness::Fresh)
+        );
+        assert_eq!(guard.check(&late, Some("alice"), now)?, None);
+        // counters are per sender
+        assert_eq!(
+            guard.check(&sealed("bob", first, now), Some("bob"), now)?,
+            Some(Freshness::Fresh)
+        );
+
+        // never seen, but sent before the window, so it may have been forgotten
+        let unseen = sealed("alice", next_counter(), now);
+        let later = now + TimeDelta::minutes(10);
+        assert_eq!(guard.check(&unseen, Some("alice"), later)?, None);
+        assert_eq!(guard.check(&message, Some("alice"), later)?, None);
+        let old = sealed("alice", next_counter(), now - TimeDelta::minutes(30));
+        assert_eq!(
+            guard.check(&old, Some("alice"), now)?,
+            Some(Freshness::OutOfWindow)
+        );
+
+        assert!(
+            guard
+                .check(&sealed("alice", next_counter(), now), Some("bob"), now)
+                .is_err()
+        );
+        let mut elsewhere = sealed("alice", next_counter(), now);
+        elsewhere.room = "static:team".to_owned();
+        assert!(guard.check(&elsewhere, Some("alice"), now).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_backlog_on_join() -> Result<()> {
+        let joined_at = Utc::now();
+        let mut guard = ReplayGuard::new("lobby", joined_at);
+        let day_ago = joined_at - TimeDelta::days(1);
+        let history = sealed("alice", next_counter() - 24 * 3600 * 1_000_000, day_ago);
+        assert_eq!(
+            guard.check(&history, Some("alice"), joined_at)?,
+            Some(Freshness::Fresh)
+        );
+        assert_eq!(
+            guard.check(&history, Some("alice"), joined_at + TimeDelta::minutes(1))?,
+            None
+        );
+        let replayed = sealed("alice", next_counter() - 24 * 3600 * 1_000_000, day_ago);
+        assert_eq!(
+            guard.check(&replayed, Some("alice"), joined_at + TimeDelta::hours(1))?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_counter_increases() {
+        let first = next_counter();
+        assert!(next_counter() > first);
+    }
+}
@@ -0,0 +1,107 @@
+use super::Result;
+use alkali::mem::FullAccess;
+use alkali::symmetric::cipher::Key;
+use std::collections::BTreeMap;
+
+/// The key of a room and the keys it replaced, by epoch.
+///
+/// Every rotation starts a new epoch. Messages carry the epoch they were encrypted in, so the
+/// retired keys still decrypt what was sent before a rotation.
+#[derive(Debug)]
+pub struct RoomKeys {
+    pub epoch: u64,
+    pub current: Key<FullAccess>,
+    retired: BTreeMap<u64, Key<FullAccess>>,
+}
+
+impl RoomKeys {
+    pub fn new(current: Key<FullAccess>, epoch: u64) -> Self {
+        Self {
+            epoch,
+            current,
+            retired: BTreeMap::new(),
+        }
+    }
+
+    pub fn try_clone(&self) -> Result<Self> {
+        let mut retired = BTreeMap::new();
+        for (epoch, key) in &self.retired {
+            retired.insert(*epoch, key.try_clone()?);
+        }
+        Ok(Self {
+            epoch: self.epoch,
+            current: self.current.try_clone()?,
+            retired,
+        })
+    }
+
+    /// Takes `key` for `epoch`. A newer epoch retires the current key, an older one is only kept
+    /// for decryption and the same epoch replaces the current key.
+    pub fn install(&mut self, key: Key<FullAccess>, epoch: u64) {
+        if epoch < self.epoch {
+            self.retired.insert(epoch, key);
+            return;
+        }
+        let previous = std::mem::replace(&mut self.current, key);
+        if epoch > self.epoch {
+            self.retired.insert(self.epoch, previous);
+            self.epoch = epoch;
+        }
+    }
+
+    /// Replaces the current key with a new one in the next epoch.
+    pub fn rotate(&mut self) -> Result<u64> {
+        self.install(Key::generate()?, self.epoch + 1);
+        Ok(self.epoch)
+    }
+
+    /// Keys to try on a message from `epoch`, the key of that epoch first.
+    ///
+    /// The others follow because a peer that lost track of the epoch still uses a valid key.
+    pub fn candidates(&self, epoch: u64) -> Vec<&Key<FullAccess>> {
+        let exact = if epoch == self.epoch {
+            Some(&self.current)
+        } else {
+            self.retired.get(&epoch)
+        };
+        let mut keys: Vec<_> = exact.into_iter().collect();
+        keys.extend(
+            std::iter::once(&self.current)
+                .chain(self.retired.values().rev())
+                .filter(|key| exact.is_none_or(|exact| !std::ptr::eq(*key, exact))),
+        );
+        keys
+    }
+
+    pub fn retired(&self) -> impl Iterator<Item = (u64, &Key<FullAccess>)> {
+        self.retired.iter().map(|(epoch, key)| (*epoch, key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_keeps_old_keys() -> Result<()> {
+        let first = Key::generate()?;
+        let mut keys = RoomKeys::new(first.try_clone()?, 0);
+        assert_eq!(keys.rotate()?, 1);
+        let second = keys.current.try_clone()?;
+        assert_ne!(first.as_slice(), second.as_slice());
+
+        assert_eq!(keys.candidates(1)[0].as_slice(), second.as_slice());
+        assert_eq!(keys.candidates(0)[0].as_slice(), first.as_slice());
+        // unknown epochs still try every key
+        assert_eq!(keys.candidates(7).len(), 2);
+
+        // a late answer with an old key doesn't replace the current one
+        let stale = Key::generate()?;
+        keys.install(stale.try_clone()?, 0);
+        assert_eq!(keys.epoch, 1);
+        assert_eq!(keys.current.as_slice(), second.as_slice());
+        assert_eq!(keys.candidates(0)[0].as_slice(), stale.as_slice());
+        assert_eq!(keys.retired().count(), 1);
+        Ok(())
+    }
+}
@@ -0,0 +1,301 @@
+use super::{Message, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
+use color_eyre::eyre::eyre;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Messages shown before and after a hit.
+pub const CONTEXT: usize = 2;
+
+/// Example of the query syntax, shown where a query is typed.
+pub const SYNTAX: &str = "from:name after:YYYY-MM-DD before:YYYY-MM-DD /regex/ or words";
+
+/// What to look for.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct Query {
+    /// Case-insensitive substring, or a pattern if `regex` is set. Empty matches every message.
+    pub text: String,
+    pub regex: bool,
+    /// Username, compared case-insensitively.
+    pub sender: Option<String>,
+    /// Sent at or after.
+    pub after: Option<DateTime<Utc>>,
+    /// Sent before.
+    pub before: Option<DateTime<Utc>>,
+    /// Only this room, every room if not set.
+    pub room: Option<String>,
+}
+
+impl Query {
+    /// Reads filters like `from:alice after:2025-01-31 before:2025-02-01` and takes the rest as
+    /// text, which is a regex if it is wrapped in slashes.
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut query = Self::default();
+        let mut words = Vec::new();
+        for word in input.split_whitespace() {
+            if let Some(sender) = word.strip_prefix("from:") {
+                query.sender = Some(sender.trim_start_matches('@').to_owned());
+            } else if let Some(date) = word.strip_prefix("after:") {
+                query.after = Some(parse_date(date)?);
+            } else if let Some(date) = word.strip_prefix("before:") {
+                query.before = Some(parse_date(date)?);
+            } else {
+                words.push(word);
+            }
+        }
+        let text = words.join(" ");
+        match text
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(pattern) => {
+                query.text = pattern.to_owned();
+                query.regex = true;
+            }
+            None => query.text = text,
+        }
+        Ok(query)
+    }
+
+    /// Compiles the query, fails for a broken regex.
+    pub fn matcher(&self) -> Result<Matcher> {
+        let pattern = match (self.text.is_empty(), self.regex) {
+            (true, _) => None,
+            (false, true) => {
+                Some(Regex::new(&self.text).map_err(|e| eyre!("Not a valid regex: {}", e))?)
+            }
+            (false, false) => Some(
+                RegexBuilder::new(&regex::escape(&self.text))
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| eyre!("Cannot search for {:?}: {}", self.text, e))?,
+            ),
+        };
+        Ok(Matcher {
+            query: self.clone(),
+            pattern,
+        })
+    }
+}
+
+/// A date is the start of that day in local time, other times are RFC 3339.
+fn parse_date(date: &str) -> Result<DateTime<Utc>> {
+    if let Ok(day) = NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+        let midnight = day.and_hms_opt(0, 0, 0).unwrap_or_default();
+        return Local
+            .from_local_datetime(&midnight)
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| eyre!("{} has no midnight here", date).into());
+    }
+    DateTime::parse_from_rfc3339(date)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| eyre!("{:?} is neither YYYY-MM-DD nor an RFC 3339 time", date).into())
+}
+
+/// A compiled [`Query`].
+pub struct Matcher {
+    query: Query,
+    pattern: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Byte ranges of the matches in the content of `message`, `None` if it doesn't match.
+    ///
+    /// Joins, leaves and attachments have no text to search and never match.
+    pub fn find(&self, room: &str, message: &Message) -> Option<Vec<Range<usize>>> {
+        if message.event.is_some() || message.attachment.is_some() {
+            return None;
+        }
+        if self.query.room.as_deref().is_some_and(|only| only != room) {
+            return None;
+        }
+        if let Some(sender) = &self.query.sender {
+            let username = message.user.as_ref()?.username.as_deref()?;
+            if !username.eq_ignore_ascii_case(sender) {
+                return None;
+            }
+        }
+        if self.query.after.is_some() || self.query.before.is_some() {
+            let send_at = message.send_at?;
+            if self.query.after.is_some_and(|after| send_at < after)
+                || self.query.before.is_some_and(|before| send_at >= before)
+            {
+                return None;
+            }
+        }
+        let Some(pattern) = &self.pattern else {
+            return Some(Vec::new());
+        };
+        if !pattern.is_match(&message.content) {
+            return None;
+        }
+        Some(
+            pattern
+                .find_iter(&message.content)
+                .map(|found| found.range())
+                .filter(|range| !range.is_empty())
+                .collect(),
+        )
+    }
+}
+
+/// A message found by a search.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Hit {
+    pub room: String,
+    pub message: Message,
+    pub matches: Vec<Range<usize>>,
+    /// Index in the local history, `None` for messages that are only in the chat.
+    pub stored_at: Option<usize>,
+    /// Up to [`CONTEXT`] messages before and after it.
+    pub before: Vec<Message>,
+    pub after: Vec<Message>,
+}
+
+/// Finds the hits in `messages` of `room`, with their index in `messages`.
+pub fn search(matcher: &Matcher, room: &str, messages: &[Message]) -> Vec<(usize, Hit)> {
+    messages
+        .iter()
+        .enumerate()
+        .filter_map(|(i, message)| {
+            let matches = matcher.find(room, message)?;
+            Some((
+                i,
+                Hit {
+                    room: room.to_owned(),
+                    message: message.clone(),
+                    matches,
+                    stored_at: None,
+                    before: messages[i.saturating_sub(CONTEXT)..i].to_vec(),
+                    after: messages[i + 1..(i + 1 + CONTEXT).min(messages.len())].to_vec(),
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Adds `found` to `hits`, newest first.
+///
+/// The chat and the history both have the messages loaded from the history, those are kept once.
+pub fn merge(hits: &mut Vec<Hit>, found: Vec<Hit>) {
+    for hit in found {
+        match hits
+            .iter_mut()
+            .find(|known| known.room == hit.room && known.message == hit.message)
+        {
+            Some(known) => {
+                known.stored_at = known.stored_at.or(hit.stored_at);
+            }
+            None => hits.push(hit),
+        }
+    }
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.message.send_at));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use openapi::models::{AppearancePublic, UserPublic};
+
+    fn message(username: &str, content: &str, send_at: DateTime<Utc>) -> Message {
+        let mut user = UserPublic::new(AppearancePublic::new("#c0ffee".to_owned()));
+        user.username = Some(username.to_owned());
+        Message {
+            content: content.to_owned(),
+            user: Some(user),
+            send_at: Some(send_at),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_query() -> Result<()> {
+        let query = Query::parse("from:@alice after:2025-01-31 deploy  failed")?;
+        assert_eq!(query.sender.as_deref(), Some("alice"));
+        assert_eq!(query.text, "deploy failed");
+        assert!(!query.regex);
+        assert_eq!(
+            query
+                .after
+                .map(|after| after.with_timezone(&Local).date_naive()),
+            NaiveDate::from_ymd_opt(2025, 1, 31)
+        );
+        assert!(query.before.is_none());
+
+        let query = Query::parse(r"/v\d+\.\d+/ before:2025-02-01T12:00:00Z")?;
+        assert!(query.regex);
+        assert_eq!(query.text, r"v\d+\.\d+");
+        assert_eq!(query.before, "2025-02-01T12:00:00Z".parse().ok());
+
+        assert!(Query::parse("after:yesterday").is_err());
+        assert!(Query::parse("/(/")?.matcher().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_search() -> Result<()> {
+        let now = Utc::now();
+        let messages: Vec<Message> = [
+            ("alice", "Deploy started"),
+            ("bob", "deploy failed on v1.2"),
+            ("alice", "rolling back"),
+            ("bob", "v1.3 is out, deploy worked"),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, (user, content))| message(user, content, now + TimeDelta::minutes(i as i64)))
+        .collect();
+
+        let hits = search(&Query::parse("deploy")?.matcher()?, "team", &messages);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].1.matches, vec![0..6]);
+        assert_eq!(hits[1].1.before.len(), 1);
+        assert_eq!(hits[2].1.after.len(), 0);
+
+        let hits = search(
+            &Query::parse("from:BOB deploy")?.matcher()?,
+            "team",
+            &messages,
+        );
+        assert_eq!(hits.iter().map(|(i, _)| *i).collect::<Vec<_>>(), vec![1, 3]);
+        let hits = search(&Query::parse(r"/v\d\.\d/")?.matcher()?, "team", &messages);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[1].1.matches, vec![0..4]);
+
+        let mut query = Query::parse("")?;
+        query.after = Some(now + TimeDelta::minutes(1));
+        query.before = Some(now + TimeDelta::minutes(3));
+        assert_eq!(search(&query.matcher()?, "team", &messages).len(), 2);
+        query.room = Some("lobby".to_owned());
+        assert!(search(&query.matcher()?, "team", &messages).is_empty());
+
+        let matcher = Query::parse("deploy")?.matcher()?;
+        let mut hits = Vec::new();
+        merge(
+            &mut hits,
+            search(&matcher, "team", &messages[..2])
+                .into_iter()
+                .map(|(_, hit)| hit)
+                .collect(),
+        );
+        let stored = search(&matcher, "team", &messages)
+            .into_iter()
+            .map(|(i, mut hit)| {
+                hit.stored_at = Some(i);
+                hit
+            })
+            .collect();
+        merge(&mut hits, stored);
+        assert_eq!(hits.len(), 3);
+        assert_eq!(hits[0].message.content, "v1.3 is out, deploy worked");
+        assert_eq!(hits[2].stored_at, Some(0));
+        Ok(())
+    }
+}
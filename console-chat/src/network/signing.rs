@@ -0,0 +1,164 @@
+use super::{Result, payload, to_base64};
+use alkali::asymmetric::sign::{self, Keypair};
+use color_eyre::eyre::eyre;
+use openapi::models::Encrypted;
+use serde::{Deserialize, Serialize};
+use strum::Display;
+use tracing::warn;
+
+/// Separates message signatures from anything else signed with the same key.
+const SIGNATURE_CONTEXT: &[u8] = b"console-chat message\0";
+
+/// Whether an encrypted message provably comes from the user the server named as sender.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, Display)]
+pub(crate) enum SignatureState {
+    /// Signed with the signing key known for the sender.
+    Verified,
+    /// No signing key is known for the sender yet, or the message isn't encrypted.
+    #[default]
+    Unverified,
+    /// The sender has a known signing key but the signature is missing or wrong.
+    Forged,
+}
+
+/// Announces the keys of its sender to a room. It is sent encrypted with the room key and signed
+/// with the announced signing key.
+///
+/// Members of passphrase rooms and of rooms joined with a shared key never exchange keys, they
+/// learn each other's signing keys from these.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub struct Hello {
+    /// Base64 public encryption key, the identity the trust store tracks.
+    pub public_key: String,
+    /// Base64 public signing key.
+    pub signing_key: String,
+    /// Answers the hello of someone who joined, those aren't answered again.
+    pub reply: bool,
+}
+
+impl Hello {
+    /// Reads a hello from the body of a decrypted message, `None` for any other message.
+    pub fn from_body(body: &[u8]) -> Option<Self> {
+        serde_json::from_slice(body).ok()
+    }
+}
+
+/// What gets signed, binds the ciphertext to the room and key epoch so it can't be reposted
+/// elsewhere or claimed for another key.
+fn signed_bytes(room: &str, encrypted: &Encrypted) -> Vec<u8> {
+    let epoch = encrypted
+        .key_epoch
+        .map(|epoch| epoch.to_string())
+        .unwrap_or_default();
+    [
+        SIGNATURE_CONTEXT,
+        room.as_bytes(),
+        b"\0",
+        epoch.as_bytes(),
+        b"\0",
+        encrypted.nonce.as_bytes(),
+        b"\0",
+        encrypted.content_base64.as_bytes(),
+    ]
+    .concat()
+}
+
+/// Signs `encrypted` for `room`, returns the base64 signature for `Encrypted::signature`.
+pub fn sign(keypair: &Keypair, room: &str, encrypted: &Encrypted) -> Result<String> {
+    let signature = sign::sign_detached(&signed_bytes(room, encrypted), keypair)?;
+    Ok(to_base64(&signature.0))
+}
+
+/// Checks the signature of `encrypted` against the base64 `signing_key` of the sender.
+pub fn verify(signing_key: Option<&str>, room: &str, encrypted: &Encrypted) -> SignatureState {
+    let Some(signing_key) = signing_key else {
+        return SignatureState::Unverified;
+    };
+    let checked = decode_signing_key(signing_key).and_then(|signing_key| {
+        let signature = encrypted
+            .signature
+            .as_deref()
+            .ok_or_else(|| eyre!("message is not signed"))?;
+        let signature = payload::signature("signature", signature)?;
+        sign::verify_detached(&signed_bytes(room, encrypted), &signature, &signing_key)?;
+        Ok(())
+    });
+    match checked {
+        Ok(()) => SignatureState::Verified,
+        Err(e) => {
+            warn!("Rejecting message signature: {}", e);
+            SignatureState::Forged
+        }
+    }
+}
+
+pub fn decode_signing_key(signing_key: &str) -> Result<sign::PublicKey> {
+    Ok(payload::signing_key("signing_key", signing_key)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encrypted() -> Encrypted {
+        Encrypted::new("Y2lwaGVydGV4dA==".to_owned(), "bm9uY2U=".to_owned())
+    }
+
+    #[test]
+    fn test_signatures() -> Result<()> {
+        let keypair = Keypair::generate()?;
+        let signing_key = to_base64(&keypair.public_key);
+        let mut message = encrypted();
+        assert_eq!(
+            verify(Some(&signing_key), "lobby", &message),
+            SignatureState::Forged
+        );
+        message.signature = Some(sign(&keypair, "lobby", &message)?);
+
+        assert_eq!(
+            verify(Some(&signing_key), "lobby", &message),
+            SignatureState::Verified
+        );
+        assert_eq!(verify(None, "lobby", &message), SignatureState::Unverified);
+        // reposted in another room
+        assert_eq!(
+            verify(Some(&signing_key), "static:team", &message),
+            SignatureState::Forged
+        );
+        // signed by someone else
+        let other = to_base64(&Keypair::generate()?.public_key);
+        assert_eq!(
+            verify(Some(&other), "lobby", &message),
+            SignatureState::Forged
+        );
+        // claimed for another key epoch
+        let mut other_epoch = message.clone();
+        other_epoch.key_epoch = Some(1);
+        assert_eq!(
+            verify(Some(&signing_key), "lobby", &other_epoch),
+            SignatureState::Forged
+        );
+        message.content_base64 = "b3RoZXIgY2lwaGVydGV4dA==".to_owned();
+        assert_eq!(
+            verify(Some(&signing_key), "lobby", &message),
+            SignatureState::Forged
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hello() -> Result<()> {
+        let hello = Hello {
+            public_key: "cHVibGlj".to_owned(),
+            signing_key: "c2lnbmluZw==".to_owned(),
+            reply: false,
+        };
+        let body = serde_json::to_vec(&hello)?;
+        assert_eq!(Hello::from_body(&body), Some(hello));
+        let text = br#"{"sender":"alice","room":"lobby","sent_at":"2025-01-31T12:00:00Z","counter":1,"text":"hi"}"#;
+        assert_eq!(Hello::from_body(text), None);
+        assert_eq!(Hello::from_body(b"plain text"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,228 @@
+use super::Result;
+use crate::config::NetworkConfig;
+use color_eyre::eyre::eyre;
+use std::path::Path;
+
+const PEM_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// TLS settings from `NetworkConfig` with all files read and checked, shared by the HTTP client
+/// and the WebSocket connector so both trust the same servers.
+pub(crate) struct TlsSettings {
+    /// PEM encoded extra root certificates, one entry per certificate.
+    roots: Vec<String>,
+    /// PEM encoded client certificate chain and PKCS#8 key.
+    identity: Option<(Vec<u8>, Vec<u8>)>,
+    accept_invalid_certs: bool,
+    accept_invalid_hostnames: bool,
+}
+
+impl TlsSettings {
+    pub fn load(config: &NetworkConfig, accept_invalid_certs: bool) -> Result<Self> {
+        let roots = match &config.ca_cert_path {
+            Some(path) => {
+                let bundle = String::from_utf8(read(path, "CA bundle")?)
+                    .map_err(|_| eyre!("CA bundle {} is not a PEM file", path.display()))?;
+                let roots = split_certificates(&bundle);
+                if roots.is_empty() {
+                    return Err(
+                        eyre!("CA bundle {} contains no certificates", path.display()).into(),
+                    );
+                }
+                roots
+            }
+            None => Vec::new(),
+        };
+        let identity = match (&config.client_cert_path, &config.client_key_path) {
+            (Some(cert), Some(key)) => {
+                Some((read(cert, "client certificate")?, read(key, "client key")?))
+            }
+            (None, None) => None,
+            _ => {
+                return Err(
+                    eyre!("client_cert_path and client_key_path have to be set together").into(),
+                );
+            }
+        };
+        let settings = Self {
+            roots,
+            identity,
+            accept_invalid_certs: accept_invalid_certs || config.accept_danger,
+            accept_invalid_hostnames: config.disable_hostname_verification,
+        };
+        // parse everything once up front, so errors name the file instead of surfacing on connect
+        settings.http_client()?;
+        Ok(settings)
+    }
+
+    pub fn http_client(&self) -> Result<reqwest::Client> {
+        let mut builder = reqwest::ClientBuilder::new()
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(self.accept_invalid_hostnames);
+        for (i, root) in self.roots.iter().enumerate() {
+            let cert = reqwest::Certificate::from_pem(root.as_bytes())
+                .map_err(|e| eyre!("Invalid certificate #{} in CA bundle: {}", i + 1, e))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if let Some((cert, key)) = &self.identity {
+            let identity = reqwest::Identity::from_pkcs8_pem(cert, key).map_err(|e| {
+                eyre!(
+                    "Invalid client identity, expected PEM certificate and PKCS#8 key: {}",
+                    e
+                )
+            })?;
+            builder = builder.identity(identity);
+        }
+        // the key is only checked against the certificate here
+        builder.build().map_err(|e| match &self.identity {
+            Some(_) => eyre!(
+                "Invalid client identity, the key may not belong to the certificate: {}",
+                std::error::Error::source(&e).map_or(e.to_string(), |source| source.to_string())
+            )
+            .into(),
+            None => e.into(),
+        })
+    }
+
+    #[cfg(feature = "websocket")]
+    pub fn tls_connector(&self) -> Result<native_tls::TlsConnector> {
+        let mut builder = native_tls::TlsConnector::builder();
+        builder
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .danger_accept_invalid_hostnames(self.accept_invalid_hostnames);
+        for (i, root) in self.roots.iter().enumerate() {
+            let cert = native_tls::Certificate::from_pem(root.as_bytes())
+                .map_err(|e| eyre!("Invalid certificate #{} in CA bundle: {}", i + 1, e))?;
+            builder.add_root_certificate(cert);
+        }
+        if let Some((cert, key)) = &self.identity {
+            let identity = native_tls::Identity::from_pkcs8(cert, key).map_err(|e| {
+                eyre!(
+                    "Invalid client identity, expected PEM certificate and PKCS#8 key: {}",
+                    e
+                )
+            })?;
+            builder.identity(identity);
+        }
+        builder.build().map_err(|e| match &self.identity {
+            Some(_) => eyre!(
+                "Invalid client identity, the key may not belong to the certificate: {}",
+                e
+            )
+            .into(),
+            None => eyre!(e).into(),
+        })
+    }
+}
+
+fn read(path: &Path, what: &str) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| eyre!("Cannot read {} {}: {}", what, path.display(), e).into())
+}
+
+/// Splits a PEM bundle into its certificates, `native_tls` only parses one at a time.
+fn split_certificates(bundle: &str) -> Vec<String> {
+    let mut certs = Vec::new();
+    let mut rest = bundle;
+    while let Some(start) = rest.find(PEM_BEGIN) {
+        let Some(len) = rest[start..].find(PEM_END) else {
+            break;
+        };
+        let end = start + len + PEM_END.len();
+        certs.push(format!("{}\n", &rest[start..end]));
+        rest = &rest[end..];
+    }
+    certs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::error::NetworkError;
+
+    #[test]
+    fn test_split_certificates() {
+        let bundle =
+            format!("# root\n{PEM_BEGIN}\nAAAA\n{PEM_END}\n\n{PEM_BEGIN}\nBBBB\n{PEM_END}\n");
+        let certs = split_certificates(&bundle);
+        assert_eq!(certs.len(), 2);
+        assert!(certs[1].contains("BBBB"));
+        assert!(split_certificates("no certificates here").is_empty());
+    }
+
+    #[test]
+    fn test_bad_files() {
+        let missing = NetworkConfig {
+            ca_cert_path: Some("/does/not/exist.pem".into()),
+            ..Default::default()
+        };
+        let Err(NetworkError::Eyre(e)) = TlsSettings::load(&missing, false) else {
+            panic!("missing CA bundle was accepted");
+        };
+        assert!(e.to_string().contains("/does/not/exist.pem"));
+
+        let only_cert = NetworkConfig {
+            client_cert_path: Some("/does/not/exist.pem".into()),
+            ..Default::default()
+        };
+        assert!(TlsSettings::load(&only_cert, false).is_err());
+    }
+
+    fn testdata(name: &str) -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata/tls")
+            .join(name)
+    }
+
+    fn error(config: &NetworkConfig) -> String {
+        match TlsSettings::load(config, false) {
+            Ok(_) => panic!("{config:?} was accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_malformed_ca_bundle() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("ca.pem");
+        std::fs::write(
+            &path,
+            format!("{PEM_BEGIN}\nnot base64 at all\n{PEM_END}\n"),
+        )?;
+        let config = NetworkConfig {
+            ca_cert_path: Some(path.clone()),
+            ..Default::default()
+        };
+        assert!(error(&config).contains("Invalid certificate #1"));
+
+        std::fs::write(&path, "just some text")?;
+        assert!(error(&config).contains("contains no certificates"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_identity() -> Result<()> {
+        let valid = NetworkConfig {
+            ca_cert_path: Some(testdata("client.pem")),
+            client_cert_path: Some(testdata("client.pem")),
+            client_key_path: Some(testdata("client.key")),
+            ..Default::default()
+        };
+        let settings = TlsSettings::load(&valid, false)?;
+        assert_eq!(settings.roots.len(), 1);
+        assert!(settings.identity.is_some());
+
+        // the certificate file holds no key
+        let without_key = NetworkConfig {
+            client_key_path: Some(testdata("client.pem")),
+            ..valid.clone()
+        };
+        assert!(error(&without_key).contains("Invalid client identity"));
+        let other_key = NetworkConfig {
+            client_key_path: Some(testdata("other.key")),
+            ..valid
+        };
+        let e = error(&other_key);
+        assert!(e.contains("Invalid client identity"), "{e}");
+        Ok(())
+    }
+}
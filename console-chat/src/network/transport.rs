@@ -0,0 +1,432 @@
+use super::error::NetworkError;
+use super::{Result, RoomAddress, RoomKind};
+use crate::config::RoomTransport;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures_util::stream::StreamExt;
+use openapi::apis::configuration::Configuration;
+use openapi::apis::rooms_api;
+use openapi::models::MessageSend;
+use std::sync::Arc;
+use tracing::debug;
+
+/// Sending half of a room connection.
+pub(crate) trait Transport: Send + Sync {
+    fn send(&self, message: MessageSend) -> BoxFuture<'_, Result<()>>;
+}
+
+/// Events pushed by a `Connection`.
+pub(crate) enum Incoming {
+    /// The connection is (again) established.
+    Open,
+    /// Raw JSON of a `MessagePublic` pushed by the server.
+    Message(String),
+}
+
+/// An open room, errors on `incoming` are fatal unless `reconnects` is set.
+pub(crate) struct Connection {
+    pub incoming: BoxStream<'static, Result<Incoming>>,
+    pub outgoing: Arc<dyn Transport>,
+    pub reconnects: bool,
+}
+
+pub(crate) async fn connect(
+    kind: RoomTransport,
+    conf: &Configuration,
+    room: &RoomAddress,
+) -> Result<Connection> {
+    match kind {
+        RoomTransport::Sse => sse::connect(conf, room).await,
+        #[cfg(feature = "websocket")]
+        RoomTransport::WebSocket => websocket::connect(conf, room).await,
+        #[cfg(not(feature = "websocket"))]
+        RoomTransport::WebSocket => Err(color_eyre::eyre::eyre!(
+            "WebSocket transport selected, but the client was built without the `websocket` feature"
+        )
+        .into()),
+    }
+}
+
+mod sse {
+    use super::*;
+    use crate::network::api_configuration;
+    use reqwest_eventsource::Event;
+    use reqwest_eventsource::retry::RetryPolicy;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    const BASE_DELAY: Duration = Duration::from_millis(500);
+    const MAX_DELAY: Duration = Duration::from_secs(30);
+    const MAX_RECONNECT_ATTEMPTS: usize = 10;
+
+    /// Exponential backoff with jitter, a `retry:` hint from the server replaces the base delay.
+    ///
+    /// Counts attempts itself, `last_retry` from the event source only tells whether the previous
+    /// attempt failed. A refusal like a room the user isn't invited to isn't retried.
+    pub(in crate::network) struct Backoff {
+        base: Duration,
+        max: Duration,
+        max_attempts: usize,
+        attempt: AtomicUsize,
+    }
+
+    impl Default for Backoff {
+        fn default() -> Self {
+            Self {
+                base: BASE_DELAY,
+                max: MAX_DELAY,
+                max_attempts: MAX_RECONNECT_ATTEMPTS,
+                attempt: AtomicUsize::new(0),
+            }
+        }
+    }
+
+    impl RetryPolicy for Backoff {
+        fn retry(
+            &self,
+            error: &reqwest_eventsource::Error,
+            last_retry: Option<(usize, Duration)>,
+        ) -> Option<Duration> {
+            if let reqwest_eventsource::Error::InvalidStatusCode(status, _) = error
+                && status.is_client_error()
+                && *status != reqwest::StatusCode::REQUEST_TIMEOUT
+                && *status != reqwest::StatusCode::TOO_MANY_REQUESTS
+            {
+                return None;
+            }
+            let attempt = match last_retry {
+                None => {
+                    self.attempt.store(1, Ordering::Relaxed);
+                    1
+                }
+                Some(_) => self.attempt.fetch_add(1, Ordering::Relaxed) + 1,
+            };
+            if attempt > self.max_attempts {
+                return None;
+            }
+            let exponent = u32::try_from(attempt - 1).unwrap_or(u32::MAX);
+            let delay = self
+                .base
+                .saturating_mul(2u32.saturating_pow(exponent))
+                .min(self.max);
+            Some(delay.mul_f64(rand::random_range(0.5..=1.0)))
+        }
+
+        fn set_reconnection_time(&mut self, duration: Duration) {
+            self.base = duration;
+            self.max = self.max.max(duration);
+        }
+    }
+
+    /// Posts every message with `rooms_send` or `rooms_send_static`, using the current
+    /// `api_configuration`.
+    struct Http {
+        room: RoomAddress,
+    }
+
+    impl Transport for Http {
+        fn send(&self, message: MessageSend) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move {
+                let conf = api_configuration().await;
+                match self.room.kind {
+                    RoomKind::Ephemeral => {
+                        rooms_api::rooms_send(&conf, &self.room.name, message).await?;
+                    }
+                    RoomKind::Static => {
+                        rooms_api::rooms_send_static(&conf, &self.room.name, message).await?;
+                    }
+                }
+                Ok(())
+            })
+        }
+    }
+
+    pub(super) async fn connect(conf: &Configuration, room: &RoomAddress) -> Result<Connection> {
+        let mut stream = match room.kind {
+            RoomKind::Ephemeral => rooms_api::rooms_listen(conf, &room.name).await?,
+            RoomKind::Static => rooms_api::rooms_listen_static(conf, &room.name).await?,
+        };
+        stream.set_retry_policy(Box::new(Backoff::default()));
+        let incoming = stream
+            .map(|event| match event {
+                Ok(Event::Open) => {
+                    debug!("Event source opened");
+                    Ok(Incoming::Open)
+                }
+                Ok(Event::Message(event)) => Ok(Incoming::Message(event.data)),
+                Err(e) => Err(NetworkError::from(e)),
+            })
+            .boxed();
+        Ok(Connection {
+            incoming,
+            outgoing: Arc::new(Http { room: room.clone() }),
+            reconnects: true,
+        })
+    }
+}
+
+#[cfg(feature = "websocket")]
+mod websocket {
+    use super::*;
+    use crate::network::TLS_CONNECTOR;
+    use crate::secret::Secret;
+    use tokio::sync::mpsc::{UnboundedSender, unbounded_channel};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+    use tokio_tungstenite::tungstenite::http::HeaderValue;
+    use tokio_tungstenite::{Connector, connect_async_tls_with_config};
+    use url::Url;
+
+    /// Queues messages for the task that owns the write half of the socket.
+    struct WebSocket {
+        tx: UnboundedSender<WsMessage>,
+    }
+
+    impl Transport for WebSocket {
+        fn send(&self, message: MessageSend) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async move {
+                let json = serde_json::to_string(&message)?;
+                self.tx
+                    .send(WsMessage::text(json))
+                    .map_err(|_| color_eyre::eyre::eyre!("WebSocket connection is closed"))?;
+                Ok(())
+            })
+        }
+    }
+
+    /// `http(s)://host/base` becomes `ws(s)://host/base/ws/{room}`, or `.../ws/static/{room}`
+    /// for static rooms.
+    ///
+    /// The backend in this repository doesn't serve these routes, they are meant for a gateway
+    /// in front of it that takes `MessageSend` frames and pushes `MessagePublic` frames.
+    pub(super) fn room_url(base_path: &str, room: &RoomAddress) -> Result<Url> {
+        let mut url = Url::parse(base_path).map_err(|e| color_eyre::eyre::eyre!(e))?;
+        let scheme = match url.scheme() {
+            "https" | "wss" => "wss",
+            _ => "ws",
+        };
+        url.set_scheme(scheme)
+            .map_err(|_| color_eyre::eyre::eyre!("cannot use {base_path} for WebSockets"))?;
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|_| color_eyre::eyre::eyre!("cannot use {base_path} for WebSockets"))?;
+        segments.pop_if_empty().push("ws");
+        if room.kind == RoomKind::Static {
+            segments.push("static");
+        }
+        segments.push(&room.name);
+        drop(segments);
+        Ok(url)
+    }
+
+    pub(super) async fn connect(conf: &Configuration, room: &RoomAddress) -> Result<Connection> {
+        let url = room_url(&conf.base_path, room)?;
+        let mut request = url.as_str().into_client_request()?;
+        if let Some(token) = conf.bearer_access_token.as_ref() {
+            let bearer = Secret::new(format!("Bearer {token}"));
+            let mut value =
+                HeaderValue::from_str(bearer.expose()).map_err(|e| color_eyre::eyre::eyre!(e))?;
+            value.set_sensitive(true);
+            request.headers_mut().insert("Authorization", value);
+        }
+        let connector = TLS_CONNECTOR.read().await.clone().map(Connector::NativeTls);
+        let (stream, _) = match connect_async_tls_with_config(request, None, false, connector).await
+        {
+            Ok(connected) => connected,
+            Err(tokio_tungstenite::tungstenite::Error::Http(response))
+                if response.status()
+                    == tokio_tungstenite::tungstenite::http::StatusCode::NOT_FOUND =>
+            {
+                return Err(color_eyre::eyre::eyre!(
+                    "{} has no WebSocket route for rooms, use the Sse transport with this server",
+                    url
+                )
+                .into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        debug!("WebSocket connected to {}", url);
+
+        let (mut sink, stream) = stream.split();
+        let (tx, mut rx) = unbounded_channel();
+        tokio::spawn(async move {
+            use futures_util::SinkExt;
+            while let Some(message) = rx.recv().await {
+                if let Err(e) = sink.send(message).await {
+                    tracing::error!("Failed to send over WebSocket: {e}");
+                    break;
+                }
+            }
+        });
+
+        let incoming = stream.filter_map(|message| async move {
+            match message {
+                Ok(WsMessage::Text(text)) => Some(Ok(Incoming::Message(text.to_string()))),
+                Ok(WsMessage::Binary(data)) => Some(
+                    String::from_utf8(data.to_vec())
+                        .map(Incoming::Message)
+                        .map_err(|e| NetworkError::from(e.utf8_error())),
+                ),
+                Ok(_) => None,
+                Err(e) => Some(Err(NetworkError::from(e))),
+            }
+        });
+        Ok(Connection {
+            incoming: futures::stream::once(async { Ok(Incoming::Open) })
+                .chain(incoming)
+                .boxed(),
+            outgoing: Arc::new(WebSocket { tx }),
+            reconnects: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest_eventsource::retry::RetryPolicy;
+    use std::time::Duration;
+
+    #[test]
+    fn test_backoff() {
+        let mut backoff = sse::Backoff::default();
+        backoff.set_reconnection_time(Duration::from_secs(1));
+        let error = reqwest_eventsource::Error::StreamEnded;
+
+        let first = backoff.retry(&error, None);
+        assert!(
+            first.is_some_and(|d| d >= Duration::from_millis(500) && d <= Duration::from_secs(1))
+        );
+        let mut last = first.map(|d| (1, d));
+        for attempt in 2..=10 {
+            let delay = backoff.retry(&error, last);
+            assert!(
+                delay.is_some_and(|d| d <= Duration::from_secs(30)),
+                "{attempt}: {delay:?}"
+            );
+            last = delay.map(|d| (1, d));
+        }
+        assert_eq!(backoff.retry(&error, last), None);
+
+        // a successful reconnect starts counting from the beginning
+        assert!(backoff.retry(&error, None).is_some());
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod websocket_tests {
+    use super::*;
+    use futures_util::SinkExt;
+    use openapi::models::{Content, MessagePublic, Plaintext};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    /// Accepts one client and echoes every `MessageSend` back as a `MessagePublic`.
+    async fn stand_in_server() -> Result<(String, tokio::task::JoinHandle<Option<String>>)> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.ok()?;
+            let mut seen = None;
+            #[allow(clippy::result_large_err)] // signature is given by tungstenite
+            let callback = |request: &Request, response: Response| {
+                seen = Some(format!(
+                    "{} {:?}",
+                    request.uri().path(),
+                    request.headers().get("Authorization")
+                ));
+                Ok(response)
+            };
+            let mut ws = tokio_tungstenite::accept_hdr_async(socket, callback)
+                .await
+                .ok()?;
+            while let Some(Ok(WsMessage::Text(text))) = ws.next().await {
+                let sent: MessageSend = serde_json::from_str(&text).ok()?;
+                let mut public = MessagePublic::new(None);
+                public.r#type = sent.r#type;
+                public.content = sent.content;
+                let json = serde_json::to_string(&public).ok()?;
+                ws.send(WsMessage::text(json)).await.ok()?;
+            }
+            seen
+        });
+        Ok((format!("http://{addr}/api"), server))
+    }
+
+    #[test]
+    fn test_room_url() -> Result<()> {
+        assert_eq!(
+            websocket::room_url("https://localhost", &"abc".parse()?)?.as_str(),
+            "wss://localhost/ws/abc"
+        );
+        assert_eq!(
+            websocket::room_url("http://127.0.0.1:8000/api/", &"a b".parse()?)?.as_str(),
+            "ws://127.0.0.1:8000/api/ws/a%20b"
+        );
+        assert_eq!(
+            websocket::room_url("https://localhost", &"static:abc".parse()?)?.as_str(),
+            "wss://localhost/ws/static/abc"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_websocket_roundtrip() -> Result<()> {
+        let (base_path, server) = stand_in_server().await?;
+        let conf = Configuration {
+            base_path,
+            bearer_access_token: Some("token".to_owned()),
+            ..Default::default()
+        };
+        let mut connection = connect(RoomTransport::WebSocket, &conf, &"room".parse()?).await?;
+
+        let content = Content::Plaintext(Plaintext::new("hello".to_owned()));
+        let message = MessageSend {
+            r#type: None,
+            content: Some(content.clone()),
+            send_at: None,
+            data: None,
+        };
+        connection.outgoing.send(message).await?;
+
+        assert!(matches!(
+            connection.incoming.next().await,
+            Some(Ok(Incoming::Open))
+        ));
+        let Some(Ok(Incoming::Message(data))) = connection.incoming.next().await else {
+            return Err(color_eyre::eyre::eyre!("expected a message").into());
+        };
+        let received: MessagePublic = serde_json::from_str(&data)?;
+        assert_eq!(received.content, Some(content));
+
+        drop(connection);
+        let seen = server.await.map_err(|e| color_eyre::eyre::eyre!(e))?;
+        assert_eq!(seen.as_deref(), Some("/api/ws/room Some(\"Bearer token\")"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_server_without_websockets() -> Result<()> {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await?;
+            socket
+                .write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+                .await
+        });
+        let conf = Configuration {
+            base_path: format!("http://{addr}"),
+            ..Default::default()
+        };
+        let Err(e) = connect(RoomTransport::WebSocket, &conf, &"room".parse()?).await else {
+            return Err(color_eyre::eyre::eyre!("connected to a server without WebSockets").into());
+        };
+        assert!(e.to_string().contains("Sse transport"), "{e}");
+        Ok(())
+    }
+}
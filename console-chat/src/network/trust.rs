@@ -0,0 +1,277 @@
+use super::{Result, approval, decode_public_key, from_base64, words};
+use alkali::hash::generic;
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Digits in a safety number, shown in groups of five.
+const SAFETY_NUMBER_DIGITS: usize = 60;
+
+/// Bytes of the safety hash read out as words.
+const SAFETY_WORDS: usize = 8;
+
+/// What is known about the key a user presented.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TrustState {
+    /// First key seen for this user, it is trusted from now on.
+    New,
+    Known,
+    /// The user presented a different key than before, nothing is shared until it is accepted.
+    Changed,
+    /// A change that was already reported and is still not accepted.
+    Unaccepted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Identity {
+    /// Base64 public key trusted for this user.
+    public_key: String,
+    /// Compared out of band on the verification screen.
+    #[serde(default)]
+    verified: bool,
+    /// A different key the user presented later, waiting to be accepted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pending: Option<String>,
+    /// Base64 signing key first published together with `public_key`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    signing_key: Option<String>,
+}
+
+/// A known user as shown on the verification screen.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityView {
+    pub username: String,
+    pub fingerprint: String,
+    pub verified: bool,
+    /// Fingerprint of a changed key that has not been accepted yet.
+    pub pending_fingerprint: Option<String>,
+    /// For the pending key if there is one, that is the key to check before accepting it.
+    pub safety_number: String,
+    pub safety_words: Vec<String>,
+}
+
+/// Usernames and the public key first seen for them (trust on first use).
+#[derive(Debug, Default)]
+pub struct TrustStore {
+    path: Option<PathBuf>,
+    identities: BTreeMap<String, Identity>,
+}
+
+impl TrustStore {
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let identities = match std::fs::read_to_string(&path) {
+            Ok(content) => serde_json::from_str(&content)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Default::default(),
+            Err(e) => return Err(eyre!("Cannot read {}: {}", path.display(), e).into()),
+        };
+        Ok(Self {
+            path: Some(path),
+            identities,
+        })
+    }
+
+    /// Checks `public_key` against the key known for `username`, recording it if there is none.
+    pub fn observe(&mut self, username: &str, public_key: &str) -> Result<TrustState> {
+        let state = match self.identities.get_mut(username) {
+            None => {
+                self.identities.insert(
+                    username.to_owned(),
+                    Identity {
+                        public_key: public_key.to_owned(),
+                        verified: false,
+                        pending: None,
+                        signing_key: None,
+                    },
+                );
+                TrustState::New
+            }
+            Some(identity) if identity.public_key == public_key => return Ok(TrustState::Known),
+            Some(identity) => {
+                if identity.pending.as_deref() == Some(public_key) {
+                    return Ok(TrustState::Unaccepted);
+                }
+                identity.pending = Some(public_key.to_owned());
+                TrustState::Changed
+            }
+        };
+        self.save()?;
+        Ok(state)
+    }
+
+    /// Trusts the pending key of `username` instead of the old one.
+    pub fn accept(&mut self, username: &str) -> Result<()> {
+        let identity = self
+            .identities
+            .get_mut(username)
+            .ok_or_else(|| eyre!("{} is not known", username))?;
+        let pending = identity
+            .pending
+            .take()
+            .ok_or_else(|| eyre!("The key of {} did not change", username))?;
+        identity.public_key = pending;
+        identity.verified = false;
+        // learned again from the new key
+        identity.signing_key = None;
+        self.save()
+    }
+
+    pub fn set_verified(&mut self, username: &str) -> Result<()> {
+        let identity = self
+            .identities
+            .get_mut(username)
+            .ok_or_else(|| eyre!("{} is not known", username))?;
+        if identity.pending.is_some() {
+            return Err(eyre!("Accept the new key of {} before verifying it", username).into());
+        }
+        identity.verified = true;
+        self.save()
+    }
+
+    /// Records the signing key `username` published with `public_key`, if that is the trusted key
+    /// and no signing key is known yet. Returns false if a different signing key is already known.
+    pub fn learn_signing_key(
+        &mut self,
+        username: &str,
+        public_key: &str,
+        signing_key: &str,
+    ) -> Result<bool> {
+        let Some(identity) = self.identities.get_mut(username) else {
+            return Ok(true);
+        };
+        if identity.public_key != public_key || identity.pending.is_some() {
+            return Ok(true);
+        }
+        match &identity.signing_key {
+            Some(known) => Ok(known == signing_key),
+            None => {
+                identity.signing_key = Some(signing_key.to_owned());
+                self.save()?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub fn signing_key(&self, username: &str) -> Option<&str> {
+        self.identities.get(username)?.signing_key.as_deref()
+    }
+
+    /// All known users, with safety numbers computed against `own_key`.
+    pub fn views(&self, own_key: &str) -> Result<Vec<IdentityView>> {
+        self.identities
+            .iter()
+            .map(|(username, identity)| {
+                let current = identity.pending.as_ref().unwrap_or(&identity.public_key);
+                let digest = safety_digest(own_key, current)?;
+                Ok(IdentityView {
+                    username: username.clone(),
+                    fingerprint: key_fingerprint(&identity.public_key)?,
+                    verified: identity.verified,
+                    pending_fingerprint: identity
+                        .pending
+                        .as_deref()
+                        .map(key_fingerprint)
+                        .transpose()?,
+                    safety_number: safety_number(&digest),
+                    safety_words: words::to_words(&digest[..SAFETY_WORDS])
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect(),
+                })
+            })
+            .collect()
+    }
+
+    fn save(&self) -> Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| eyre!("Cannot create {}: {}", parent.display(), e))?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(&self.identities)?)
+            .map_err(|e| eyre!("Cannot write {}: {}", path.display(), e).into())
+    }
+}
+
+pub fn key_fingerprint(public_key: &str) -> Result<String> {
+    approval::fingerprint(&decode_public_key(public_key)?)
+}
+
+/// Hash over both keys, in a fixed order so both parties get the same result.
+fn safety_digest(a: &str, b: &str) -> Result<Vec<u8>> {
+    let (a, b) = (from_base64(a)?, from_base64(b)?);
+    let (first, second) = if a <= b { (a, b) } else { (b, a) };
+    Ok(generic::hash(&[first, second].concat(), None)?.to_vec())
+}
+
+fn safety_number(digest: &[u8]) -> String {
+    // five digits from every two bytes, like the numbers other messengers show
+    digest
+        .chunks(2)
+        .take(SAFETY_NUMBER_DIGITS / 5)
+        .map(|pair| format!("{:05}", u16::from_be_bytes([pair[0], pair[1]])))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::to_base64;
+    use alkali::asymmetric::cipher::Keypair;
+
+    #[test]
+    fn test_trust_on_first_use() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("identities.json");
+        let original = to_base64(&Keypair::generate()?.public_key);
+        let changed = to_base64(&Keypair::generate()?.public_key);
+
+        let mut store = TrustStore::load(path.clone())?;
+        assert_eq!(store.observe("alice", &original)?, TrustState::New);
+        assert_eq!(store.observe("alice", &original)?, TrustState::Known);
+        assert_eq!(store.observe("alice", &changed)?, TrustState::Changed);
+
+        // the change survives a restart and stays blocked until accepted
+        let mut store = TrustStore::load(path)?;
+        assert_eq!(store.observe("alice", &changed)?, TrustState::Unaccepted);
+        assert!(store.set_verified("alice").is_err());
+        store.accept("alice")?;
+        assert_eq!(store.observe("alice", &changed)?, TrustState::Known);
+        assert_eq!(store.observe("alice", &original)?, TrustState::Changed);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signing_key_is_pinned() -> Result<()> {
+        let original = to_base64(&Keypair::generate()?.public_key);
+        let changed = to_base64(&Keypair::generate()?.public_key);
+        let mut store = TrustStore::default();
+        store.observe("alice", &original)?;
+        assert!(store.learn_signing_key("alice", &original, "first")?);
+        assert!(!store.learn_signing_key("alice", &original, "second")?);
+        assert_eq!(store.signing_key("alice"), Some("first"));
+
+        // not taken from a key that isn't trusted
+        store.observe("alice", &changed)?;
+        store.learn_signing_key("alice", &changed, "second")?;
+        assert_eq!(store.signing_key("alice"), Some("first"));
+        store.accept("alice")?;
+        assert_eq!(store.signing_key("alice"), None);
+        assert!(store.learn_signing_key("alice", &changed, "second")?);
+        assert_eq!(store.signing_key("alice"), Some("second"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_safety_number_is_symmetric() -> Result<()> {
+        let alice = to_base64(&Keypair::generate()?.public_key);
+        let bob = to_base64(&Keypair::generate()?.public_key);
+        let number = safety_number(&safety_digest(&alice, &bob)?);
+        assert_eq!(number, safety_number(&safety_digest(&bob, &alice)?));
+        assert_eq!(number.split(' ').count(), SAFETY_NUMBER_DIGITS / 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+/// One word per byte value, used to read out keys and safety numbers.
+///
+/// The order is part of the format: changing it changes every rendered word list.
+pub const WORDS: [&str; 256] = [
+    "acid", "acorn", "actor", "adult", "agent", "alarm", "album", "alert", "alley", "amber",
+    "angle", "ankle", "apple", "apron", "arena", "armor", "arrow", "atlas", "attic", "award",
+    "bacon", "badge", "bagel", "baker", "bamboo", "banjo", "barn", "basil", "basket", "beach",
+    "beard", "beaver", "bell", "bench", "berry", "bison", "blade", "blanket", "blaze", "bloom",
+    "board", "bonus", "boot", "bottle", "bounty", "brain", "brass", "bread", "brick", "bridge",
+    "broom", "brush", "bucket", "buffalo", "bugle", "cabin", "cactus", "camel", "candle", "canoe",
+    "canyon", "carbon", "carpet", "castle", "cedar", "chalk", "cherry", "chess", "chief", "cider",
+    "cinema", "circus", "citrus", "clock", "cloud", "clover", "coast", "cobra", "cocoa", "comet",
+    "coral", "cotton", "cousin", "coyote", "crane", "crater", "crayon", "cricket", "crown",
+    "crystal", "cube", "dagger", "daisy", "dance", "delta", "denim", "desert", "diamond", "dinner",
+    "dolphin", "donkey", "dragon", "drum", "eagle", "easel", "echo", "eclipse", "elbow", "ember",
+    "engine", "falcon", "feather", "fern", "ferry", "fiddle", "flag", "flame", "flute", "forest",
+    "fossil", "fox", "galaxy", "garden", "garlic", "gem", "ginger", "giraffe", "glacier", "globe",
+    "goat", "gorilla", "grape", "gravel", "guitar", "hammer", "harbor", "harp", "hazel", "helmet",
+    "hermit", "honey", "horizon", "hotel", "igloo", "island", "ivory", "jacket", "jaguar", "jelly",
+    "jewel", "jungle", "kayak", "kettle", "kitten", "koala", "ladder", "lagoon", "lantern",
+    "laser", "lemon", "leopard", "lily", "lizard", "lobster", "lotus", "magnet", "mango", "maple",
+    "marble", "meadow", "melon", "meteor", "mirror", "mitten", "monkey", "moose", "mosaic",
+    "motor", "muffin", "nectar", "needle", "nickel", "noodle", "oasis", "ocean", "olive", "onion",
+    "opera", "orbit", "orchid", "otter", "owl", "oyster", "paddle", "palace", "panda", "parrot",
+    "peach", "pearl", "pebble", "pepper", "piano", "pigeon", "pillow", "pilot", "pine", "planet",
+    "plum", "pocket", "pony", "poppy", "potato", "prism", "puzzle", "quartz", "quill", "rabbit",
+    "radar", "radio", "raven", "reef", "ribbon", "river", "robot", "rocket", "rose", "ruby",
+    "saddle", "salmon", "sand", "satin", "scarf", "shadow", "shell", "silver", "sketch", "sled",
+    "slope", "socket", "spider", "spoon", "spruce", "squid", "stamp", "statue", "storm", "sugar",
+    "summit", "sunset", "swan", "tablet", "tango", "teapot", "tiger", "timber", "toast",
+];
+
+/// Renders `bytes` as words, one word per byte.
+pub fn to_words(bytes: &[u8]) -> Vec<&'static str> {
+    bytes.iter().map(|b| WORDS[*b as usize]).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_words_are_unique() {
+        assert_eq!(WORDS.iter().collect::<HashSet<_>>().len(), WORDS.len());
+        assert_eq!(to_words(&[0, 255]), ["acid", "toast"]);
+    }
+}
@@ -0,0 +1,417 @@
+/// Error correction codewords per block and the data codewords of each block, for versions 1 to
+/// 9 at level M. Enough for short codes and small enough to check against the standard.
+const BLOCKS: [(usize, &[usize]); 9] = [
+    (10, &[16]),
+    (16, &[28]),
+    (26, &[44]),
+    (18, &[32, 32]),
+    (24, &[43, 43]),
+    (16, &[27, 27, 27, 27]),
+    (18, &[31, 31, 31, 31]),
+    (22, &[38, 38, 39, 39]),
+    (22, &[36, 36, 36, 37, 37]),
+];
+
+/// Centers of the alignment patterns, by version.
+const ALIGNMENT: [&[usize]; 9] = [
+    &[],
+    &[6, 18],
+    &[6, 22],
+    &[6, 26],
+    &[6, 30],
+    &[6, 34],
+    &[6, 22, 38],
+    &[6, 24, 42],
+    &[6, 26, 46],
+];
+
+/// Format bits of error correction level M.
+const LEVEL_M: u32 = 0b00;
+
+/// Light modules around the code, scanners need them to find it.
+const QUIET_ZONE: usize = 4;
+
+/// A QR code as a square of dark (`true`) and light modules, without the quiet zone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes `data` in byte mode at level M, in the smallest version it fits.
+    ///
+    /// Returns `None` if it is too long for version 9, about 180 bytes.
+    pub fn encode(data: &[u8]) -> Option<Self> {
+        let version = (1..=BLOCKS.len()).find(|v| data.len() + 2 <= data_capacity(*v))?;
+        let codewords = codewords(version, data);
+
+        let (base, function) = Self::function_patterns(version);
+        (0..8)
+            .map(|mask| {
+                let mut code = base.clone();
+                code.draw_codewords(&codewords, &function);
+                code.apply_mask(mask, &function);
+                code.draw_format(mask);
+                code
+            })
+            .min_by_key(Self::penalty)
+    }
+
+    /// Outside the code is light, like the quiet zone around it.
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.size && y < self.size && self.modules[y * self.size + x]
+    }
+
+    /// Renders two rows of modules per line with half blocks, surrounded by the quiet zone.
+    ///
+    /// Blocks are drawn for light modules, so the code reads right on a dark terminal.
+    pub fn to_half_blocks(&self) -> Vec<String> {
+        let width = self.size + 2 * QUIET_ZONE;
+        let light = |x: usize, y: usize| {
+            y >= width
+                || x < QUIET_ZONE
+                || y < QUIET_ZONE
+                || !self.get(x - QUIET_ZONE, y - QUIET_ZONE)
+        };
+        (0..width)
+            .step_by(2)
+            .map(|y| {
+                (0..width)
+                    .map(|x| match (light(x, y), light(x, y + 1)) {
+                        (true, true) => '█',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (false, false) => ' ',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+    }
+
+    /// Finder, timing and alignment patterns and the version information, with the format areas
+    /// reserved. Also returns which modules these take, the data goes around them.
+    fn function_patterns(version: usize) -> (Self, Vec<bool>) {
+        let size = version * 4 + 17;
+        let mut code = Self {
+            size,
+            modules: vec![false; size * size],
+        };
+        let mut function = vec![false; size * size];
+        let mut reserve = |x: usize, y: usize, dark: bool| {
+            code.set(x, y, dark);
+            function[y * size + x] = true;
+        };
+
+        for i in 0..size {
+            reserve(6, i, i % 2 == 0);
+            reserve(i, 6, i % 2 == 0);
+        }
+        for (cx, cy) in [(3, 3), (size - 4, 3), (3, size - 4)] {
+            for dy in -4i32..=4 {
+                for dx in -4i32..=4 {
+                    let (x, y) = (cx as i32 + dx, cy as i32 + dy);
+                    if (0..size as i32).contains(&x) && (0..size as i32).contains(&y) {
+                        let distance = dx.abs().max(dy.abs());
+                        reserve(x as usize, y as usize, distance != 2 && distance != 4);
+                    }
+                }
+            }
+        }
+        let centers = ALIGNMENT[version - 1];
+        let last = centers.len().saturating_sub(1);
+        for (i, cx) in centers.iter().enumerate() {
+            for (j, cy) in centers.iter().enumerate() {
+                // these would overlap the finder patterns
+                if (i == 0 && (j == 0 || j == last)) || (i == last && j == 0) {
+                    continue;
+                }
+                for dy in -2i32..=2 {
+                    for dx in -2i32..=2 {
+                        let (x, y) = ((*cx as i32 + dx) as usize, (*cy as i32 + dy) as usize);
+                        reserve(x, y, dx.abs().max(dy.abs()) != 1);
+                    }
+                }
+            }
+        }
+        // the format is drawn once the mask is chosen
+        for i in (0..9).filter(|i| *i != 6) {
+            reserve(8, i, false);
+            reserve(i, 8, false);
+        }
+        for i in 0..8 {
+            reserve(size - 1 - i, 8, false);
+            reserve(8, size - 1 - i, false);
+        }
+        if version >= 7 {
+            let bits = version_bits(version);
+            for i in 0..18 {
+                let dark = (bits >> i) & 1 == 1;
+                let (a, b) = (size - 11 + i % 3, i / 3);
+                reserve(a, b, dark);
+                reserve(b, a, dark);
+            }
+        }
+        (code, function)
+    }
+
+    /// Places the codewords in the zigzag order of the standard, leftover modules stay light.
+    fn draw_codewords(&mut self, codewords: &[u8], function: &[bool]) {
+        let size = self.size;
+        let mut bits = codewords
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1));
+        let mut right = size - 1;
+        loop {
+            // the vertical timing pattern takes a whole column
+            if right == 6 {
+                right = 5;
+            }
+            let upward = (right + 1) & 2 == 0;
+            for vertical in 0..size {
+                let y = if upward {
+                    size - 1 - vertical
+                } else {
+                    vertical
+                };
+                for x in [right, right - 1] {
+                    if !function[y * size + x] {
+                        let dark = bits.next().unwrap_or(false);
+                        self.set(x, y, dark);
+                    }
+                }
+            }
+            if right < 3 {
+                break;
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u32, function: &[bool]) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                if invert && !function[y * self.size + x] {
+                    let index = y * self.size + x;
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    fn draw_format(&mut self, mask: u32) {
+        let size = self.size;
+        let bits = format_bits(mask);
+        let bit = |i: usize| (bits >> i) & 1 == 1;
+        for i in 0..6 {
+            self.set(8, i, bit(i));
+        }
+        self.set(8, 7, bit(6));
+        self.set(8, 8, bit(7));
+        self.set(7, 8, bit(8));
+        for i in 9..15 {
+            self.set(14 - i, 8, bit(i));
+        }
+        for i in 0..8 {
+            self.set(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set(8, size - 15 + i, bit(i));
+        }
+        // always dark
+        self.set(8, size - 8, true);
+    }
+
+    /// How hard the code is to scan, the mask with the lowest penalty is used.
+    ///
+    /// Scores long runs, 2x2 blocks and an uneven balance of dark and light. Patterns that look
+    /// like finders aren't scored, the other rules already rule out the worst masks.
+    fn penalty(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+        for line in 0..size {
+            for module in [
+                |code: &Self, line, i| code.get(i, line),
+                |code: &Self, line, i| code.get(line, i),
+            ] {
+                let mut run = 1;
+                for i in 1..size {
+                    if module(self, line, i) == module(self, line, i - 1) {
+                        run += 1;
+                        continue;
+                    }
+                    if run >= 5 {
+                        penalty += run - 2;
+                    }
+                    run = 1;
+                }
+                if run >= 5 {
+                    penalty += run - 2;
+                }
+            }
+        }
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if self.get(x + 1, y) == dark
+                    && self.get(x, y + 1) == dark
+                    && self.get(x + 1, y + 1) == dark
+                {
+                    penalty += 3;
+                }
+            }
+        }
+        let dark = self.modules.iter().filter(|dark| **dark).count();
+        let percent = dark * 100 / self.modules.len();
+        penalty + (percent.abs_diff(50) / 5 * 10) as u32
+    }
+}
+
+fn data_capacity(version: usize) -> usize {
+    BLOCKS[version - 1].1.iter().sum()
+}
+
+/// Data in byte mode, padded to the capacity of `version`, with its error correction codewords
+/// interleaved block by block.
+fn codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity = data_capacity(version);
+    // mode 0100 and an 8 bit length, so every data byte is shifted by 4 bits
+    let mut bytes = Vec::with_capacity(capacity);
+    let mut carry = 0b0100u8;
+    for byte in std::iter::once(data.len() as u8).chain(data.iter().copied()) {
+        bytes.push((carry << 4) | (byte >> 4));
+        carry = byte & 0x0f;
+    }
+    // the last nibble and the terminator
+    bytes.push(carry << 4);
+    bytes.extend([0xec, 0x11].iter().cycle().take(capacity - bytes.len()));
+
+    let (ec_length, lengths) = BLOCKS[version - 1];
+    let divisor = rs_divisor(ec_length);
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for length in lengths {
+        let block = &bytes[start..start + length];
+        blocks.push((block, rs_remainder(block, &divisor)));
+        start += length;
+    }
+    let longest = lengths.iter().max().copied().unwrap_or_default();
+    let mut interleaved = Vec::new();
+    for i in 0..longest {
+        interleaved.extend(blocks.iter().filter_map(|(block, _)| block.get(i)));
+    }
+    for i in 0..ec_length {
+        interleaved.extend(blocks.iter().map(|(_, ec)| ec[i]));
+    }
+    interleaved
+}
+
+/// Multiplication in GF(256) with the QR polynomial.
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u16 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11d);
+        z ^= ((y as u16 >> i) & 1) * x as u16;
+    }
+    z as u8
+}
+
+/// Reed-Solomon generator polynomial of `degree`, without its leading coefficient.
+fn rs_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn rs_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (x, y) in result.iter_mut().zip(divisor) {
+            *x ^= gf_multiply(*y, factor);
+        }
+    }
+    result
+}
+
+/// Level and mask with their BCH error correction, masked as the standard requires.
+fn format_bits(mask: u32) -> u32 {
+    let data = (LEVEL_M << 3) | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+fn version_bits(version: usize) -> u32 {
+    let version = version as u32;
+    let mut remainder = version;
+    for _ in 0..12 {
+        remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1f25);
+    }
+    (version << 12) | remainder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_correction() {
+        // the version 1-M example of the standard's annex
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            rs_remainder(&data, &rs_divisor(10)),
+            [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+        assert_eq!(format_bits(0), 0b101010000010010);
+        assert_eq!(format_bits(7), 0b100101010100000);
+        assert_eq!(version_bits(7), 0x07c94);
+        assert_eq!(version_bits(9), 0x09a99);
+    }
+
+    #[test]
+    fn test_encode() {
+        let short = QrCode::encode(b"hello").expect("fits");
+        assert_eq!(short.size, 21);
+        // finder pattern corners and the dark module
+        assert!(short.get(0, 0) && short.get(6, 6) && !short.get(7, 7));
+        assert!(short.get(20, 0) && short.get(0, 20) && short.get(8, 13));
+
+        let long = QrCode::encode(&[b'x'; 170]).expect("fits");
+        assert_eq!(long.size, 9 * 4 + 17);
+        assert!(QrCode::encode(&[b'x'; 200]).is_none());
+
+        let lines = short.to_half_blocks();
+        assert_eq!(lines.len(), (21 + 2 * QUIET_ZONE).div_ceil(2));
+        assert!(lines[0].chars().all(|c| c == '█'));
+    }
+}
@@ -0,0 +1,196 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use zeroize::Zeroize;
+
+const REDACTED: &str = "***";
+
+/// A password, passphrase, token or encoded key.
+///
+/// `Debug` and `Serialize` only ever show `***`, so a secret inside an `Action` or any other
+/// logged value doesn't end up in the log files. The memory is zeroed on drop.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: String) -> Self {
+        Self(secret)
+    }
+
+    /// The actual secret, never pass it to anything that logs.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Self(secret)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self)
+    }
+}
+
+/// For `#[serde(serialize_with)]` on fields that are written to an encrypted file, where the
+/// secret itself has to be serialized.
+pub fn serialize_exposed<S: Serializer>(secret: &Secret, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(secret.expose())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action::Action;
+    use crate::network::{self, CONFIGURATION};
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::{fmt, prelude::*};
+
+    const PASSWORD: &str = "hunter2-do-not-log";
+
+    /// Collects everything the log layers write.
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0
+                .lock()
+                .map_err(|_| std::io::Error::other("poisoned"))?
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Captured {
+        /// The same layers `logging::init` sets up, writing here.
+        fn subscriber(&self) -> impl tracing::Subscriber + Send + Sync + use<> {
+            let (json, ansi) = (self.clone(), self.clone());
+            tracing_subscriber::registry()
+                .with(fmt::layer().json().with_writer(move || json.clone()))
+                .with(
+                    fmt::layer()
+                        .with_ansi(true)
+                        .with_writer(move || ansi.clone()),
+                )
+        }
+
+        fn logs(&self) -> String {
+            String::from_utf8_lossy(&self.0.lock().expect("not poisoned")).to_string()
+        }
+    }
+
+    #[test]
+    fn test_secrets_stay_out_of_logs() {
+        let captured = Captured::default();
+        tracing::subscriber::with_default(captured.subscriber(), || {
+            let secret = || Secret::new(PASSWORD.to_owned());
+            let actions = [
+                Action::PerformLogin("alice".to_owned(), secret()),
+                Action::UnlockKeystore(secret()),
+                Action::JoinWithPassphrase("static:team".to_owned(), secret()),
+            ];
+            for action in actions {
+                tracing::debug!("{action:?}");
+                tracing::debug!(?action, "{:#?}", action);
+                tracing::debug!("{}", serde_json::to_string(&action).unwrap_or_default());
+            }
+        });
+
+        let logs = captured.logs();
+        assert!(logs.contains(REDACTED));
+        assert!(!logs.contains(PASSWORD), "{logs}");
+    }
+
+    /// Answers every request with a 401, so a login goes on to register and fails.
+    async fn refuse(listener: TcpListener) -> std::io::Result<()> {
+        const BODY: &str = r#"{"detail":"nope"}"#;
+        loop {
+            let (mut socket, _) = listener.accept().await?;
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            // the body is the JSON login data
+            while !request.ends_with(b"}") {
+                let read = socket.read(&mut buf).await?;
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            let response = format!(
+                "HTTP/1.1 401 Unauthorized\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{BODY}",
+                BODY.len()
+            );
+            socket.write_all(response.as_bytes()).await?;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_login_stays_out_of_logs() -> color_eyre::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        CONFIGURATION.write().await.base_path = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(refuse(listener));
+
+        let captured = Captured::default();
+        let guard = tracing::subscriber::set_default(captured.subscriber());
+        let result = network::login("alice", &Secret::new(PASSWORD.to_owned())).await;
+        drop(guard);
+        assert!(result.is_err());
+
+        let logs = captured.logs();
+        assert!(logs.contains("registering alice"), "{logs}");
+        assert!(!logs.contains(PASSWORD), "{logs}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_serialize_exposed() -> Result<(), serde_json::Error> {
+        #[derive(Serialize, Deserialize)]
+        struct Stored {
+            #[serde(serialize_with = "serialize_exposed")]
+            key: Secret,
+        }
+        let stored = Stored {
+            key: Secret::new(PASSWORD.to_owned()),
+        };
+        let json = serde_json::to_string(&stored)?;
+        assert!(json.contains(PASSWORD));
+        assert_eq!(serde_json::from_str::<Stored>(&json)?.key, stored.key);
+        assert_eq!(
+            serde_json::to_string(&stored.key)?,
+            format!("\"{REDACTED}\"")
+        );
+        Ok(())
+    }
+}